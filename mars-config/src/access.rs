@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Method;
+
+/// `AccessRule` matches requests to a subproject, optionally narrowed down by path prefix and methods.
+///
+/// `subproject` can be `*` to match every subproject of the project. `path_prefix` matches whole
+/// segments of the path, after it is percent-decoded and `.`, `..` and empty segments are
+/// resolved, the way upstreams see it. `get` matches `get` and `get/1`, not `getAdmin`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccessRule {
+    pub subproject: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<Method>,
+}
+
+impl AccessRule {
+    pub fn matches(&self, subproject: &str, path: &str, method: &str) -> bool {
+        (self.subproject == "*" || self.subproject == subproject)
+            && self
+                .path_prefix
+                .as_ref()
+                .map(|prefix| segments(path).starts_with(&segments(prefix)))
+                .unwrap_or(true)
+            && (self.methods.is_empty() || self.methods.iter().any(|x| x.matches(method)))
+    }
+}
+
+/// segments of `path` without its query, percent-decoded and normalized
+fn segments(path: &str) -> Vec<String> {
+    let path = path.split('?').next().unwrap_or_default();
+    let mut segments: Vec<String> = vec![];
+    for segment in percent_decode(path).split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment.to_string()),
+        }
+    }
+    segments
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `AccessPolicy` restricts a grant to a part of the project.
+///
+/// A request is denied when any `deny` rule matches. Otherwise it is allowed when `allow` is empty
+/// or any `allow` rule matches. The default policy allows the whole project.
+///
+/// ```json
+/// {
+///     "allow": [{"subproject": "json"}, {"subproject": "xml", "path_prefix": "get", "methods": ["GET"]}],
+///     "deny": [{"subproject": "json", "path_prefix": "delete"}]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<AccessRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<AccessRule>,
+}
+
+impl AccessPolicy {
+    pub fn permits(&self, subproject: &str, path: &str, method: &str) -> bool {
+        if self
+            .deny
+            .iter()
+            .any(|rule| rule.matches(subproject, path, method))
+        {
+            return false;
+        }
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|rule| rule.matches(subproject, path, method))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::AccessPolicy;
+
+    #[test]
+    fn test_policy() {
+        let policy: AccessPolicy = serde_json::from_value(json!({
+            "allow": [
+                {"subproject": "json"},
+                {"subproject": "xml", "path_prefix": "/get", "methods": ["GET"]}
+            ],
+            "deny": [{"subproject": "json", "path_prefix": "delete"}]
+        }))
+        .unwrap();
+        assert!(policy.permits("json", "anything", "POST"));
+        assert!(!policy.permits("json", "delete/1", "GET"));
+        assert!(policy.permits("xml", "get?x=1", "GET"));
+        assert!(!policy.permits("xml", "get", "POST"));
+        assert!(!policy.permits("xml", "post", "GET"));
+        assert!(!policy.permits("yaml", "", "GET"));
+        // prefixes match whole segments
+        assert!(policy.permits("xml", "get/1", "GET"));
+        assert!(!policy.permits("xml", "getAdmin/1", "GET"));
+        assert!(policy.permits("json", "deleted", "GET"));
+        // deny can't be got around with paths upstream normalizes
+        for path in [
+            "%64elete/1",
+            "./delete",
+            "//delete",
+            "x/../delete/1",
+            "x%2F..%2Fdelete",
+            "..\\delete",
+            "delete?x=1",
+        ] {
+            assert!(!policy.permits("json", path, "GET"), "{path}");
+        }
+        assert!(!policy.permits("xml", "get/../admin", "GET"));
+        assert!(AccessPolicy::default().permits("yaml", "", "GET"));
+    }
+}
@@ -22,7 +22,6 @@ pub const TRANSFORM_JSON_TO_JSON_JOLT_REQUEST: &str = "jolt_request_transform";
         }
     }]
 */
-
 pub const TRANSFORM_JSON_TO_JSON_JOLT_RESPONSE: &str = "jolt_response_transform";
 
 /// transform request payload from json to yaml
@@ -65,7 +64,6 @@ pub const TRANSFORM_YAML_JSON_REQUEST: &str = "request_yaml_to_json";
 /*
     "response_yaml_to_json" : true
 */
-
 pub const TRANSFORM_YAML_TO_JSON_RESPONSE: &str = "response_yaml_to_json";
 
 /// request header used for auth token
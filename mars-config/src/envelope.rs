@@ -0,0 +1,263 @@
+//! Envelope encrypted auth params, `{"$enc": "v1.<key id>.<data key>.<ciphertext>"}`.
+//!
+//! Every value is encrypted with a data key of its own, which is kept next to it, encrypted with
+//! the master key. Rotating master key only encrypts data keys again, see [`MasterKey::rekey`].
+//! Master key is 32 bytes, base64 encoded, given in `MARS_MASTER_KEY` or in a file named by
+//! `MARS_MASTER_KEY_FILE`. Values are decrypted by [`ServiceConfig::resolve_secrets`] when a
+//! service is built, config keeps them encrypted.
+use std::sync::{Arc, OnceLock, RwLock};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::MarsError;
+
+/// key of objects holding an encrypted value
+pub const ENCRYPTED: &str = "$enc";
+
+const VERSION: &str = "v1";
+
+const NONCE_LEN: usize = 12;
+
+fn error(message: impl Into<String>) -> MarsError {
+    MarsError::ServiceConfigError(message.into())
+}
+
+/// `MasterKey` encrypts data keys of encrypted values.
+pub struct MasterKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl MasterKey {
+    /// key from base64 encoded 32 bytes
+    pub fn parse(encoded: &str) -> Result<Self, MarsError> {
+        let key =
+            base64::decode(encoded.trim()).map_err(|_| error("master key is not valid base64"))?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| error("master key is not 32 bytes long"))?;
+        let id = hex(&Sha256::digest(&key)[..4]);
+        Ok(MasterKey { id, cipher })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, MarsError> {
+        let encoded = std::fs::read_to_string(path)
+            .map_err(|err| error(format!("unable to read master key `{path}`, {err}")))?;
+        MasterKey::parse(&encoded)
+    }
+
+    /// key of `MARS_MASTER_KEY`, or of file `MARS_MASTER_KEY_FILE`. `None` when neither is set
+    pub fn from_env() -> Result<Option<Self>, MarsError> {
+        if let Ok(encoded) = std::env::var("MARS_MASTER_KEY") {
+            return MasterKey::parse(&encoded).map(Some);
+        }
+        match std::env::var("MARS_MASTER_KEY_FILE") {
+            Ok(path) => MasterKey::from_file(&path).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// new random key, base64 encoded
+    pub fn generate() -> String {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        base64::encode(key)
+    }
+
+    /// identifies key, without revealing it
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// `{"$enc": ..}` of `plaintext`
+    pub fn encrypt(&self, plaintext: &str) -> Value {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let data_cipher = Aes256Gcm::new(&data_key);
+        json!({
+            ENCRYPTED: format!(
+                "{VERSION}.{}.{}.{}",
+                self.id,
+                seal(&self.cipher, &data_key),
+                seal(&data_cipher, plaintext.as_bytes())
+            )
+        })
+    }
+
+    /// plaintext of what an encrypted value, `{"$enc": ..}`, holds
+    pub fn decrypt(&self, encrypted: &str) -> Result<String, MarsError> {
+        let (data_key, ciphertext) = self.data_key(encrypted)?;
+        let plaintext = open(&data_key, ciphertext)?;
+        String::from_utf8(plaintext).map_err(|_| error("encrypted value is not utf-8"))
+    }
+
+    /// encrypted value with its data key encrypted by `new` instead, ciphertext stays as it is
+    pub fn rekey(&self, encrypted: &str, new: &MasterKey) -> Result<Value, MarsError> {
+        let (data_key, ciphertext) = split(encrypted, &self.id)?;
+        let data_key = open(&self.cipher, data_key)?;
+        Ok(json!({
+            ENCRYPTED: format!(
+                "{VERSION}.{}.{}.{ciphertext}",
+                new.id,
+                seal(&new.cipher, &data_key)
+            )
+        }))
+    }
+
+    fn data_key<'a>(&self, encrypted: &'a str) -> Result<(Aes256Gcm, &'a str), MarsError> {
+        let (data_key, ciphertext) = split(encrypted, &self.id)?;
+        let data_key = open(&self.cipher, data_key)?;
+        let data_cipher =
+            Aes256Gcm::new_from_slice(&data_key).map_err(|_| error("data key is not valid"))?;
+        Ok((data_cipher, ciphertext))
+    }
+}
+
+/// `(data key, ciphertext)` of an encrypted value, checked to be encrypted by key `id`
+fn split<'a>(encrypted: &'a str, id: &str) -> Result<(&'a str, &'a str), MarsError> {
+    match encrypted.trim().split('.').collect::<Vec<_>>()[..] {
+        [VERSION, key_id, data_key, ciphertext] if key_id == id => Ok((data_key, ciphertext)),
+        [VERSION, key_id, _, _] => Err(error(format!(
+            "value is encrypted with master key `{key_id}`, not `{id}`"
+        ))),
+        _ => Err(error("encrypted value is not valid")),
+    }
+}
+
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8]) -> String {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("aes-gcm encrypts any input that fits in memory");
+    base64::encode_config(
+        [nonce.as_slice(), &ciphertext].concat(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &str) -> Result<Vec<u8>, MarsError> {
+    let sealed = base64::decode_config(sealed, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| error("encrypted value is not valid"))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(error("encrypted value is not valid"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| error("unable to decrypt value, master key doesn't match"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// what `{"$enc": ..}` holds, `None` for any other value
+pub fn encrypted(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(object) if object.len() == 1 => object.get(ENCRYPTED)?.as_str(),
+        _ => None,
+    }
+}
+
+/// `value` with every encrypted value within it replaced by `replace` of what it holds. returns
+/// number of values replaced
+pub fn map_encrypted(
+    value: &Value,
+    replace: &mut impl FnMut(&str) -> Result<Value, MarsError>,
+) -> Result<(Value, usize), MarsError> {
+    if let Some(encrypted) = encrypted(value) {
+        return Ok((replace(encrypted)?, 1));
+    }
+    let mut count = 0;
+    let value = match value {
+        Value::Array(array) => Value::Array(
+            array
+                .iter()
+                .map(|value| {
+                    let (value, replaced) = map_encrypted(value, replace)?;
+                    count += replaced;
+                    Ok(value)
+                })
+                .collect::<Result<_, MarsError>>()?,
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| {
+                    let (value, replaced) = map_encrypted(value, replace)?;
+                    count += replaced;
+                    Ok((key.clone(), value))
+                })
+                .collect::<Result<_, MarsError>>()?,
+        ),
+        value => value.clone(),
+    };
+    Ok((value, count))
+}
+
+fn master_key() -> &'static RwLock<Option<Arc<MasterKey>>> {
+    static MASTER_KEY: OnceLock<RwLock<Option<Arc<MasterKey>>>> = OnceLock::new();
+    MASTER_KEY.get_or_init(|| RwLock::new(None))
+}
+
+/// makes encrypted auth params decrypt with `key`
+pub fn register_master_key(key: MasterKey) {
+    *master_key()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(key));
+}
+
+/// `value` with encrypted values within it decrypted by registered master key
+pub fn decrypt_value(value: &Value) -> Result<Value, MarsError> {
+    let key = master_key()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    let (value, _) = map_encrypted(value, &mut |encrypted| {
+        match &key {
+        Some(key) => key.decrypt(encrypted).map(Value::String),
+        None => Err(error(
+            "auth params are encrypted, but neither MARS_MASTER_KEY nor MARS_MASTER_KEY_FILE is set",
+        )),
+    }
+    })?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{map_encrypted, MasterKey};
+
+    #[test]
+    fn test_envelope() {
+        let key = MasterKey::parse(&MasterKey::generate()).unwrap();
+        let encrypted = key.encrypt("hunter2");
+        let sealed = encrypted["$enc"].as_str().unwrap();
+        assert!(sealed.starts_with(&format!("v1.{}.", key.id())));
+        assert!(!sealed.contains("hunter2"));
+        assert_eq!(key.decrypt(sealed).unwrap(), "hunter2");
+
+        // rekeyed value opens with new key only, ciphertext stays
+        let new = MasterKey::parse(&MasterKey::generate()).unwrap();
+        let rekeyed = key.rekey(sealed, &new).unwrap();
+        let resealed = rekeyed["$enc"].as_str().unwrap();
+        assert_eq!(sealed.rsplit('.').next(), resealed.rsplit('.').next());
+        assert_eq!(new.decrypt(resealed).unwrap(), "hunter2");
+        assert!(key.decrypt(resealed).is_err());
+
+        let params = json!({"username": "u", "password": encrypted, "nested": [{"token": key.encrypt("t")}]});
+        let (decrypted, count) =
+            map_encrypted(&params, &mut |x| key.decrypt(x).map(Into::into)).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            decrypted,
+            json!({"username": "u", "password": "hunter2", "nested": [{"token": "t"}]})
+        );
+        assert!(MasterKey::parse("c2hvcnQ=").is_err());
+    }
+}
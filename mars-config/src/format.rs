@@ -0,0 +1,150 @@
+//! Formats projects and tokens files can be written in, json5, yaml or toml.
+//!
+//! Files mean the same whichever format they are written in, they are parsed into the same
+//! values. Format is picked by extension, `.yaml`/`.yml` and `.toml`, anything else is json5.
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::MarsError;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConfigFormat {
+    /// json5, which plain json is as well
+    #[default]
+    Json5,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// format of file at `path`, a path or url, by its extension
+    pub fn from_path(path: &str) -> Self {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        match path.rsplit_once('.').map(|(_, extension)| extension) {
+            Some(extension) => extension.parse().unwrap_or_default(),
+            None => ConfigFormat::Json5,
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T, MarsError> {
+        let error = |err: &dyn Display| {
+            MarsError::ServiceConfigError(format!("unable to parse {self}, {err}"))
+        };
+        match self {
+            ConfigFormat::Json5 => json5::from_str(text).map_err(|err| error(&err)),
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|err| error(&err)),
+            ConfigFormat::Toml => toml::from_str(text).map_err(|err| error(&err)),
+        }
+    }
+
+    /// `value` written in this format. toml has no null, fields that are `null` can't be written
+    pub fn to_string<T: Serialize>(&self, value: &T) -> Result<String, MarsError> {
+        let error = |err: &dyn Display| {
+            MarsError::ServiceConfigError(format!("unable to write {self}, {err}"))
+        };
+        match self {
+            ConfigFormat::Json5 => serde_json::to_string_pretty(value).map_err(|err| error(&err)),
+            ConfigFormat::Yaml => serde_yaml::to_string(value).map_err(|err| error(&err)),
+            ConfigFormat::Toml => toml::to_string_pretty(value).map_err(|err| error(&err)),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = MarsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "json5" | "json" => Ok(ConfigFormat::Json5),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            _ => Err(MarsError::ServiceConfigError(format!(
+                "format should be `json5`, `yaml` or `toml`, not `{value}`"
+            ))),
+        }
+    }
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFormat::Json5 => write!(f, "json5"),
+            ConfigFormat::Yaml => write!(f, "yaml"),
+            ConfigFormat::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use super::ConfigFormat;
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            ConfigFormat::from_path("config/config.json5"),
+            ConfigFormat::Json5
+        );
+        assert_eq!(ConfigFormat::from_path("config.yml"), ConfigFormat::Yaml);
+        assert_eq!(
+            ConfigFormat::from_path("https://example.com/config.toml?version=2"),
+            ConfigFormat::Toml
+        );
+        assert_eq!(ConfigFormat::from_path("config"), ConfigFormat::Json5);
+
+        let config = json!({
+            "aviko": {
+                "needs_auth": true,
+                "subprojects": {
+                    "json": {
+                        "url": "https://httpbin.org/json",
+                        "method": "ANY",
+                        "headers": [{"key": "x-team", "value": "platform", "action": "Add"}],
+                        "params": {"timeout": 10}
+                    }
+                }
+            }
+        });
+        let json5 = r#"{aviko: {needs_auth: true, subprojects: {json: {
+            url: "https://httpbin.org/json", method: "ANY",
+            headers: [{key: "x-team", value: "platform", action: "Add"}],
+            params: {timeout: 10},
+        }}}}"#;
+        let yaml = r#"
+aviko:
+  needs_auth: true
+  subprojects:
+    json:
+      url: https://httpbin.org/json
+      method: ANY
+      headers:
+        - {key: x-team, value: platform, action: Add}
+      params:
+        timeout: 10
+"#;
+        let toml = r#"
+[aviko]
+needs_auth = true
+
+[aviko.subprojects.json]
+url = "https://httpbin.org/json"
+method = "ANY"
+headers = [{key = "x-team", value = "platform", action = "Add"}]
+params = {timeout = 10}
+"#;
+        for (format, text) in [
+            (ConfigFormat::Json5, json5),
+            (ConfigFormat::Yaml, yaml),
+            (ConfigFormat::Toml, toml),
+        ] {
+            assert_eq!(format.parse::<Value>(text).unwrap(), config, "{format}");
+            let written = format.to_string(&config).unwrap();
+            assert_eq!(format.parse::<Value>(&written).unwrap(), config, "{format}");
+        }
+        assert!("xml".parse::<ConfigFormat>().is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Project `defaults` and named `auth_profiles` that subprojects inherit.
+//!
+//! ```json
+//! "defaults": {
+//!     "url": "https://httpbin.org/",
+//!     "method": "ANY",
+//!     "headers": [{"key": "x-team", "value": "platform", "action": "Add"}],
+//!     "auth_profile": "httpbin"
+//! },
+//! "auth_profiles": {"httpbin": {"auth_type": "basic_auth", "params": {..}}},
+//! "subprojects": {
+//!     "json": {"url": "json"},
+//!     "anything": {"url": "anything", "headers": [{"key": "x-team", "value": "", "action": "Discard"}]}
+//! }
+//! ```
+//!
+//! A relative `url` is joined to that of defaults, a missing one is taken as it is. Headers and
+//! query params are inherited unless the subproject lists one with the same key, whichever its
+//! action is, so `Discard` cancels an inherited header. Params are merged key by key. `auth`, or
+//! `auth_profile` naming one of `auth_profiles`, replaces inherited auth as a whole.
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{GeneralParams, Header, MarsAuth, MarsError, Method, ServiceConfig, UrlParam};
+
+/// named auth configs of a project, subprojects and defaults refer to them by `auth_profile`
+pub type AuthProfiles = BTreeMap<String, MarsAuth>;
+
+fn error(message: String) -> MarsError {
+    MarsError::ServiceConfigError(message)
+}
+
+/// `PartialServiceConfig` is a [`ServiceConfig`] that can leave fields out, as project `defaults`
+/// are written and subprojects inheriting them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct PartialServiceConfig {
+    /// url, or path relative to url of defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<Method>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query_params: Vec<UrlParam>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<MarsAuth>,
+    /// name of auth profile of project, instead of `auth`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<GeneralParams>,
+}
+
+impl PartialServiceConfig {
+    /// config with what is left out taken from `defaults`, and auth profile looked up
+    pub fn inherit(
+        self,
+        defaults: &PartialServiceConfig,
+        profiles: &AuthProfiles,
+    ) -> Result<ServiceConfig, MarsError> {
+        let auth = match self.auth(profiles)? {
+            Some(auth) => auth,
+            None => defaults.auth(profiles)?.unwrap_or_default(),
+        };
+        let url = match (self.url, &defaults.url) {
+            (Some(url), _) if url.contains("://") => url,
+            (Some(url), Some(base)) if !url.is_empty() => format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                url.trim_start_matches('/')
+            ),
+            (Some(url), None) if !url.is_empty() => {
+                return Err(error(format!(
+                    "url `{url}` is relative, but defaults have no url"
+                )))
+            }
+            (_, Some(base)) => base.clone(),
+            (_, None) => return Err(error("url is not configured".to_string())),
+        };
+        let method = self
+            .method
+            .or(defaults.method)
+            .ok_or_else(|| error("method is not configured".to_string()))?;
+        let params = match (self.params, &defaults.params) {
+            (Some(params), Some(defaults)) => merge_params(defaults, params),
+            (Some(params), None) => params,
+            (None, Some(defaults)) => defaults.clone(),
+            (None, None) => GeneralParams::default(),
+        };
+        Ok(ServiceConfig {
+            url,
+            method,
+            query_params: inherit_list(&defaults.query_params, self.query_params, |x| {
+                x.key.clone()
+            }),
+            headers: inherit_list(&defaults.headers, self.headers, |x| {
+                x.key.to_ascii_lowercase()
+            }),
+            auth,
+            params,
+        })
+    }
+
+    /// own auth, `None` when it is to be inherited
+    fn auth(&self, profiles: &AuthProfiles) -> Result<Option<MarsAuth>, MarsError> {
+        match (&self.auth, &self.auth_profile) {
+            (Some(_), Some(profile)) => Err(error(format!(
+                "both auth and auth_profile `{profile}` are configured"
+            ))),
+            (Some(auth), None) => Ok(Some(auth.clone())),
+            (None, Some(profile)) => profiles
+                .get(profile)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| error(format!("auth profile `{profile}` is not configured"))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl From<ServiceConfig> for PartialServiceConfig {
+    fn from(config: ServiceConfig) -> Self {
+        PartialServiceConfig {
+            url: Some(config.url),
+            method: Some(config.method),
+            query_params: config.query_params,
+            headers: config.headers,
+            auth: Some(config.auth),
+            auth_profile: None,
+            params: Some(config.params),
+        }
+    }
+}
+
+/// inherited entries whose key isn't in `own`, followed by `own`
+fn inherit_list<T: Clone>(inherited: &[T], own: Vec<T>, key: impl Fn(&T) -> String) -> Vec<T> {
+    let mut list: Vec<T> = inherited
+        .iter()
+        .filter(|x| !own.iter().any(|y| key(y) == key(x)))
+        .cloned()
+        .collect();
+    list.extend(own);
+    list
+}
+
+fn merge_params(defaults: &GeneralParams, params: GeneralParams) -> GeneralParams {
+    match (&defaults.0, params.0) {
+        (Value::Object(defaults), Value::Object(own)) => {
+            let mut merged = defaults.clone();
+            merged.extend(own);
+            GeneralParams(Value::Object(merged))
+        }
+        (_, own) => GeneralParams(own),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{AuthProfiles, PartialServiceConfig};
+    use crate::{Action, AuthType, GeneralParams};
+
+    #[test]
+    fn test_inherit() {
+        let defaults: PartialServiceConfig = serde_json::from_value(json!({
+            "url": "https://httpbin.org/",
+            "method": "ANY",
+            "headers": [
+                {"key": "x-team", "value": "platform", "action": "Add"},
+                {"key": "x-env", "value": "prod", "action": "Add"}
+            ],
+            "query_params": [{"key": "source", "value": "avalanche", "action": "Add"}],
+            "auth_profile": "httpbin",
+            "params": {"timeout": 10, "concurrency_limit": 5}
+        }))
+        .unwrap();
+        let profiles: AuthProfiles = serde_json::from_value(json!({
+            "httpbin": {"auth_type": "basic_auth", "params": {"username": "u", "password": "p"}},
+            "admin": {"auth_type": "header_auth", "params": [{"key": "x-admin", "value": "1"}]}
+        }))
+        .unwrap();
+        let inherit = |subproject| {
+            serde_json::from_value::<PartialServiceConfig>(subproject)
+                .unwrap()
+                .inherit(&defaults, &profiles)
+        };
+
+        let json = inherit(json!({"url": "json"})).unwrap();
+        assert_eq!(json.url, "https://httpbin.org/json");
+        assert_eq!(json.headers, defaults.headers);
+        assert_eq!(json.query_params, defaults.query_params);
+        assert_eq!(json.auth.auth_type(), &AuthType::BasicAuth);
+        assert_eq!(
+            json.params,
+            GeneralParams::new(json!({"timeout": 10, "concurrency_limit": 5}))
+        );
+
+        let anything = inherit(json!({
+            "url": "https://example.com/anything",
+            "method": "GET",
+            "headers": [
+                {"key": "X-Team", "value": "", "action": "Discard"},
+                {"key": "x-trace", "value": "1", "action": "Add"}
+            ],
+            "auth_profile": "admin",
+            "params": {"timeout": 30}
+        }))
+        .unwrap();
+        assert_eq!(anything.url, "https://example.com/anything");
+        assert_eq!(anything.method, crate::Method::GET);
+        let headers: Vec<_> = anything
+            .headers
+            .iter()
+            .map(|x| (x.key.as_str(), x.action.clone()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                ("x-env", Action::Add),
+                ("X-Team", Action::Discard),
+                ("x-trace", Action::Add)
+            ]
+        );
+        assert_eq!(anything.auth.auth_type(), &AuthType::HeaderAuth);
+        assert_eq!(
+            anything.params,
+            GeneralParams::new(json!({"timeout": 30, "concurrency_limit": 5}))
+        );
+
+        // config without defaults stays as it is
+        let plain = json!({"url": "https://example.com/", "method": "ANY"});
+        assert_eq!(
+            serde_json::from_value::<PartialServiceConfig>(plain.clone())
+                .unwrap()
+                .inherit(&Default::default(), &Default::default())
+                .unwrap(),
+            serde_json::from_value(plain).unwrap()
+        );
+
+        assert!(inherit(json!({"auth_profile": "missing"})).is_err());
+        assert!(inherit(
+            json!({"auth_profile": "admin", "auth": {"auth_type": "no_auth", "params": {}}})
+        )
+        .is_err());
+        assert!(
+            serde_json::from_value::<PartialServiceConfig>(json!({"url": "json"}))
+                .unwrap()
+                .inherit(&Default::default(), &profiles)
+                .is_err()
+        );
+    }
+}
@@ -30,20 +30,50 @@
 /// ```
 ///
 /// For more information, refer to the individual struct and enum documentation.
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+mod access;
 mod config;
 mod consts;
+mod envelope;
 mod error;
+mod format;
+mod inherit;
+mod network;
+mod overlay;
+mod params;
+mod quota;
+mod schema;
+mod secret;
+mod signature;
+pub use access::{AccessPolicy, AccessRule};
 pub use config::ServiceConfig;
+pub use envelope::{
+    decrypt_value, encrypted, map_encrypted, register_master_key, MasterKey, ENCRYPTED,
+};
 pub use error::*;
+pub use format::ConfigFormat;
+pub use inherit::{AuthProfiles, PartialServiceConfig};
+pub use ipnet::IpNet;
+pub use network::IpRules;
+pub use overlay::{merge_patch, overlay_path};
+pub use params::Params;
+pub use quota::Quota;
+pub use schema::{config_schema, ProjectConfig};
+pub use secret::{
+    is_secret_ref, register_secret_provider, resolve_str, resolve_value, secrets_generation,
+    secrets_rotated, EnvProvider, FileProvider, SecretProvider, DEFAULT_SECRETS_DIR,
+    DEFAULT_SECRET_ENV_PREFIX, RESERVED_ENV_PREFIXES,
+};
+pub use signature::{SignatureScheme, DEFAULT_SIGNATURE_TOLERANCE};
 
 pub use consts::*;
 
 /// `Action` represents an action that can be performed by a user.
 ///
 /// It contains fields for the action's name, description, and other relevant information.
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum Action {
     Add,
     Discard,
@@ -53,7 +83,7 @@ pub enum Action {
 /// `Header` represents an HTTP header.
 ///
 /// It contains fields for the header's name and value.
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct Header {
     pub key: String,
     pub value: String,
@@ -63,7 +93,7 @@ pub struct Header {
 /// `UrlParam` represents a URL parameter.
 ///
 /// It contains fields for the parameter's name and value.
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
 pub struct UrlParam {
     pub key: String,
     pub value: String,
@@ -74,7 +104,7 @@ pub struct UrlParam {
 ///
 /// It is an enum with variants for each possible HTTP method, such as GET, POST, PUT, DELETE, etc.
 #[allow(unused)]
-#[derive(Clone, Debug, Eq, PartialEq, Copy, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Copy, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum Method {
     GET,
     POST,
@@ -101,6 +131,13 @@ pub enum Method {
     ANY,
 }
 
+impl Method {
+    /// whether http method of a request (`"GET"`, `"post"`, ..) is covered by this method
+    pub fn matches(&self, method: &str) -> bool {
+        *self == Method::ANY || format!("{:?}", self).eq_ignore_ascii_case(method)
+    }
+}
+
 /// `MarsAuth` represents an authentication object for Mars.
 ///
 /// It contains a `params` field, which is a JSON value that contains the authentication parameters,
@@ -110,9 +147,10 @@ pub enum Method {
 /// can be used to get a clone of `params`, and the `auth_type` method can be used to get a reference to `auth_type`.
 ///
 /// The `new` method can be used to create a new instance of `MarsAuth`.
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 
 pub struct MarsAuth {
+    /// parameters of `auth_type`, `{"$enc": ..}` and `${provider:path}` values are resolved when service is built
     params: serde_json::Value,
     auth_type: AuthType,
 }
@@ -144,7 +182,7 @@ impl Default for MarsAuth {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Copy)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Copy, JsonSchema)]
 #[non_exhaustive]
 pub enum AuthType {
     #[serde(rename = "basic_auth")]
@@ -163,9 +201,10 @@ pub enum AuthType {
     NoAuth,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-
-pub struct GeneralParams(serde_json::Value);
+/// `GeneralParams` are `params` of a subproject as they are configured, see [`Params`] for their
+/// typed form.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GeneralParams(#[schemars(with = "Params")] serde_json::Value);
 
 impl GeneralParams {
     pub fn get_value(&self, key: &str) -> Option<&serde_json::Value> {
@@ -185,3 +224,73 @@ impl Default for GeneralParams {
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Default, Deserialize)]
 pub struct AvalancheTrace(pub String);
+
+/// `ClientIdentity` is the identity presented by a client certificate on a mutual-TLS connection.
+///
+/// It is inserted into request extensions, like `AvalancheTrace`, when mars-rover terminates TLS.
+/// Identities are matched against configured grants using [`ClientIdentity::candidates`], for example
+/// `sha256:<hex fingerprint>`, `subject:CN=billing,O=acme` or `san:billing.internal`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Default, Deserialize)]
+pub struct ClientIdentity {
+    /// lowercase hex sha256 fingerprint of the DER encoded certificate
+    pub fingerprint: String,
+    pub subject: String,
+    pub sans: Vec<String>,
+}
+
+impl ClientIdentity {
+    pub const FINGERPRINT_PREFIX: &'static str = "sha256:";
+    pub const SUBJECT_PREFIX: &'static str = "subject:";
+    pub const SAN_PREFIX: &'static str = "san:";
+
+    /// keys this identity can be granted with, most specific first
+    pub fn candidates(&self) -> Vec<String> {
+        let mut candidates = vec![
+            format!("{}{}", Self::FINGERPRINT_PREFIX, self.fingerprint),
+            format!("{}{}", Self::SUBJECT_PREFIX, self.subject),
+        ];
+        candidates.extend(self.sans.iter().map(|san| format!("{}{}", Self::SAN_PREFIX, san)));
+        candidates
+    }
+
+    /// whether a configured key refers to a certificate identity instead of a token
+    pub fn is_identity_key(key: &str) -> bool {
+        key.starts_with(Self::FINGERPRINT_PREFIX)
+            || key.starts_with(Self::SUBJECT_PREFIX)
+            || key.starts_with(Self::SAN_PREFIX)
+    }
+}
+
+/// `TokenSource` tells where a project expects the avalanche token to be carried.
+///
+/// A project can accept more than one source, they are tried in the configured order.
+/// Whatever source is configured is stripped from the request before it is forwarded upstream.
+///
+/// ```json
+/// "token_sources": [
+///     {"type": "header", "name": "avalanche-token"},
+///     {"type": "bearer"},
+///     {"type": "query", "name": "avalanche_token"},
+///     {"type": "cookie", "name": "avalanche_token"}
+/// ]
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenSource {
+    /// custom request header
+    Header { name: String },
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// url query parameter
+    Query { name: String },
+    /// cookie in `Cookie` header
+    Cookie { name: String },
+}
+
+impl Default for TokenSource {
+    fn default() -> Self {
+        TokenSource::Header {
+            name: AVALANCHE_TOKEN.to_string(),
+        }
+    }
+}
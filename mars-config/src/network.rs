@@ -0,0 +1,58 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// `IpRules` restricts source addresses requests are accepted from.
+///
+/// A request is denied when its address is in any `deny` block. Otherwise it is allowed when
+/// `allow` is empty or the address is in any `allow` block.
+///
+/// ```json
+/// {"allow": ["10.0.0.0/8", "192.168.1.7/32"], "deny": ["10.1.0.0/16"]}
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct IpRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub allow: Vec<IpNet>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    /// whether no rule is configured, in which case every address is allowed
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::IpRules;
+
+    #[test]
+    fn test_ip_rules() {
+        let rules: IpRules = serde_json::from_value(json!({
+            "allow": ["10.0.0.0/8", "2001:db8::/32"],
+            "deny": ["10.1.0.0/16"]
+        }))
+        .unwrap();
+        assert!(rules.permits("10.0.0.1".parse().unwrap()));
+        assert!(!rules.permits("10.1.0.1".parse().unwrap()));
+        assert!(!rules.permits("192.168.1.1".parse().unwrap()));
+        assert!(rules.permits("2001:db8::1".parse().unwrap()));
+        assert!(IpRules::default().permits("192.168.1.1".parse().unwrap()));
+    }
+}
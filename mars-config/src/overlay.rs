@@ -0,0 +1,114 @@
+//! Environment overlays, files that patch a base config for one environment (dev, staging, prod).
+//!
+//! Overlay of `config/config.json5` for `prod` is `config/config.prod.json5`. It is a JSON merge
+//! patch ([RFC 7386](https://www.rfc-editor.org/rfc/rfc7386)) of config, objects are merged key by
+//! key, `null` removes a key and anything else replaces it:
+//!
+//! ```json
+//! {
+//!     "aviko": {
+//!         "defaults": {"url": "https://httpbin.prod.internal/"},
+//!         "subprojects": {
+//!             "json": {"auth": {"auth_type": "basic_auth", "params": {"username": "${env:MARS_SECRET_USER}", "password": "${env:MARS_SECRET_PASSWORD}"}}},
+//!             "debug": null
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Lists, `headers` for example, are replaced as a whole.
+use serde_json::Value;
+
+/// path or url of overlay of `config` for `environment`, named before extension of config
+pub fn overlay_path(config: &str, environment: &str) -> String {
+    let end = config.find(['?', '#']).unwrap_or(config.len());
+    let (path, suffix) = config.split_at(end);
+    let name_start = path.rfind('/').map(|x| x + 1).unwrap_or(0);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(name_start + dot);
+            format!("{stem}.{environment}{extension}{suffix}")
+        }
+        _ => format!("{path}.{environment}{suffix}"),
+    }
+}
+
+/// applies `patch` to `value` as a JSON merge patch
+pub fn merge_patch(value: &mut Value, patch: Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *value = patch;
+            return;
+        }
+    };
+    if !value.is_object() {
+        *value = Value::Object(Default::default());
+    }
+    let object = value.as_object_mut().expect("value is object");
+    for (key, patch) in patch {
+        if patch.is_null() {
+            object.remove(&key);
+        } else {
+            merge_patch(object.entry(key).or_insert(Value::Null), patch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{merge_patch, overlay_path};
+
+    #[test]
+    fn test_overlay() {
+        assert_eq!(
+            overlay_path("config/config.json5", "prod"),
+            "config/config.prod.json5"
+        );
+        assert_eq!(
+            overlay_path("https://example.com/v1.2/config.yaml?ref=main", "dev"),
+            "https://example.com/v1.2/config.dev.yaml?ref=main"
+        );
+        assert_eq!(overlay_path("config/config", "dev"), "config/config.dev");
+        assert_eq!(overlay_path(".config", "dev"), ".config.dev");
+
+        let mut config = json!({
+            "aviko": {
+                "defaults": {"url": "http://localhost:1/", "method": "ANY"},
+                "subprojects": {
+                    "json": {"url": "json", "headers": [{"key": "x-env", "value": "dev", "action": "Add"}]},
+                    "debug": {"url": "debug"}
+                }
+            },
+            "other": {"subprojects": {}}
+        });
+        merge_patch(
+            &mut config,
+            json!({
+                "aviko": {
+                    "defaults": {"url": "https://example.com/"},
+                    "subprojects": {
+                        "json": {"headers": [], "params": {"timeout": 5}},
+                        "debug": null
+                    }
+                },
+                "staging": {"subprojects": {}}
+            }),
+        );
+        assert_eq!(
+            config,
+            json!({
+                "aviko": {
+                    "defaults": {"url": "https://example.com/", "method": "ANY"},
+                    "subprojects": {
+                        "json": {"url": "json", "headers": [], "params": {"timeout": 5}}
+                    }
+                },
+                "other": {"subprojects": {}},
+                "staging": {"subprojects": {}}
+            })
+        );
+    }
+}
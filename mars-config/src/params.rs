@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{GeneralParams, MarsError};
+
+/// `Params` are the typed form of a subproject's `params`.
+///
+/// Values of a wrong type, a string `"10"` for `timeout` for example, fail the subproject. Keys
+/// that are not params are kept in `unknown`, they are ignored with a warning so older configs
+/// keep working.
+///
+/// ```json
+/// "params": {"timeout": 10, "concurrency_limit": 5, "response_xml_to_json": true}
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Params {
+    /// seconds a request may take
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    /// max number of requests in flight at a time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_limit: Option<usize>,
+    /// max number of requests allowed per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u64>,
+    /// jolt spec request payload is transformed with, payload has to be json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jolt_request_transform: Option<Value>,
+    /// jolt spec response payload is transformed with, payload has to be json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jolt_response_transform: Option<Value>,
+    /// transform request payload from json to yaml
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub request_json_to_yaml: bool,
+    /// transform response payload from json to yaml
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub response_json_to_yaml: bool,
+    /// transform request payload from xml to json
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub request_xml_to_json: bool,
+    /// transform response payload from xml to json
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub response_xml_to_json: bool,
+    /// transform request payload from yaml to json
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub request_yaml_to_json: bool,
+    /// transform response payload from yaml to json
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub response_yaml_to_json: bool,
+    /// keys that are not params, ignored
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub unknown: Map<String, Value>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl Params {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs_f64)
+    }
+
+    /// warnings for keys that are not params, naming param they are likely a misspelling of
+    pub fn warnings(&self) -> Vec<String> {
+        let mut keys: Vec<_> = self.unknown.keys().collect();
+        keys.sort();
+        keys.into_iter()
+            .map(|key| match closest_param(key) {
+                Some(param) => format!("param `{key}` is not known, did you mean `{param}`?"),
+                None => format!("param `{key}` is not known, it is ignored"),
+            })
+            .collect()
+    }
+}
+
+/// known param within two edits of `key`
+fn closest_param(key: &str) -> Option<&'static str> {
+    [
+        crate::TIMEOUT,
+        crate::CONCURRENCY_LIMIT,
+        crate::RATE_LIMIT,
+        crate::TRANSFORM_JSON_TO_JSON_JOLT_REQUEST,
+        crate::TRANSFORM_JSON_TO_JSON_JOLT_RESPONSE,
+        crate::TRANSFORM_JSON_YAML_REQUEST,
+        crate::TRANSFORM_JSON_TO_YAML_RESPONSE,
+        crate::TRANSFORM_XML_JSON_REQUEST,
+        crate::TRANSFORM_XML_TO_JSON_RESPONSE,
+        crate::TRANSFORM_YAML_JSON_REQUEST,
+        crate::TRANSFORM_YAML_TO_JSON_RESPONSE,
+    ]
+    .into_iter()
+    .map(|param| (edit_distance(key, param), param))
+    .filter(|(distance, _)| *distance <= 2)
+    .min()
+    .map(|(_, param)| param)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if x == *y {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+impl GeneralParams {
+    /// typed params, errors when a param has a value of wrong type
+    pub fn parse(&self) -> Result<Params, MarsError> {
+        let params: Params = match &self.0 {
+            Value::Null => Params::default(),
+            value => serde_json::from_value(value.clone()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("params are not valid: {err}"))
+            })?,
+        };
+        if let Some(timeout) = params.timeout {
+            if !timeout.is_finite() || timeout < 0.0 {
+                return Err(MarsError::ServiceConfigError(format!(
+                    "params are not valid: timeout `{timeout}` is not a number of seconds"
+                )));
+            }
+        }
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::GeneralParams;
+
+    #[test]
+    fn test_params() {
+        let params = GeneralParams::new(json!({
+            "timeout": 1.5,
+            "concurency_limit": 10,
+            "response_xml_to_json": true,
+            "comment": "kept for humans"
+        }))
+        .parse()
+        .unwrap();
+        assert_eq!(params.timeout(), Some(Duration::from_millis(1500)));
+        assert_eq!(params.concurrency_limit, None);
+        assert!(params.response_xml_to_json);
+        assert!(!params.request_xml_to_json);
+        assert_eq!(
+            params.warnings(),
+            vec![
+                "param `comment` is not known, it is ignored",
+                "param `concurency_limit` is not known, did you mean `concurrency_limit`?",
+            ]
+        );
+
+        assert!(GeneralParams::new(json!({"timeout": "10"}))
+            .parse()
+            .is_err());
+        assert!(GeneralParams::new(json!({"timeout": -1})).parse().is_err());
+        assert!(GeneralParams::new(json!({"concurrency_limit": 1.5}))
+            .parse()
+            .is_err());
+        assert_eq!(
+            GeneralParams::default().parse().unwrap(),
+            Default::default()
+        );
+    }
+}
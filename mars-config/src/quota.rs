@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// `Quota` limits number of requests a token or user can make per calendar day and month (UTC).
+///
+/// ```json
+/// {"daily": 1000, "monthly": 20000}
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Quota {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<u64>,
+}
+
+impl Quota {
+    /// whether quota limits anything at all
+    pub fn is_limited(&self) -> bool {
+        self.daily.is_some() || self.monthly.is_some()
+    }
+}
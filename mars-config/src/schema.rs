@@ -0,0 +1,76 @@
+//! JSON Schema of the project config file, for editors to validate and autocomplete
+//! `config.json5` with.
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{AuthProfiles, IpRules, PartialServiceConfig, SignatureScheme, TokenSource};
+
+fn default_needs_auth() -> bool {
+    true
+}
+
+/// `ProjectConfig` is a project of the config file, keyed by its index.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// whether requests need an avalanche token or client certificate
+    #[serde(default = "default_needs_auth")]
+    pub needs_auth: bool,
+    /// where avalanche token is looked up, `avalanche-token` header when not configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_sources: Option<Vec<TokenSource>>,
+    /// source addresses requests are accepted from, any when not configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_rules: Option<IpRules>,
+    /// how requests are signed, when project requires signed requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureScheme>,
+    /// config subprojects inherit, see [`PartialServiceConfig::inherit`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<PartialServiceConfig>,
+    /// auth configs subprojects and defaults refer to by `auth_profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub auth_profiles: AuthProfiles,
+    /// services of project, keyed by path they are served under
+    pub subprojects: BTreeMap<String, PartialServiceConfig>,
+}
+
+/// JSON Schema of a config file, projects keyed by their index
+pub fn config_schema() -> Value {
+    let mut schema = schemars::schema_for!(BTreeMap<String, ProjectConfig>);
+    schema.schema.metadata().title = Some("avalanche config".to_string());
+    serde_json::to_value(schema).expect("schema serializes to json")
+}
+
+#[cfg(test)]
+mod test {
+    use super::config_schema;
+
+    #[test]
+    fn test_config_schema() {
+        let schema = config_schema();
+        let definitions = &schema["definitions"];
+        assert!(definitions["PartialServiceConfig"]["properties"]["params"].is_object());
+        assert!(definitions["PartialServiceConfig"]["required"].is_null());
+        assert_eq!(
+            definitions["Params"]["additionalProperties"],
+            serde_json::json!(false)
+        );
+        assert_eq!(
+            definitions["Params"]["properties"]["timeout"]["type"],
+            serde_json::json!(["number", "null"])
+        );
+
+        // checked in schema, editors point to it, is kept in sync
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../config/config.schema.json");
+        let checked_in: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            checked_in, schema,
+            "config/config.schema.json is stale, regenerate it with `mars_rover schema`"
+        );
+    }
+}
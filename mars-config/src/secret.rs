@@ -0,0 +1,343 @@
+//! References to secrets kept outside of config.
+//!
+//! Strings in auth params, headers and query params can hold references like
+//! `${env:MARS_SECRET_STRIPE_KEY}`, `${file:stripe}` or `${vault:kv/data/app#key}`, alone or
+//! within a longer value such as `Bearer ${env:MARS_SECRET_TOKEN}`. `$${` is a literal `${`. Config is stored and
+//! dumped with references as they are written, they are only replaced by
+//! [`ServiceConfig::resolve_secrets`] when a service is built. Errors name the reference, never
+//! the value it resolved to.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use serde_json::Value;
+
+use crate::{MarsError, ServiceConfig};
+
+/// `SecretProvider` resolves references of one kind, `${<name>:<path>}`, given their `path`.
+pub trait SecretProvider: Send + Sync {
+    fn resolve(&self, path: &str) -> Result<String, MarsError>;
+}
+
+/// prefix of variables `${env:..}` reads, unless `MARS_SECRET_ENV_PREFIXES` lists others
+pub const DEFAULT_SECRET_ENV_PREFIX: &str = "MARS_SECRET_";
+
+/// variables holding keys of mars itself, `${env:..}` never reads them whatever the prefixes
+pub const RESERVED_ENV_PREFIXES: &[&str] = &["MARS_MASTER_KEY", "VAULT_"];
+
+/// `${env:NAME}`, value of environment variable. only variables starting with one of its
+/// prefixes are read, so that config can't send any variable of mars upstream
+pub struct EnvProvider {
+    prefixes: Vec<String>,
+}
+
+impl EnvProvider {
+    pub fn new(prefixes: Vec<String>) -> Self {
+        EnvProvider { prefixes }
+    }
+
+    /// provider of prefixes listed in `MARS_SECRET_ENV_PREFIXES`, comma separated,
+    /// [`DEFAULT_SECRET_ENV_PREFIX`] when it is not set
+    pub fn from_env() -> Self {
+        EnvProvider::new(match std::env::var("MARS_SECRET_ENV_PREFIXES") {
+            Ok(prefixes) => prefixes
+                .split(',')
+                .map(str::trim)
+                .filter(|prefix| !prefix.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            Err(_) => vec![DEFAULT_SECRET_ENV_PREFIX.to_string()],
+        })
+    }
+}
+
+impl SecretProvider for EnvProvider {
+    fn resolve(&self, path: &str) -> Result<String, MarsError> {
+        if RESERVED_ENV_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+            || !self.prefixes.iter().any(|prefix| path.starts_with(prefix))
+        {
+            return Err(MarsError::ServiceConfigError(format!(
+                "environment variable `{path}` is not allowed, only ones starting with {} are",
+                self.prefixes
+                    .iter()
+                    .map(|prefix| format!("`{prefix}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        std::env::var(path).map_err(|_| {
+            MarsError::ServiceConfigError(format!("environment variable `{path}` is not set"))
+        })
+    }
+}
+
+/// directory `${file:..}` references are read from, unless `MARS_SECRETS_DIR` is set
+pub const DEFAULT_SECRETS_DIR: &str = "/run/secrets";
+
+/// `${file:name}`, contents of file without its trailing newline. files are read from secrets
+/// directory alone, paths leading out of it are refused, so config can't read any file mars
+/// can
+pub struct FileProvider {
+    dir: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileProvider { dir: dir.into() }
+    }
+
+    /// provider of `MARS_SECRETS_DIR`, [`DEFAULT_SECRETS_DIR`] when it is not set
+    pub fn from_env() -> Self {
+        FileProvider::new(
+            std::env::var("MARS_SECRETS_DIR").unwrap_or_else(|_| DEFAULT_SECRETS_DIR.to_string()),
+        )
+    }
+}
+
+impl SecretProvider for FileProvider {
+    fn resolve(&self, path: &str) -> Result<String, MarsError> {
+        let dir = self.dir.canonicalize().map_err(|err| {
+            MarsError::ServiceConfigError(format!(
+                "secrets directory `{}` is not available, {err}",
+                self.dir.display()
+            ))
+        })?;
+        // symlinks and `..` are followed before checking, so that neither leads out of it
+        let file = dir.join(path).canonicalize().map_err(|err| {
+            MarsError::ServiceConfigError(format!("unable to read `{path}`, {err}"))
+        })?;
+        if !file.starts_with(&dir) {
+            return Err(MarsError::ServiceConfigError(format!(
+                "`{path}` is outside of secrets directory `{}`",
+                dir.display()
+            )));
+        }
+        let contents = std::fs::read_to_string(&file).map_err(|err| {
+            MarsError::ServiceConfigError(format!("unable to read `{path}`, {err}"))
+        })?;
+        Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn providers() -> &'static RwLock<HashMap<String, Arc<dyn SecretProvider>>> {
+    static PROVIDERS: OnceLock<RwLock<HashMap<String, Arc<dyn SecretProvider>>>> = OnceLock::new();
+    PROVIDERS.get_or_init(|| {
+        let mut providers: HashMap<String, Arc<dyn SecretProvider>> = HashMap::new();
+        providers.insert("env".to_string(), Arc::new(EnvProvider::from_env()));
+        providers.insert("file".to_string(), Arc::new(FileProvider::from_env()));
+        RwLock::new(providers)
+    })
+}
+
+/// makes `${<name>:..}` references resolve through `provider`, replacing one registered earlier
+pub fn register_secret_provider(name: &str, provider: Arc<dyn SecretProvider>) {
+    providers()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(name.to_string(), provider);
+}
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// counts secrets that were replaced after being handed out. services built before it changed
+/// hold stale secrets and are to be built again
+pub fn secrets_generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// called by providers when a secret they resolved earlier has a new value, a rotated password
+/// or dynamic credentials past their lease
+pub fn secrets_rotated() {
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// whether `value` holds a secret reference
+pub fn is_secret_ref(value: &str) -> bool {
+    value.contains("${")
+}
+
+/// replaces every `${<name>:<path>}` of `value` with the secret it refers to, and every `$${`
+/// with `${`
+pub fn resolve_str(value: &str) -> Result<String, MarsError> {
+    let mut resolved = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            resolved.push_str(&rest[..start - 1]);
+            resolved.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        resolved.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            MarsError::ServiceConfigError("secret reference is not closed with `}`".to_string())
+        })?;
+        let reference = &rest[start..start + end + 1];
+        let (name, path) = reference[2..reference.len() - 1]
+            .split_once(':')
+            .ok_or_else(|| {
+                MarsError::ServiceConfigError(format!(
+                    "secret reference `{reference}` is not valid, expected `${{<provider>:<path>}}`"
+                ))
+            })?;
+        let provider = providers()
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .cloned()
+            .ok_or_else(|| {
+                MarsError::ServiceConfigError(format!(
+                    "secret provider `{name}` of `{reference}` is not configured"
+                ))
+            })?;
+        let secret = provider.resolve(path).map_err(|err| {
+            MarsError::ServiceConfigError(format!(
+                "unable to resolve `{reference}`, {}",
+                err.to_string().trim_end()
+            ))
+        })?;
+        resolved.push_str(&secret);
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// resolves references of every string within `value`. encrypted values are left as they are,
+/// what they hold is never taken for a reference
+pub fn resolve_value(value: &Value) -> Result<Value, MarsError> {
+    Ok(match value {
+        value if crate::encrypted(value).is_some() => value.clone(),
+        Value::String(value) if is_secret_ref(value) => Value::String(resolve_str(value)?),
+        Value::Array(array) => {
+            Value::Array(array.iter().map(resolve_value).collect::<Result<_, _>>()?)
+        }
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), resolve_value(value)?)))
+                .collect::<Result<_, MarsError>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+impl ServiceConfig {
+    /// copy of config with secret references of auth params, headers and query params
+    /// resolved, and encrypted auth params decrypted. it is meant to build a service with, not
+    /// to be stored or logged. auth params are decrypted after references are resolved, so
+    /// that a decrypted `${` is kept as it is
+    pub fn resolve_secrets(&self) -> Result<ServiceConfig, MarsError> {
+        let mut resolved = self.clone();
+        resolved.auth = crate::MarsAuth::new(
+            crate::decrypt_value(&resolve_value(&self.auth.get_params())?)?,
+            *self.auth.auth_type(),
+        );
+        for header in resolved.headers.iter_mut() {
+            if is_secret_ref(&header.value) {
+                header.value = resolve_str(&header.value)?;
+            }
+        }
+        for param in resolved.query_params.iter_mut() {
+            if is_secret_ref(&param.value) {
+                param.value = resolve_str(&param.value)?;
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use std::sync::Arc;
+
+    use super::{register_secret_provider, resolve_str, EnvProvider, FileProvider, SecretProvider};
+    use crate::{register_master_key, MasterKey, ServiceConfig};
+
+    #[test]
+    fn test_resolve_secrets() {
+        std::env::set_var("MARS_SECRET_TEST_PASSWORD", "hunter2");
+        let dir =
+            std::env::temp_dir().join(format!("mars-config-test-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("token"), "t0ken\n").unwrap();
+        register_secret_provider("file", Arc::new(FileProvider::new(&dir)));
+        let config: ServiceConfig = serde_json::from_value(json!({
+            "url": "https://example.com",
+            "method": "ANY",
+            "headers": [{"key": "authorization", "value": "Bearer ${file:token}", "action": "Add"}],
+            "query_params": [{"key": "page", "value": "1", "action": "Add"}],
+            "auth": {"auth_type": "basic_auth", "params": {"username": "u", "password": "${env:MARS_SECRET_TEST_PASSWORD}"}},
+        }))
+        .unwrap();
+        let resolved = config.resolve_secrets().unwrap();
+        assert_eq!(
+            resolved.auth.get_params(),
+            json!({"username": "u", "password": "hunter2"})
+        );
+        assert_eq!(resolved.headers[0].value, "Bearer t0ken");
+        assert_eq!(resolved.query_params, config.query_params);
+        // config itself keeps its references
+        assert_eq!(
+            config.auth.get_param("password"),
+            Some(&json!("${env:MARS_SECRET_TEST_PASSWORD}"))
+        );
+        // absolute paths are only read within secrets directory
+        let inside = format!("${{file:{}}}", dir.join("token").display());
+        assert_eq!(resolve_str(&inside).unwrap(), "t0ken");
+        assert!(resolve_str("${file:../../../etc/hostname}").is_err());
+        assert!(resolve_str("${file:/etc/hostname}").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            resolve_str("$${env:MARS_SECRET_TEST_PASSWORD} is ${env:MARS_SECRET_TEST_PASSWORD}")
+                .unwrap(),
+            "${env:MARS_SECRET_TEST_PASSWORD} is hunter2"
+        );
+        assert_eq!(resolve_str("$$${unknown:x}").unwrap(), "$${unknown:x}");
+        assert_eq!(resolve_str("costs $5").unwrap(), "costs $5");
+
+        let error = resolve_str("${env:MARS_SECRET_TEST_MISSING}")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("`${env:MARS_SECRET_TEST_MISSING}`"));
+        assert!(resolve_str("${unknown:x}").is_err());
+        // variables of mars itself, or without the prefix, are not read
+        std::env::set_var("MARS_MASTER_KEY_TEST", "master");
+        std::env::set_var("VAULT_TEST_TOKEN", "vault");
+        assert!(resolve_str("${env:MARS_MASTER_KEY_TEST}").is_err());
+        assert!(resolve_str("${env:PATH}").is_err());
+        let any = EnvProvider::new(vec!["".to_string()]);
+        assert!(any.resolve("PATH").is_ok());
+        assert!(any.resolve("MARS_MASTER_KEY_TEST").is_err());
+        assert!(any.resolve("VAULT_TEST_TOKEN").is_err());
+        assert!(resolve_str("${env:MARS_SECRET_TEST_PASSWORD").is_err());
+    }
+
+    #[test]
+    fn test_encrypted_not_resolved() {
+        let key = MasterKey::generate();
+        let encrypted = |plaintext: &str| MasterKey::parse(&key).unwrap().encrypt(plaintext);
+        register_master_key(MasterKey::parse(&key).unwrap());
+        std::env::set_var("MARS_SECRET_TEST_USERNAME", "u");
+        let config: ServiceConfig = serde_json::from_value(json!({
+            "url": "https://example.com",
+            "method": "ANY",
+            "auth": {"auth_type": "basic_auth", "params": {
+                "username": "${env:MARS_SECRET_TEST_USERNAME}",
+                "password": encrypted("a${b"),
+                "token": encrypted("${env:MARS_SECRET_TEST_USERNAME}"),
+            }},
+        }))
+        .unwrap();
+        assert_eq!(
+            config.resolve_secrets().unwrap().auth.get_params(),
+            json!({"username": "u", "password": "a${b", "token": "${env:MARS_SECRET_TEST_USERNAME}"})
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// default age (in seconds) of a signed request, after which it is considered a replay
+pub const DEFAULT_SIGNATURE_TOLERANCE: u64 = 300;
+
+fn default_tolerance() -> u64 {
+    DEFAULT_SIGNATURE_TOLERANCE
+}
+
+fn default_signature_header() -> String {
+    "x-signature".to_string()
+}
+
+fn default_signature_prefix() -> String {
+    "sha256=".to_string()
+}
+
+fn default_timestamp_header() -> String {
+    "x-signature-timestamp".to_string()
+}
+
+/// `SignatureScheme` describes how inbound requests to a project are signed with HMAC-SHA256.
+///
+/// Presets follow GitHub, Stripe and Slack webhooks. `hmac` is a generic scheme signing
+/// `<timestamp>.<body>` with the timestamp sent in its own header.
+///
+/// ```json
+/// {"type": "stripe", "secret": "whsec_..", "tolerance": 300}
+/// {"type": "hmac", "secret": "..", "header": "x-signature", "prefix": "sha256="}
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignatureScheme {
+    /// `X-Hub-Signature-256: sha256=<hex>` over the body. GitHub does not sign a timestamp, so
+    /// replays are detected within a week of the first delivery, or `tolerance` when longer
+    Github {
+        secret: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+    },
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex>` over `<timestamp>.<body>`
+    Stripe {
+        secret: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+    },
+    /// `X-Slack-Signature: v0=<hex>` over `v0:<timestamp>:<body>`, timestamp in
+    /// `X-Slack-Request-Timestamp`
+    Slack {
+        secret: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+    },
+    /// `<header>: <prefix><hex>` over `<timestamp>.<body>`, timestamp in `timestamp_header`
+    Hmac {
+        secret: String,
+        #[serde(default = "default_signature_header")]
+        header: String,
+        #[serde(default = "default_signature_prefix")]
+        prefix: String,
+        #[serde(default = "default_timestamp_header")]
+        timestamp_header: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+    },
+}
+
+impl SignatureScheme {
+    pub fn secret(&self) -> &str {
+        match self {
+            SignatureScheme::Github { secret, .. }
+            | SignatureScheme::Stripe { secret, .. }
+            | SignatureScheme::Slack { secret, .. }
+            | SignatureScheme::Hmac { secret, .. } => secret,
+        }
+    }
+
+    /// seconds a signed request stays valid
+    pub fn tolerance(&self) -> u64 {
+        match self {
+            SignatureScheme::Github { tolerance, .. }
+            | SignatureScheme::Stripe { tolerance, .. }
+            | SignatureScheme::Slack { tolerance, .. }
+            | SignatureScheme::Hmac { tolerance, .. } => *tolerance,
+        }
+    }
+}
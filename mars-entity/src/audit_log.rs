@@ -0,0 +1,318 @@
+//! Audit trail of changes to projects, subprojects, grants and tokens.
+//!
+//! Records are only ever inserted, [`ActiveModelBehavior`] refuses to update or delete them.
+//! Snapshots are redacted before they are stored, the paths of changed fields are computed on
+//! the unredacted values, so a rotated credential still shows up as changed.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sea_orm::{
+    entity::prelude::*, ActiveValue::NotSet, ActiveValue::Set, ConnectionTrait, FromJsonQueryResult,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// what secret values are replaced with
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct Snapshot(pub Value);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct Changes(pub Vec<String>);
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// unix time in seconds
+    pub at: i64,
+    /// `user:<id>` of an admin, or `cli`
+    pub actor: String,
+    /// `create`, `update` or `delete`
+    pub action: String,
+    /// `project`, `subproject`, `grant` or `token`
+    pub resource: String,
+    /// `<project>`, `<project>/<subproject>` or id of grant and token
+    pub resource_key: String,
+    pub before: Option<Snapshot>,
+    pub after: Option<Snapshot>,
+    /// paths of fields that differ between `before` and `after`, `auth.params.password`
+    pub changes: Changes,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn before_save(self, insert: bool) -> Result<Self, DbErr> {
+        if !insert {
+            return Err(DbErr::Custom("audit records can't be updated".to_string()));
+        }
+        Ok(self)
+    }
+
+    fn before_delete(self) -> Result<Self, DbErr> {
+        Err(DbErr::Custom("audit records can't be deleted".to_string()))
+    }
+}
+
+/// `Change` is a resource as it was and as it is, `None` when it didn't or doesn't exist.
+pub struct Change {
+    pub resource: &'static str,
+    pub resource_key: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl Change {
+    /// change of `resource`, `before` and `after` are its models as they were and are
+    pub fn new<T: Serialize>(
+        resource: &'static str,
+        resource_key: impl ToString,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) -> Self {
+        Change {
+            resource,
+            resource_key: resource_key.to_string(),
+            before: before.map(|x| serde_json::json!(x)),
+            after: after.map(|x| serde_json::json!(x)),
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match (&self.before, &self.after) {
+            (None, _) => "create",
+            (_, None) => "delete",
+            _ => "update",
+        }
+    }
+}
+
+/// writes audit record of `change` made by `actor`
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    actor: &str,
+    change: Change,
+) -> Result<Model, DbErr> {
+    // created and deleted resources list all of their fields
+    let missing = Value::Object(Map::new());
+    let changes = changes(
+        change.before.as_ref().unwrap_or(&missing),
+        change.after.as_ref().unwrap_or(&missing),
+    );
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default();
+    ActiveModel {
+        id: NotSet,
+        at: Set(at),
+        actor: Set(actor.to_string()),
+        action: Set(change.action().to_string()),
+        resource: Set(change.resource.to_string()),
+        resource_key: Set(change.resource_key),
+        before: Set(change.before.map(|x| Snapshot(redact(x)))),
+        after: Set(change.after.map(|x| Snapshot(redact(x)))),
+        changes: Set(Changes(changes)),
+    }
+    .insert(db)
+    .await
+}
+
+/// names of fields, headers and query params holding secrets
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase().replace('-', "_");
+    [
+        "password",
+        "secret",
+        "token",
+        "_key",
+        "apikey",
+        "authorization",
+        "cookie",
+    ]
+    .iter()
+    .any(|secret| name == *secret || name.ends_with(secret))
+}
+
+/// replaces secrets of a snapshot with [`REDACTED`]. every value under `auth.params` is
+/// considered a secret, along with fields, headers and query params named like one
+pub fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            // header and query param, `{"key": "x-api-key", "value": "..", "action": "Add"}`
+            let named_secret = matches!(object.get("key"), Some(Value::String(key)) if is_secret(key))
+                && object.contains_key("value");
+            let mut redacted = Map::new();
+            for (key, value) in object {
+                let value = if (named_secret && key == "value") || is_secret(&key) {
+                    redact_all(value)
+                } else if key == "auth" {
+                    redact_auth(value)
+                } else if key == "auth_profiles" {
+                    match value {
+                        Value::Object(profiles) => Value::Object(
+                            profiles
+                                .into_iter()
+                                .map(|(name, auth)| (name, redact_auth(auth)))
+                                .collect(),
+                        ),
+                        value => redact(value),
+                    }
+                } else {
+                    redact(value)
+                };
+                redacted.insert(key, value);
+            }
+            Value::Object(redacted)
+        }
+        Value::Array(array) => Value::Array(array.into_iter().map(redact).collect()),
+        value => value,
+    }
+}
+
+/// auth config with every value of its params redacted
+fn redact_auth(value: Value) -> Value {
+    match value {
+        Value::Object(mut auth) => {
+            if let Some(params) = auth.remove("params") {
+                auth.insert("params".to_string(), redact_all(params));
+            }
+            redact(Value::Object(auth))
+        }
+        value => redact(value),
+    }
+}
+
+/// keeps shape of `value`, but none of its scalars
+fn redact_all(value: Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (key, redact_all(value)))
+                .collect(),
+        ),
+        Value::Array(array) => Value::Array(array.into_iter().map(redact_all).collect()),
+        _ => Value::String(REDACTED.to_string()),
+    }
+}
+
+/// paths of scalars that differ between `before` and `after`, `auth.params.0.value`
+pub fn changes(before: &Value, after: &Value) -> Vec<String> {
+    let mut changes = vec![];
+    diff("", before, after, &mut changes);
+    changes
+}
+
+fn diff(path: &str, before: &Value, after: &Value, changes: &mut Vec<String>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                diff(
+                    &join(key),
+                    before.get(key).unwrap_or(&Value::Null),
+                    after.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for index in 0..before.len().max(after.len()) {
+                diff(
+                    &join(&index.to_string()),
+                    before.get(index).unwrap_or(&Value::Null),
+                    after.get(index).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (before, after) if before != after => changes.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sea_orm::{ActiveModelTrait, ConnectionTrait, Database, EntityTrait, Schema, Set};
+    use serde_json::json;
+
+    use super::{record, redact, Change, Entity, REDACTED};
+
+    #[test]
+    fn test_redact() {
+        let subproject = json!({
+            "url": "https://example.com",
+            "headers": [
+                {"key": "X-Api-Key", "value": "abc", "action": "Add"},
+                {"key": "accept", "value": "json", "action": "Add"},
+            ],
+            "auth": {"auth_type": "BasicAuth", "params": {"user": "u", "password": "p"}},
+            "auth_token": "6f9b...",
+            "auth_profiles": {"httpbin": {"auth_type": "BasicAuth", "params": {"user": "u"}}},
+        });
+        assert_eq!(
+            redact(subproject),
+            json!({
+                "url": "https://example.com",
+                "headers": [
+                    {"key": "X-Api-Key", "value": REDACTED, "action": "Add"},
+                    {"key": "accept", "value": "json", "action": "Add"},
+                ],
+                "auth": {"auth_type": "BasicAuth", "params": {"user": REDACTED, "password": REDACTED}},
+                "auth_token": REDACTED,
+                "auth_profiles": {"httpbin": {"auth_type": "BasicAuth", "params": {"user": REDACTED}}},
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        db.execute(
+            db.get_database_backend()
+                .build(&schema.create_table_from_entity(Entity)),
+        )
+        .await
+        .unwrap();
+
+        let auth = |password: &str| json!({"auth_type": "BasicAuth", "params": {"user": "u", "password": password}});
+        let model = record(
+            &db,
+            "user:1",
+            Change {
+                resource: "subproject",
+                resource_key: "aviko/json".to_string(),
+                before: Some(json!({"url": "https://a", "auth": auth("old")})),
+                after: Some(json!({"url": "https://a", "auth": auth("new")})),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(model.action, "update");
+        assert_eq!(model.changes.0, vec!["auth.params.password".to_string()]);
+        assert!(!serde_json::to_string(&model).unwrap().contains("new"));
+
+        // records can't be changed afterwards
+        let mut active: super::ActiveModel = model.clone().into();
+        active.actor = Set("user:2".to_string());
+        assert!(active.update(&db).await.is_err());
+        let active: super::ActiveModel = model.into();
+        assert!(active.delete(&db).await.is_err());
+        assert_eq!(Entity::find().all(&db).await.unwrap().len(), 1);
+    }
+}
@@ -1,6 +1,8 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+pub use super::http_params::{Policy, Quota, SourceCidrs};
+
 pub enum AuthTokenPermissions {
     Read = 0b1,
     Write = 0b10,
@@ -16,6 +18,12 @@ pub struct Model {
     pub user_id: Option<i32>,
     pub auth_token: Uuid,
     pub permissions: i32,
+    /// restricts project token to subprojects, paths and methods. `None` allows whole project
+    pub policy: Option<Policy>,
+    /// requests allowed with this token per day and month
+    pub quota: Option<Quota>,
+    /// source addresses the token can be used from, any when `None`
+    pub source_cidrs: Option<SourceCidrs>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -31,9 +39,9 @@ impl RelationTrait for Relation {
                 .from(Column::ProjectId)
                 .to(super::project::Column::Id)
                 .into(),
-            Self::User => Entity::belongs_to(super::user_project::Entity)
+            Self::User => Entity::belongs_to(super::user::Entity)
                 .from(Column::UserId)
-                .to(super::user_project::Column::Id)
+                .to(super::user::Column::Id)
                 .into(),
         }
     }
@@ -45,9 +53,18 @@ impl Related<super::project::Entity> for Entity {
     }
 }
 
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
 impl Related<super::user_project::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Project.def()
+        Entity::belongs_to(super::user_project::Entity)
+            .from(Column::UserId)
+            .to(super::user_project::Column::UserId)
+            .into()
     }
 }
 
@@ -1,12 +1,18 @@
 use std::collections::HashMap;
 use clap::{Parser, Subcommand};
-use mars_config::ServiceConfig;
+use mars_config::{
+    AccessPolicy, AuthProfiles, ConfigFormat, IpNet, IpRules, MasterKey,
+    PartialServiceConfig, Quota, SignatureScheme, TokenSource,
+};
+use mars_entity::audit_log::Change;
 use mars_entity::project::ActiveModel;
 use mars_entity::project::Entity as ProjectEntity;
 use mars_entity::subproject::Entity as SubProjectEntity;
 use sea_orm::prelude::Uuid;
 use sea_orm::{
-    sea_query::TableCreateStatement, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Schema,
+    sea_query::TableCreateStatement, ActiveModelTrait, ColumnTrait, ConnectionTrait,
+    DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Schema,
+    TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +24,10 @@ struct Args {
     #[clap(short, long, default_value = "config/config.json5")]
     file: String,
 
+    /// format of `file`, `json5`, `yaml` or `toml`. picked by its extension when omitted
+    #[clap(long)]
+    format: Option<ConfigFormat>,
+
     #[clap(short, long)]
     db: String,
 }
@@ -33,15 +43,262 @@ enum SubCommand {
         user_id: Option<i32>,
         /// projectid
         #[clap(short, long)]
-        project_id: Option<i32>
+        project_id: Option<i32>,
+        /// access policy of project token as json, `{"allow": [{"subproject": "json"}]}`
+        #[clap(long)]
+        policy: Option<String>,
+        /// requests allowed with the token per day
+        #[clap(long)]
+        daily_quota: Option<u64>,
+        /// requests allowed with the token per month
+        #[clap(long)]
+        monthly_quota: Option<u64>,
+        /// CIDR the token can be used from, can be repeated. any address when omitted
+        #[clap(long)]
+        source_cidr: Vec<IpNet>,
+    },
+    /// limit requests of a user across all of its tokens. omit both to remove quota
+    SetQuota {
+        /// userid
+        #[clap(short, long)]
+        user_id: i32,
+        /// requests allowed per day
+        #[clap(long)]
+        daily: Option<u64>,
+        /// requests allowed per month
+        #[clap(long)]
+        monthly: Option<u64>,
+    },
+    /// restrict user's grant on a project to subprojects, paths and methods
+    Restrict {
+        /// userid
+        #[clap(short, long)]
+        user_id: i32,
+        /// projectid
+        #[clap(short, long)]
+        project_id: i32,
+        /// access policy as json, `{"allow": [{"subproject": "json"}]}`. omit to allow whole project
+        #[clap(long)]
+        policy: Option<String>,
+    },
+    /// create a group of users
+    CreateGroup {
+        /// name of the group
+        #[clap(short, long)]
+        name: String,
+    },
+    /// delete a group along with its memberships and project grants
+    DeleteGroup {
+        /// groupid
+        #[clap(short, long)]
+        group_id: i32,
+    },
+    /// add user to a group
+    AddMember {
+        /// groupid
+        #[clap(short, long)]
+        group_id: i32,
+        /// userid
+        #[clap(short, long)]
+        user_id: i32,
+    },
+    /// remove user from a group
+    RemoveMember {
+        /// groupid
+        #[clap(short, long)]
+        group_id: i32,
+        /// userid
+        #[clap(short, long)]
+        user_id: i32,
+    },
+    /// grant a project to every member of a group
+    GrantGroup {
+        /// groupid
+        #[clap(short, long)]
+        group_id: i32,
+        /// projectid
+        #[clap(short, long)]
+        project_id: i32,
+        /// access policy as json, `{"allow": [{"subproject": "json"}]}`. omit to allow whole project
+        #[clap(long)]
+        policy: Option<String>,
+    },
+    /// revoke group's grant on a project
+    RevokeGroup {
+        /// groupid
+        #[clap(short, long)]
+        group_id: i32,
+        /// projectid
+        #[clap(short, long)]
+        project_id: i32,
+    },
+    /// create the first admin user and print its token for the admin api
+    Bootstrap {
+        /// name of the admin
+        #[clap(short, long)]
+        name: String,
+        /// email of the admin
+        #[clap(short, long)]
+        email: String,
+    },
+    /// bind a client certificate identity to a user for mutual-TLS
+    BindCertificate {
+        /// userid
+        #[clap(short, long)]
+        user_id: i32,
+        /// `sha256:<fingerprint>`, `subject:<dn>` or `san:<name>`
+        #[clap(short, long)]
+        identity: String,
+    },
+    /// print audit records of changes to projects, subprojects, grants and tokens, newest first
+    Audit {
+        /// `project`, `subproject`, `grant` or `token`
+        #[clap(long)]
+        resource: Option<String>,
+        /// `<project>`, `<project>/<subproject>`, `user:<id>/<project>`, `group:<id>/<project>`
+        /// or token id
+        #[clap(long)]
+        resource_key: Option<String>,
+        /// `user:<id>` of an admin, or `cli`
+        #[clap(long)]
+        actor: Option<String>,
+        /// unix time in seconds
+        #[clap(long)]
+        since: Option<i64>,
+        /// unix time in seconds
+        #[clap(long)]
+        until: Option<i64>,
+        #[clap(long, default_value = "100")]
+        limit: u64,
+    },
+    /// list saved versions of a subproject's config
+    Versions {
+        /// index of project
+        #[clap(short, long)]
+        project: String,
+        /// index of subproject
+        #[clap(short, long)]
+        subproject: String,
+    },
+    /// print fields that differ between two versions of a subproject's config
+    DiffVersions {
+        /// index of project
+        #[clap(short, long)]
+        project: String,
+        /// index of subproject
+        #[clap(short, long)]
+        subproject: String,
+        #[clap(long)]
+        from: i32,
+        #[clap(long)]
+        to: i32,
+    },
+    /// restore config of a subproject's version, saved as its newest version
+    Rollback {
+        /// index of project
+        #[clap(short, long)]
+        project: String,
+        /// index of subproject
+        #[clap(short, long)]
+        subproject: String,
+        /// version to restore
+        #[clap(short, long)]
+        version: i32,
+    },
+    /// print a new random master key for encrypted auth params
+    GenerateKey,
+    /// print `{"$enc": ..}` of a value, to put in auth params. master key is read from
+    /// MARS_MASTER_KEY or MARS_MASTER_KEY_FILE
+    Encrypt {
+        /// value to encrypt, read from stdin when omitted
+        value: Option<String>,
+    },
+    /// print plaintext of an encrypted value, `{"$enc": ..}` or what it holds
+    Decrypt {
+        value: String,
+    },
+    /// encrypt auth params of every subproject, and of their versions, with a new master key.
+    /// current key is read from MARS_MASTER_KEY or MARS_MASTER_KEY_FILE
+    Rekey {
+        /// file holding new master key
+        #[clap(long)]
+        new_key_file: String,
+    },
+}
+
+/// writes audit record of a change made through this cli
+async fn audit<C: ConnectionTrait>(db: &C, change: Change) {
+    mars_entity::audit_log::record(db, "cli", change)
+        .await
+        .expect("unable to write audit record");
+}
+
+/// subproject by index of project and subproject
+async fn find_subproject(
+    db: &DatabaseConnection,
+    project: &str,
+    subproject: &str,
+) -> Option<mars_entity::subproject::Model> {
+    let project = mars_entity::project::Entity::find()
+        .filter(mars_entity::project::Column::Index.eq(project))
+        .one(db)
+        .await
+        .expect("unable to make query")?;
+    mars_entity::subproject::Entity::find()
+        .filter(mars_entity::subproject::Column::ProjectId.eq(project.id))
+        .filter(mars_entity::subproject::Column::Index.eq(subproject))
+        .one(db)
+        .await
+        .expect("unable to make query")
+}
+
+/// version of subproject, printed when it doesn't exist
+async fn find_version(
+    db: &DatabaseConnection,
+    subproject: &mars_entity::subproject::Model,
+    version: i32,
+) -> Option<mars_entity::subproject_version::Model> {
+    let found = mars_entity::subproject_version::find(db, subproject.id, version)
+        .await
+        .expect("unable to make query");
+    if found.is_none() {
+        println!("subproject {} has no version {version}", subproject.index);
     }
+    found
+}
+
+/// master key given through environment, printed when there is none
+fn master_key() -> Option<MasterKey> {
+    match MasterKey::from_env() {
+        Ok(Some(key)) => Some(key),
+        Ok(None) => {
+            println!("master key is read from MARS_MASTER_KEY or MARS_MASTER_KEY_FILE, neither is set");
+            None
+        }
+        Err(err) => {
+            println!("{err}");
+            None
+        }
+    }
+}
+
+/// index of project, its id when it doesn't exist
+async fn project_index<C: ConnectionTrait>(db: &C, project_id: i32) -> String {
+    mars_entity::project::Entity::find_by_id(project_id)
+        .one(db)
+        .await
+        .expect("unable to make query")
+        .map(|x| x.index)
+        .unwrap_or_else(|| project_id.to_string())
 }
 
+#[allow(unused)]
 #[derive(Serialize, Deserialize, Debug)]
 struct TotalConfig {
     tokens: TokenConfig,
     projects: MultipleProjects,
 }
+#[allow(unused)]
 #[derive(Serialize, Deserialize, Debug)]
 struct TokenConfig(HashMap<String, String>);
 
@@ -50,13 +307,26 @@ struct MultipleProjects(HashMap<String, ProjectDTO>);
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ProjectDTO {
-    subprojects: HashMap<String, ServiceConfig>,
+    subprojects: HashMap<String, PartialServiceConfig>,
     needs_auth: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    defaults: Option<PartialServiceConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_profiles: Option<AuthProfiles>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_sources: Option<Vec<TokenSource>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip_rules: Option<IpRules>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<SignatureScheme>,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let format = args
+        .format
+        .unwrap_or_else(|| ConfigFormat::from_path(&args.file));
 
     println!("able to parse file");
     let db = sea_orm::Database::connect(args.db)
@@ -65,7 +335,26 @@ async fn main() {
     println!("able to connect to db");
 
     match args.action {
-        SubCommand::CreateToken { user_id, project_id } => {
+        SubCommand::CreateToken {
+            user_id,
+            project_id,
+            policy,
+            daily_quota,
+            monthly_quota,
+            source_cidr,
+        } => {
+            let source_cidrs =
+                (!source_cidr.is_empty()).then_some(mars_entity::authtoken::SourceCidrs(source_cidr));
+            let policy = policy.map(|policy| {
+                mars_entity::authtoken::Policy(
+                    serde_json::from_str::<AccessPolicy>(&policy).expect("unable to parse policy"),
+                )
+            });
+            let quota = Quota {
+                daily: daily_quota,
+                monthly: monthly_quota,
+            };
+            let quota = quota.is_limited().then_some(mars_entity::authtoken::Quota(quota));
             if let Some(project_id) = project_id {
                 let auth_token = Uuid::new_v4();
                 println!("generated auth_token is {}", auth_token);
@@ -90,12 +379,16 @@ async fn main() {
                     permissions: sea_orm::ActiveValue::Set(
                         mars_entity::authtoken::AuthTokenPermissions::Execute as i32,
                     ),
+                    policy: sea_orm::ActiveValue::Set(policy.clone()),
+                    quota: sea_orm::ActiveValue::Set(quota.clone()),
+                    source_cidrs: sea_orm::ActiveValue::Set(source_cidrs.clone()),
                 };
-                let _res = mars_entity::authtoken::Entity::insert(auth_token_model)
-                    .exec(&db)
+                let res = auth_token_model
+                    .insert(&db)
                     .await
                     .expect("unable to insert auth_token");
-                println!("inserted {:?}", _res);
+                audit(&db, Change::new("token", res.id, None, Some(&res))).await;
+                println!("inserted {:?}", res);
             }
             if let Some(user) = user_id {
                 let auth_token = Uuid::new_v4();
@@ -113,23 +406,505 @@ async fn main() {
                     }
                 };
 
+                // admin tokens only work with admin api, api tokens never do
+                let permissions = if user.user_type == mars_entity::user::UserType::Admin as i32 {
+                    mars_entity::authtoken::AuthTokenPermissions::Read as i32
+                        | mars_entity::authtoken::AuthTokenPermissions::Write as i32
+                } else {
+                    mars_entity::authtoken::AuthTokenPermissions::Execute as i32
+                };
                 let auth_token_model = mars_entity::authtoken::ActiveModel {
                     id: sea_orm::ActiveValue::NotSet,
                     user_id: sea_orm::ActiveValue::Set(Some(user.id)),
                     auth_token: sea_orm::ActiveValue::Set(auth_token),
                     project_id: sea_orm::ActiveValue::NotSet,
-                    permissions: sea_orm::ActiveValue::Set(
-                        mars_entity::authtoken::AuthTokenPermissions::Execute as i32,
-                    ),
+                    permissions: sea_orm::ActiveValue::Set(permissions),
+                    // user tokens are restricted through user's grant, see `restrict`
+                    policy: sea_orm::ActiveValue::NotSet,
+                    quota: sea_orm::ActiveValue::Set(quota.clone()),
+                    source_cidrs: sea_orm::ActiveValue::Set(source_cidrs.clone()),
                 };
-                let _res = mars_entity::authtoken::Entity::insert(auth_token_model)
-                    .exec(&db)
+                let res = auth_token_model
+                    .insert(&db)
                     .await
                     .expect("unable to insert auth_token");
-                println!("inserted {:?}", _res);
+                audit(&db, Change::new("token", res.id, None, Some(&res))).await;
+                println!("inserted {:?}", res);
             }
         
         },
+        SubCommand::Restrict {
+            user_id,
+            project_id,
+            policy,
+        } => {
+            let policy = policy.map(|policy| {
+                mars_entity::user_project::Policy(
+                    serde_json::from_str::<AccessPolicy>(&policy).expect("unable to parse policy"),
+                )
+            });
+            let user_project = match mars_entity::user_project::Entity::find()
+                .filter(mars_entity::user_project::Column::UserId.eq(user_id))
+                .filter(mars_entity::user_project::Column::ProjectId.eq(project_id))
+                .one(&db)
+                .await
+                .expect("unable to make query")
+            {
+                Some(user_project) => user_project,
+                None => {
+                    println!("user {user_id} has no access to project {project_id}");
+                    return;
+                }
+            };
+            let before = user_project.clone();
+            let mut user_project: mars_entity::user_project::ActiveModel = user_project.into();
+            user_project.policy = sea_orm::ActiveValue::Set(policy);
+            let res = user_project
+                .update(&db)
+                .await
+                .expect("unable to update user project");
+            let key = format!("user:{user_id}/{}", project_index(&db, project_id).await);
+            audit(&db, Change::new("grant", key, Some(&before), Some(&res))).await;
+            println!("updated {:?}", res);
+        }
+        SubCommand::SetQuota {
+            user_id,
+            daily,
+            monthly,
+        } => {
+            let quota = Quota { daily, monthly };
+            let user = match mars_entity::user::Entity::find_by_id(user_id)
+                .one(&db)
+                .await
+                .expect("unable to make query")
+            {
+                Some(user) => user,
+                None => {
+                    println!("user {user_id} does not exist");
+                    return;
+                }
+            };
+            let mut user: mars_entity::user::ActiveModel = user.into();
+            user.quota =
+                sea_orm::ActiveValue::Set(quota.is_limited().then_some(mars_entity::user::Quota(quota)));
+            let res = user.update(&db).await.expect("unable to update user");
+            println!("updated {:?}", res);
+        }
+        SubCommand::CreateGroup { name } => {
+            let group = mars_entity::group::ActiveModel {
+                id: sea_orm::ActiveValue::NotSet,
+                name: sea_orm::ActiveValue::Set(name),
+            };
+            let res = mars_entity::group::Entity::insert(group)
+                .exec(&db)
+                .await
+                .expect("unable to insert group");
+            println!("created group {}", res.last_insert_id);
+        }
+        SubCommand::DeleteGroup { group_id } => {
+            // grants, members and group go together, or none of them does
+            let txn = db.begin().await.expect("unable to start transaction");
+            let grants = mars_entity::group_project::Entity::find()
+                .filter(mars_entity::group_project::Column::GroupId.eq(group_id))
+                .all(&txn)
+                .await
+                .expect("unable to make query");
+            mars_entity::group_project::Entity::delete_many()
+                .filter(mars_entity::group_project::Column::GroupId.eq(group_id))
+                .exec(&txn)
+                .await
+                .expect("unable to delete group grants");
+            for grant in grants {
+                let key = format!("group:{group_id}/{}", project_index(&txn, grant.project_id).await);
+                audit(&txn, Change::new("grant", key, Some(&grant), None)).await;
+            }
+            mars_entity::group_member::Entity::delete_many()
+                .filter(mars_entity::group_member::Column::GroupId.eq(group_id))
+                .exec(&txn)
+                .await
+                .expect("unable to delete group members");
+            let res = mars_entity::group::Entity::delete_by_id(group_id)
+                .exec(&txn)
+                .await
+                .expect("unable to delete group");
+            txn.commit().await.expect("unable to delete group");
+            println!("deleted {} group", res.rows_affected);
+        }
+        SubCommand::AddMember { group_id, user_id } => {
+            if mars_entity::group::Entity::find_by_id(group_id)
+                .one(&db)
+                .await
+                .expect("unable to make query")
+                .is_none()
+            {
+                println!("group {group_id} does not exist");
+                return;
+            }
+            if mars_entity::user::Entity::find_by_id(user_id)
+                .one(&db)
+                .await
+                .expect("unable to make query")
+                .is_none()
+            {
+                println!("user {user_id} does not exist");
+                return;
+            }
+            if mars_entity::group_member::Entity::find()
+                .filter(mars_entity::group_member::Column::GroupId.eq(group_id))
+                .filter(mars_entity::group_member::Column::UserId.eq(user_id))
+                .one(&db)
+                .await
+                .expect("unable to make query")
+                .is_some()
+            {
+                println!("user {user_id} is already member of group {group_id}");
+                return;
+            }
+            let member = mars_entity::group_member::ActiveModel {
+                id: sea_orm::ActiveValue::NotSet,
+                group_id: sea_orm::ActiveValue::Set(group_id),
+                user_id: sea_orm::ActiveValue::Set(user_id),
+            };
+            let res = mars_entity::group_member::Entity::insert(member)
+                .exec(&db)
+                .await
+                .expect("unable to insert group member");
+            println!("inserted {:?}", res);
+        }
+        SubCommand::RemoveMember { group_id, user_id } => {
+            let res = mars_entity::group_member::Entity::delete_many()
+                .filter(mars_entity::group_member::Column::GroupId.eq(group_id))
+                .filter(mars_entity::group_member::Column::UserId.eq(user_id))
+                .exec(&db)
+                .await
+                .expect("unable to delete group member");
+            println!("removed {} membership", res.rows_affected);
+        }
+        SubCommand::GrantGroup {
+            group_id,
+            project_id,
+            policy,
+        } => {
+            let policy = policy.map(|policy| {
+                mars_entity::group_project::Policy(
+                    serde_json::from_str::<AccessPolicy>(&policy).expect("unable to parse policy"),
+                )
+            });
+            if mars_entity::group::Entity::find_by_id(group_id)
+                .one(&db)
+                .await
+                .expect("unable to make query")
+                .is_none()
+            {
+                println!("group {group_id} does not exist");
+                return;
+            }
+            let key = format!("group:{group_id}/{}", project_index(&db, project_id).await);
+            match mars_entity::group_project::Entity::find()
+                .filter(mars_entity::group_project::Column::GroupId.eq(group_id))
+                .filter(mars_entity::group_project::Column::ProjectId.eq(project_id))
+                .one(&db)
+                .await
+                .expect("unable to make query")
+            {
+                Some(group_project) => {
+                    let before = group_project.clone();
+                    let mut group_project: mars_entity::group_project::ActiveModel =
+                        group_project.into();
+                    group_project.policy = sea_orm::ActiveValue::Set(policy);
+                    let res = group_project
+                        .update(&db)
+                        .await
+                        .expect("unable to update group grant");
+                    audit(&db, Change::new("grant", key, Some(&before), Some(&res))).await;
+                    println!("updated {:?}", res);
+                }
+                None => {
+                    let group_project = mars_entity::group_project::ActiveModel {
+                        id: sea_orm::ActiveValue::NotSet,
+                        group_id: sea_orm::ActiveValue::Set(group_id),
+                        project_id: sea_orm::ActiveValue::Set(project_id),
+                        permissions: sea_orm::ActiveValue::Set(
+                            mars_entity::authtoken::AuthTokenPermissions::Execute as i32,
+                        ),
+                        policy: sea_orm::ActiveValue::Set(policy),
+                    };
+                    let res = group_project
+                        .insert(&db)
+                        .await
+                        .expect("unable to insert group grant");
+                    audit(&db, Change::new("grant", key, None, Some(&res))).await;
+                    println!("inserted {:?}", res);
+                }
+            }
+        }
+        SubCommand::RevokeGroup {
+            group_id,
+            project_id,
+        } => {
+            let grant = mars_entity::group_project::Entity::find()
+                .filter(mars_entity::group_project::Column::GroupId.eq(group_id))
+                .filter(mars_entity::group_project::Column::ProjectId.eq(project_id))
+                .one(&db)
+                .await
+                .expect("unable to make query");
+            let res = mars_entity::group_project::Entity::delete_many()
+                .filter(mars_entity::group_project::Column::GroupId.eq(group_id))
+                .filter(mars_entity::group_project::Column::ProjectId.eq(project_id))
+                .exec(&db)
+                .await
+                .expect("unable to delete group grant");
+            if let Some(grant) = grant {
+                let key = format!("group:{group_id}/{}", project_index(&db, project_id).await);
+                audit(&db, Change::new("grant", key, Some(&grant), None)).await;
+            }
+            println!("revoked {} grant", res.rows_affected);
+        }
+        SubCommand::Bootstrap { name, email } => {
+            let admin_type = mars_entity::user::UserType::Admin as i32;
+            if let Some(admin) = mars_entity::user::Entity::find()
+                .filter(mars_entity::user::Column::UserType.eq(admin_type))
+                .one(&db)
+                .await
+                .expect("unable to make query")
+            {
+                println!(
+                    "admin user {} already exists, create other admins through admin api",
+                    admin.id
+                );
+                return;
+            }
+            // users table has no auto increment
+            let id = mars_entity::user::Entity::find()
+                .order_by_desc(mars_entity::user::Column::Id)
+                .one(&db)
+                .await
+                .expect("unable to make query")
+                .map(|x| x.id + 1)
+                .unwrap_or(1);
+            let user = mars_entity::user::ActiveModel {
+                id: sea_orm::ActiveValue::Set(id),
+                user_type: sea_orm::ActiveValue::Set(admin_type),
+                user_name: sea_orm::ActiveValue::Set(name),
+                user_email: sea_orm::ActiveValue::Set(email),
+                cert_identity: sea_orm::ActiveValue::Set(None),
+                quota: sea_orm::ActiveValue::Set(None),
+            };
+            mars_entity::user::Entity::insert(user)
+                .exec(&db)
+                .await
+                .expect("unable to insert admin user");
+            let auth_token = Uuid::new_v4();
+            let auth_token_model = mars_entity::authtoken::ActiveModel {
+                id: sea_orm::ActiveValue::NotSet,
+                user_id: sea_orm::ActiveValue::Set(Some(id)),
+                auth_token: sea_orm::ActiveValue::Set(auth_token),
+                project_id: sea_orm::ActiveValue::NotSet,
+                permissions: sea_orm::ActiveValue::Set(
+                    mars_entity::authtoken::AuthTokenPermissions::Read as i32
+                        | mars_entity::authtoken::AuthTokenPermissions::Write as i32,
+                ),
+                policy: sea_orm::ActiveValue::NotSet,
+                quota: sea_orm::ActiveValue::NotSet,
+                source_cidrs: sea_orm::ActiveValue::NotSet,
+            };
+            let token = auth_token_model
+                .insert(&db)
+                .await
+                .expect("unable to insert admin token");
+            audit(&db, Change::new("token", token.id, None, Some(&token))).await;
+            println!("created admin user {id}");
+            println!("admin token is user:{auth_token}");
+        }
+        SubCommand::BindCertificate { user_id, identity } => {
+            if !mars_config::ClientIdentity::is_identity_key(&identity) {
+                println!("identity should start with `sha256:`, `subject:` or `san:`");
+                return;
+            }
+            let user = match mars_entity::user::Entity::find()
+                .filter(mars_entity::user::Column::Id.eq(user_id))
+                .one(&db)
+                .await
+                .expect("unable to make query")
+            {
+                Some(user) => user,
+                None => {
+                    println!("user {user_id} does not exist");
+                    return;
+                }
+            };
+            let mut user: mars_entity::user::ActiveModel = user.into();
+            user.cert_identity = sea_orm::ActiveValue::Set(Some(identity));
+            let res = user.update(&db).await.expect("unable to update user");
+            println!("updated {:?}", res);
+        }
+        SubCommand::Audit {
+            resource,
+            resource_key,
+            actor,
+            since,
+            until,
+            limit,
+        } => {
+            let mut select = mars_entity::audit_log::Entity::find();
+            if let Some(resource) = resource {
+                select = select.filter(mars_entity::audit_log::Column::Resource.eq(resource));
+            }
+            if let Some(resource_key) = resource_key {
+                select = select.filter(mars_entity::audit_log::Column::ResourceKey.eq(resource_key));
+            }
+            if let Some(actor) = actor {
+                select = select.filter(mars_entity::audit_log::Column::Actor.eq(actor));
+            }
+            if let Some(since) = since {
+                select = select.filter(mars_entity::audit_log::Column::At.gte(since));
+            }
+            if let Some(until) = until {
+                select = select.filter(mars_entity::audit_log::Column::At.lte(until));
+            }
+            let records = select
+                .order_by_desc(mars_entity::audit_log::Column::Id)
+                .limit(limit)
+                .all(&db)
+                .await
+                .expect("unable to make query");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&records).expect("impossible to fail")
+            );
+        }
+        SubCommand::Versions {
+            project,
+            subproject,
+        } => {
+            let subproject = match find_subproject(&db, &project, &subproject).await {
+                Some(subproject) => subproject,
+                None => {
+                    println!("subproject {project}/{subproject} does not exist");
+                    return;
+                }
+            };
+            for version in mars_entity::subproject_version::list(&db, subproject.id)
+                .await
+                .expect("unable to make query")
+            {
+                println!(
+                    "version {} at {} by {}, {:?} {}",
+                    version.version,
+                    version.at,
+                    version.actor,
+                    version.config.0.method,
+                    version.config.0.url
+                );
+            }
+        }
+        SubCommand::DiffVersions {
+            project,
+            subproject,
+            from,
+            to,
+        } => {
+            let subproject = match find_subproject(&db, &project, &subproject).await {
+                Some(subproject) => subproject,
+                None => {
+                    println!("subproject {project}/{subproject} does not exist");
+                    return;
+                }
+            };
+            let (from, to) = match (
+                find_version(&db, &subproject, from).await,
+                find_version(&db, &subproject, to).await,
+            ) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&mars_entity::subproject_version::diff(&from, &to))
+                    .expect("impossible to fail")
+            );
+        }
+        SubCommand::Rollback {
+            project,
+            subproject,
+            version,
+        } => {
+            let subproject = match find_subproject(&db, &project, &subproject).await {
+                Some(subproject) => subproject,
+                None => {
+                    println!("subproject {project}/{subproject} does not exist");
+                    return;
+                }
+            };
+            let version = match find_version(&db, &subproject, version).await {
+                Some(version) => version,
+                None => return,
+            };
+            let (_, restored) =
+                mars_entity::subproject_version::rollback(&db, subproject, &version, "cli")
+                    .await
+                    .expect("unable to roll back subproject");
+            println!(
+                "restored version {} as version {}. running mars-rover keeps serving the cached config until restarted, roll back through admin api to apply it right away",
+                version.version, restored.version
+            );
+        }
+        SubCommand::GenerateKey => {
+            println!("{}", MasterKey::generate());
+        }
+        SubCommand::Encrypt { value } => {
+            let key = match master_key() {
+                Some(key) => key,
+                None => return,
+            };
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    let mut value = String::new();
+                    std::io::stdin()
+                        .read_line(&mut value)
+                        .expect("unable to read value");
+                    value.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            println!("{}", key.encrypt(&value));
+        }
+        SubCommand::Decrypt { value } => {
+            let key = match master_key() {
+                Some(key) => key,
+                None => return,
+            };
+            let parsed = serde_json::from_str(&value).ok();
+            let encrypted = parsed
+                .as_ref()
+                .and_then(mars_config::encrypted)
+                .unwrap_or(&value);
+            match key.decrypt(encrypted) {
+                Ok(plaintext) => println!("{plaintext}"),
+                Err(err) => println!("{err}"),
+            }
+        }
+        SubCommand::Rekey { new_key_file } => {
+            let key = match master_key() {
+                Some(key) => key,
+                None => return,
+            };
+            let new_key = match MasterKey::from_file(&new_key_file) {
+                Ok(new_key) => new_key,
+                Err(err) => {
+                    println!("{err}");
+                    return;
+                }
+            };
+            match mars_entity::encryption::rekey(&db, &key, &new_key, "cli").await {
+                Ok(count) => println!(
+                    "rekeyed auth params of {count} subprojects from key {} to {}, restart mars-rover with new key",
+                    key.id(),
+                    new_key.id()
+                ),
+                Err(err) => println!("unable to rekey, nothing was changed: {err}"),
+            }
+        }
         SubCommand::Dump => {
             let mut living_projects = MultipleProjects(Default::default());
             for project in mars_entity::project::Entity::find()
@@ -140,6 +915,11 @@ async fn main() {
                 let mut current_project = ProjectDTO {
                     subprojects: Default::default(),
                     needs_auth: project.needs_auth,
+                    token_sources: project.token_sources.map(|x| x.0),
+                    ip_rules: project.ip_rules.map(|x| x.0),
+                    signature: project.signature.map(|x| x.0),
+                    defaults: project.defaults.map(|x| x.0),
+                    auth_profiles: project.auth_profiles.map(|x| x.0),
                 };
                 for service in mars_entity::subproject::Entity::find()
                     .filter(mars_entity::subproject::Column::ProjectId.eq(project.id))
@@ -147,13 +927,15 @@ async fn main() {
                     .await
                     .expect("unable to make query")
                 {
-                    let service_config = ServiceConfig {
-                        url: service.url,
-                        method: service.method.0,
+                    // empty url and null auth are inherited from defaults
+                    let service_config = PartialServiceConfig {
+                        url: Some(service.url).filter(|x| !x.is_empty()),
+                        method: Some(service.method.0),
                         query_params: service.query_params.0,
                         headers: service.headers.0,
-                        auth: service.auth.0,
-                        params: service.params.0,
+                        auth: service.auth.map(|x| x.0),
+                        auth_profile: service.auth_profile,
+                        params: Some(service.params.0),
                     };
                     current_project
                         .subprojects
@@ -164,37 +946,47 @@ async fn main() {
 
             println!("config of  projects and its services  \n\nXXXXXXXXXXXXXXXXXXXXXXX\n\n");
 
+            let dump = match format.to_string(&living_projects) {
+                Ok(dump) => dump,
+                Err(err) => {
+                    println!("{err}");
+                    return;
+                }
+            };
             match std::fs::OpenOptions::new()
-                .create(true)
                 .create_new(true)
                 .write(true)
                 .open(args.file)
             {
                 Err(_out) => {
-                    println!(
-                        "failed to write to file {_out}, dumping here \n\n{}",
-                        serde_json::to_string_pretty(&living_projects).expect("impossible to fail")
-                    );
+                    println!("failed to write to file {_out}, dumping here \n\n{dump}");
 
                     println!(
                         "\n\nXXXXXXXXXXXXXXXXXXXXXXX\n\n
                         done"
                     );
                 }
-                Ok(file) => serde_json::to_writer_pretty(file, &living_projects)
-                    .expect("impossible to fail"),
+                Ok(mut file) => {
+                    std::io::Write::write_all(&mut file, dump.as_bytes()).expect("unable to write file")
+                }
             };
         }
         SubCommand::Load => {
             let config = std::fs::read_to_string(args.file).expect("unable to open file");
             println!("able to read file");
-            let config: MultipleProjects = json5::from_str(&config).expect("unable to parse");
+            let config: MultipleProjects = format.parse(&config).expect("unable to parse");
             let mut failed = MultipleProjects(Default::default());
             for (index, project) in config.0.into_iter() {
                 let mut failed_project = ProjectDTO {
                     subprojects: Default::default(),
                     needs_auth: project.needs_auth,
+                    token_sources: project.token_sources.clone(),
+                    ip_rules: project.ip_rules.clone(),
+                    signature: project.signature.clone(),
+                    defaults: project.defaults.clone(),
+                    auth_profiles: project.auth_profiles.clone(),
                 };
+                let default_method = project.defaults.as_ref().and_then(|x| x.method);
                 let project_id = match mars_entity::project::Entity::find()
                     .filter(mars_entity::project::Column::Index.eq(index.clone()))
                     .one(&db)
@@ -207,12 +999,34 @@ async fn main() {
                             id: sea_orm::ActiveValue::NotSet,
                             index: sea_orm::ActiveValue::Set(index.clone()),
                             needs_auth: sea_orm::ActiveValue::Set(project.needs_auth),
+                            token_sources: sea_orm::ActiveValue::Set(
+                                project
+                                    .token_sources
+                                    .clone()
+                                    .map(mars_entity::project::TokenSources),
+                            ),
+                            ip_rules: sea_orm::ActiveValue::Set(
+                                project.ip_rules.clone().map(mars_entity::project::IpRules),
+                            ),
+                            signature: sea_orm::ActiveValue::Set(
+                                project.signature.clone().map(mars_entity::project::Signature),
+                            ),
+                            defaults: sea_orm::ActiveValue::Set(
+                                project.defaults.clone().map(mars_entity::project::Defaults),
+                            ),
+                            auth_profiles: sea_orm::ActiveValue::Set(
+                                project
+                                    .auth_profiles
+                                    .clone()
+                                    .map(mars_entity::project::AuthProfiles),
+                            ),
                         };
-                        let res = mars_entity::project::Entity::insert(proect_active_model)
-                            .exec(&db)
+                        let res = proect_active_model
+                            .insert(&db)
                             .await
                             .expect("unable to insert");
-                        res.last_insert_id
+                        audit(&db, Change::new("project", &index, None, Some(&res))).await;
+                        res.id
                     }
                 };
 
@@ -230,12 +1044,19 @@ async fn main() {
                                 .subprojects
                                 .insert(service_index.clone(), service_config);
                         }
+                        None if service_config.method.or(default_method).is_none() => {
+                            println!("for project {index} subproject {service_index} method is not configured, not inserting it");
+                            failed_project
+                                .subprojects
+                                .insert(service_index.clone(), service_config);
+                        }
                         None => {
+                            let method = service_config.method.or(default_method).unwrap();
                             let pear = mars_entity::subproject::ActiveModel {
                                 project_id: sea_orm::ActiveValue::Set(project_id),
                                 id: sea_orm::ActiveValue::NotSet,
                                 method: sea_orm::ActiveValue::Set(mars_entity::subproject::Method(
-                                    service_config.method,
+                                    method,
                                 )),
                                 query_params: sea_orm::ActiveValue::Set(
                                     mars_entity::subproject::QueryParams(
@@ -245,22 +1066,35 @@ async fn main() {
                                 headers: sea_orm::ActiveValue::Set(
                                     mars_entity::subproject::Headers(service_config.headers),
                                 ),
-                                auth: sea_orm::ActiveValue::Set(mars_entity::subproject::Auth(
-                                    service_config.auth,
-                                )),
+                                // auth left out and empty url are inherited from defaults
+                                auth: sea_orm::ActiveValue::Set(
+                                    service_config.auth.map(mars_entity::subproject::Auth),
+                                ),
                                 params: sea_orm::ActiveValue::Set(
-                                    mars_entity::subproject::GeneralParams(service_config.params),
+                                    mars_entity::subproject::GeneralParams(
+                                        service_config.params.unwrap_or_default(),
+                                    ),
                                 ),
                                 index: sea_orm::ActiveValue::Set(service_index.clone()),
-                                url: sea_orm::ActiveValue::Set(service_config.url),
+                                url: sea_orm::ActiveValue::Set(
+                                    service_config.url.unwrap_or_default(),
+                                ),
+                                auth_profile: sea_orm::ActiveValue::Set(
+                                    service_config.auth_profile,
+                                ),
                             };
-                            if let Err(err) = mars_entity::subproject::Entity::insert(pear)
-                                .exec(&db)
-                                .await
-                            {
-                                println!(
+                            match pear.insert(&db).await {
+                                Ok(res) => {
+                                    mars_entity::subproject_version::record(&db, None, &res, "cli")
+                                        .await
+                                        .expect("unable to save subproject version");
+                                    let key = format!("{index}/{service_index}");
+                                    audit(&db, Change::new("subproject", key, None, Some(&res)))
+                                        .await;
+                                }
+                                Err(err) => println!(
                                     "unable to insert sub project {service_index} for project {index}, error {err}"
-                                );
+                                ),
                             }
                         }
                     };
@@ -312,6 +1146,40 @@ async fn main() {
                 schema.create_table_from_entity(mars_entity::authtoken::Entity);
             let result = db.execute(db.get_database_backend().build(&stmt)).await;
             println!("created auth_tokens {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::group::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created group {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::group_member::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created group_member {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::group_project::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created group_project {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::usage::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created usage {:?}", result);
+            let result = db
+                .execute(db.get_database_backend().build(&mars_entity::usage::unique_index()))
+                .await;
+            println!("created usage index {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::audit_log::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created audit_log {:?}", result);
+
+            let stmt: TableCreateStatement =
+                schema.create_table_from_entity(mars_entity::subproject_version::Entity);
+            let result = db.execute(db.get_database_backend().build(&stmt)).await;
+            println!("created subproject_versions {:?}", result);
         }
     }
 }
@@ -0,0 +1,215 @@
+//! Rotation of the master key encrypted auth params of subprojects are kept with.
+//!
+//! Saved versions of subproject configs hold encrypted params as well, they are rekeyed along so
+//! older versions can still be rolled back to, as are auth profiles and defaults of projects.
+use mars_config::{map_encrypted, MarsAuth, MarsError, MasterKey};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, DatabaseConnection, DbErr, EntityTrait, QuerySelect,
+    TransactionTrait,
+};
+
+use super::audit_log::{self, Change};
+use super::{project, subproject, subproject_version};
+
+/// `auth` with its encrypted params encrypted by `new` instead of `old`, `None` when it has none
+fn rekey_auth(
+    auth: &MarsAuth,
+    old: &MasterKey,
+    new: &MasterKey,
+) -> Result<Option<MarsAuth>, DbErr> {
+    let (params, rekeyed) = map_encrypted(&auth.get_params(), &mut |x| old.rekey(x, new))
+        .map_err(|err: MarsError| DbErr::Custom(err.to_string().trim_end().to_string()))?;
+    Ok((rekeyed > 0).then(|| MarsAuth::new(params, *auth.auth_type())))
+}
+
+/// encrypts data keys of encrypted auth params of every subproject, of their saved versions and of
+/// project auth profiles and defaults, with `new` instead of `old`. nothing is changed unless
+/// every one of them is encrypted by `old`. rows are read within the transaction they are
+/// written in, locked where the database can, so that no write made meanwhile is lost. returns
+/// number of subprojects rekeyed
+pub async fn rekey(
+    db: &DatabaseConnection,
+    old: &MasterKey,
+    new: &MasterKey,
+    actor: &str,
+) -> Result<usize, DbErr> {
+    let txn = db.begin().await?;
+    let projects = project::Entity::find().lock_exclusive().all(&txn).await?;
+    let subprojects = subproject::Entity::find()
+        .lock_exclusive()
+        .all(&txn)
+        .await?;
+    let versions = subproject_version::Entity::find()
+        .lock_exclusive()
+        .all(&txn)
+        .await?;
+
+    let mut count = 0;
+    for subproject in subprojects {
+        let auth = match &subproject.auth {
+            Some(auth) => rekey_auth(&auth.0, old, new)?,
+            None => None,
+        };
+        let auth = match auth {
+            Some(auth) => auth,
+            None => continue,
+        };
+        let mut model: subproject::ActiveModel = subproject.clone().into();
+        model.auth = Set(Some(subproject::Auth(auth)));
+        let model = model.update(&txn).await?;
+        let project = projects
+            .iter()
+            .find(|x| x.id == model.project_id)
+            .map(|x| x.index.clone())
+            .unwrap_or_else(|| model.project_id.to_string());
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new(
+                "subproject",
+                format!("{project}/{}", model.index),
+                Some(&subproject),
+                Some(&model),
+            ),
+        )
+        .await?;
+        count += 1;
+    }
+    for project in projects {
+        let mut rekeyed = false;
+        let mut profiles = project.auth_profiles.clone();
+        for auth in profiles.iter_mut().flat_map(|x| x.0.values_mut()) {
+            if let Some(new_auth) = rekey_auth(auth, old, new)? {
+                *auth = new_auth;
+                rekeyed = true;
+            }
+        }
+        let mut defaults = project.defaults.clone();
+        if let Some(auth) = defaults.as_mut().and_then(|x| x.0.auth.as_mut()) {
+            if let Some(new_auth) = rekey_auth(auth, old, new)? {
+                *auth = new_auth;
+                rekeyed = true;
+            }
+        }
+        if !rekeyed {
+            continue;
+        }
+        let mut model: project::ActiveModel = project.clone().into();
+        model.auth_profiles = Set(profiles);
+        model.defaults = Set(defaults);
+        let model = model.update(&txn).await?;
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("project", &model.index, Some(&project), Some(&model)),
+        )
+        .await?;
+    }
+    for version in versions {
+        let mut config = version.config.0.clone();
+        config.auth = match rekey_auth(&config.auth, old, new)? {
+            Some(auth) => auth,
+            None => continue,
+        };
+        let mut model: subproject_version::ActiveModel = version.into();
+        model.config = Set(subproject_version::Config(config));
+        model.update(&txn).await?;
+    }
+    txn.commit().await?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use mars_config::{AuthType, MarsAuth, MasterKey};
+    use sea_orm::{ActiveModelTrait, ConnectionTrait, Database, EntityTrait, Schema, Set};
+    use serde_json::json;
+
+    use super::rekey;
+    use crate::{audit_log, project, subproject, subproject_version};
+
+    #[tokio::test]
+    async fn test_rekey() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        for statement in [
+            schema.create_table_from_entity(project::Entity),
+            schema.create_table_from_entity(subproject::Entity),
+            schema.create_table_from_entity(subproject_version::Entity),
+            schema.create_table_from_entity(audit_log::Entity),
+        ] {
+            db.execute(db.get_database_backend().build(&statement))
+                .await
+                .unwrap();
+        }
+        let (old, new) = (
+            MasterKey::parse(&MasterKey::generate()).unwrap(),
+            MasterKey::parse(&MasterKey::generate()).unwrap(),
+        );
+        let auth = |params| MarsAuth::new(params, AuthType::BasicAuth);
+        project::ActiveModel {
+            index: Set("aviko".to_string()),
+            needs_auth: Set(true),
+            auth_profiles: Set(Some(project::AuthProfiles(
+                [(
+                    "httpbin".to_string(),
+                    auth(json!({"username": "u", "password": old.encrypt("hunter2")})),
+                )]
+                .into(),
+            ))),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+        let model = subproject::ActiveModel {
+            project_id: Set(1),
+            index: Set("json".to_string()),
+            url: Set("https://example.com".to_string()),
+            method: Set(subproject::Method(mars_config::Method::ANY)),
+            query_params: Set(subproject::QueryParams(vec![])),
+            headers: Set(subproject::Headers(vec![])),
+            auth: Set(Some(subproject::Auth(auth(
+                json!({"username": "u", "password": old.encrypt("hunter2")}),
+            )))),
+            params: Set(subproject::GeneralParams(Default::default())),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+        subproject_version::record(&db, None, &model, "cli")
+            .await
+            .unwrap();
+
+        assert_eq!(rekey(&db, &old, &new, "cli").await.unwrap(), 1);
+        let model = subproject::Entity::find_by_id(1)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        let password = model.auth.clone().unwrap().0.get_params()["password"]["$enc"].clone();
+        assert_eq!(new.decrypt(password.as_str().unwrap()).unwrap(), "hunter2");
+        let version = subproject_version::find(&db, 1, 1).await.unwrap().unwrap();
+        let password = version.config.0.auth.get_params()["password"]["$enc"].clone();
+        assert_eq!(new.decrypt(password.as_str().unwrap()).unwrap(), "hunter2");
+        let project = project::Entity::find_by_id(1)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        let password =
+            project.auth_profiles.unwrap().0["httpbin"].get_params()["password"]["$enc"].clone();
+        assert_eq!(new.decrypt(password.as_str().unwrap()).unwrap(), "hunter2");
+        assert_eq!(audit_log::Entity::find().all(&db).await.unwrap().len(), 2);
+
+        // values are no longer encrypted by old key, nothing is changed
+        assert!(rekey(&db, &old, &new, "cli").await.is_err());
+        let unchanged = subproject::Entity::find_by_id(1)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(unchanged, model);
+    }
+}
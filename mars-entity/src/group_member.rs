@@ -0,0 +1,55 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "group_members")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub group_id: i32,
+    pub user_id: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Group,
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Group => Entity::belongs_to(super::group::Entity)
+                .from(Column::GroupId)
+                .to(super::group::Column::Id)
+                .into(),
+            Self::User => Entity::belongs_to(super::user::Entity)
+                .from(Column::UserId)
+                .to(super::user::Column::Id)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::group::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Group.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::group_project::Entity> for Entity {
+    fn to() -> RelationDef {
+        // not part of `Relation`, so that no foreign key is derived from it
+        Entity::belongs_to(super::group_project::Entity)
+            .from(Column::GroupId)
+            .to(super::group_project::Column::GroupId)
+            .into()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
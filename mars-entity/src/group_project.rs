@@ -0,0 +1,61 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub use super::http_params::Policy;
+
+/// project grant of a group, counterpart of `user_projects` for groups
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "group_projects")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub group_id: i32,
+    pub project_id: i32,
+    pub permissions: i32,
+    /// restricts grant to subprojects, paths and methods. `None` allows whole project
+    pub policy: Option<Policy>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Group,
+    Project,
+    GroupMember,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Group => Entity::belongs_to(super::group::Entity)
+                .from(Column::GroupId)
+                .to(super::group::Column::Id)
+                .into(),
+            Self::Project => Entity::belongs_to(super::project::Entity)
+                .from(Column::ProjectId)
+                .to(super::project::Column::Id)
+                .into(),
+            // members share group of the grant, `has_many` keeps it out of foreign keys
+            Self::GroupMember => Entity::has_many(super::group_member::Entity).into(),
+        }
+    }
+}
+
+impl Related<super::group::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Group.def()
+    }
+}
+
+impl Related<super::project::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Project.def()
+    }
+}
+
+impl Related<super::group_member::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GroupMember.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
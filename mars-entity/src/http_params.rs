@@ -1,4 +1,4 @@
-pub use mars_config::{Action, Header, UrlParam as QueryParam};
+pub use mars_config::{AccessPolicy, Action, Header, TokenSource, UrlParam as QueryParam};
 use sea_orm::{self, FromJsonQueryResult};
 use serde::{Deserialize, Serialize};
 
@@ -19,3 +19,35 @@ pub struct GeneralParams(pub mars_config::GeneralParams);
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
 
 pub struct Method(pub mars_config::Method);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct TokenSources(pub Vec<TokenSource>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct Policy(pub AccessPolicy);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct Quota(pub mars_config::Quota);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct IpRules(pub mars_config::IpRules);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct SourceCidrs(pub Vec<mars_config::IpNet>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct Signature(pub mars_config::SignatureScheme);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct Defaults(pub mars_config::PartialServiceConfig);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+
+pub struct AuthProfiles(pub mars_config::AuthProfiles);
@@ -1,7 +1,14 @@
+pub mod audit_log;
 pub mod authtoken;
+pub mod encryption;
+pub mod group;
+pub mod group_member;
+pub mod group_project;
 pub mod http_params;
 pub mod project;
 pub mod subproject;
+pub mod subproject_version;
+pub mod usage;
 pub mod user;
 pub mod user_project;
 pub mod utils;
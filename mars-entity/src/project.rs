@@ -1,6 +1,8 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+pub use super::http_params::{AuthProfiles, Defaults, IpRules, Signature, TokenSources};
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "project")]
 pub struct Model {
@@ -8,6 +10,15 @@ pub struct Model {
     pub id: i32,
     pub index: String,
     pub needs_auth: bool,
+    pub token_sources: Option<TokenSources>,
+    /// source addresses the project accepts requests from, any when `None`
+    pub ip_rules: Option<IpRules>,
+    /// how inbound requests are signed, when project requires signed requests
+    pub signature: Option<Signature>,
+    /// config subprojects inherit, see [`subproject::Model::inherited_config`](super::subproject::Model::inherited_config)
+    pub defaults: Option<Defaults>,
+    /// auth configs subprojects and defaults refer to by `auth_profile`
+    pub auth_profiles: Option<AuthProfiles>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -44,6 +55,11 @@ mod test {
             id: sea_orm::ActiveValue::Set(1),
             index: sea_orm::ActiveValue::Set("test".to_owned()),
             needs_auth: sea_orm::ActiveValue::Set(false),
+            token_sources: sea_orm::ActiveValue::Set(None),
+            ip_rules: sea_orm::ActiveValue::Set(None),
+            signature: sea_orm::ActiveValue::Set(None),
+            defaults: sea_orm::ActiveValue::Set(None),
+            auth_profiles: sea_orm::ActiveValue::Set(None),
         };
         let res = Entity::insert(pear).exec(&db).await.unwrap();
 
@@ -12,10 +12,14 @@ pub struct Model {
     pub method: Method,
     pub query_params: QueryParams,
     pub headers: Headers,
-    pub auth: Auth,
+    /// null to inherit auth of `auth_profile` or of project defaults, `no_auth` is no auth at all
+    pub auth: Option<Auth>,
     pub params: GeneralParams,
     pub index: String,
+    /// url, or path relative to url of project defaults, empty to take it as it is
     pub url: String,
+    /// name of auth profile of project, used when `auth` is null
+    pub auth_profile: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -23,6 +27,72 @@ pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
+impl Model {
+    /// config the subproject's service is built from, `no_auth` when auth is inherited
+    pub fn service_config(&self) -> mars_config::ServiceConfig {
+        mars_config::ServiceConfig {
+            url: self.url.clone(),
+            method: self.method.0,
+            query_params: self.query_params.0.clone(),
+            headers: self.headers.0.clone(),
+            auth: self.auth.clone().map(|x| x.0).unwrap_or_default(),
+            params: self.params.0.clone(),
+        }
+    }
+
+    /// config the subproject's service is built from, with what it leaves out taken from
+    /// defaults of `project`, see [`inherit`]
+    pub fn inherited_config(
+        &self,
+        project: &super::project::Model,
+    ) -> Result<mars_config::ServiceConfig, mars_config::MarsError> {
+        inherit(
+            self.service_config(),
+            self.auth.is_none(),
+            self.auth_profile.clone(),
+            project,
+        )
+    }
+}
+
+/// `config` of a subproject with what it leaves out taken from defaults of `project`. empty url
+/// is left out, as is auth of `config` when `inherits_auth`, they are inherited
+pub fn inherit(
+    config: mars_config::ServiceConfig,
+    inherits_auth: bool,
+    auth_profile: Option<String>,
+    project: &super::project::Model,
+) -> Result<mars_config::ServiceConfig, mars_config::MarsError> {
+    let config = mars_config::PartialServiceConfig {
+        url: Some(config.url).filter(|x| !x.is_empty()),
+        method: Some(config.method),
+        query_params: config.query_params,
+        headers: config.headers,
+        auth: Some(config.auth).filter(|_| !inherits_auth),
+        auth_profile,
+        params: Some(config.params),
+    };
+    let defaults = project.defaults.clone().map(|x| x.0).unwrap_or_default();
+    let profiles = project
+        .auth_profiles
+        .clone()
+        .map(|x| x.0)
+        .unwrap_or_default();
+    config.inherit(&defaults, &profiles)
+}
+
+impl ActiveModel {
+    /// auth of `config` is left out when `inherits_auth`
+    pub fn set_service_config(&mut self, config: mars_config::ServiceConfig, inherits_auth: bool) {
+        self.url = sea_orm::ActiveValue::Set(config.url);
+        self.method = sea_orm::ActiveValue::Set(Method(config.method));
+        self.query_params = sea_orm::ActiveValue::Set(QueryParams(config.query_params));
+        self.headers = sea_orm::ActiveValue::Set(Headers(config.headers));
+        self.auth = sea_orm::ActiveValue::Set(Some(Auth(config.auth)).filter(|_| !inherits_auth));
+        self.params = sea_orm::ActiveValue::Set(GeneralParams(config.params));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use sea_orm::{sea_query::TableCreateStatement, ConnectionTrait, Database, Schema};
@@ -66,18 +136,19 @@ mod test {
                 value: "headervalue".to_owned(),
                 action: Action::Add,
             }])),
-            auth: sea_orm::ActiveValue::Set(Auth(mars_config::MarsAuth::new(
+            auth: sea_orm::ActiveValue::Set(Some(Auth(mars_config::MarsAuth::new(
                 json!([{
                     "key": "Authorization",
                     "value": "Bearer hai"
                 }]),
                 mars_config::AuthType::HeaderAuth,
-            ))),
+            )))),
             params: sea_orm::ActiveValue::Set(GeneralParams(mars_config::GeneralParams::new(
                 json!({}),
             ))),
             index: sea_orm::ActiveValue::Set("userinput".to_owned()),
             url: sea_orm::ActiveValue::Set("https://httpbin.org/".to_owned()),
+            auth_profile: sea_orm::ActiveValue::Set(None),
         };
         let res = Entity::insert(pear).exec(&db).await.unwrap();
 
@@ -90,4 +161,74 @@ mod test {
         println!("Result: {:?}", result);
         println!("Inserted: last_insert_id = {}\n", res.last_insert_id);
     }
+
+    #[tokio::test]
+    async fn test_inherit_auth() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        db.execute(
+            db.get_database_backend()
+                .build(&schema.create_table_from_entity(Entity)),
+        )
+        .await
+        .unwrap();
+        let basic = |username: &str| {
+            mars_config::MarsAuth::new(
+                json!({"username": username, "password": "p"}),
+                mars_config::AuthType::BasicAuth,
+            )
+        };
+        let project = crate::project::Model {
+            id: 1,
+            index: "aviko".to_string(),
+            needs_auth: true,
+            token_sources: None,
+            ip_rules: None,
+            signature: None,
+            defaults: Some(crate::project::Defaults(
+                mars_config::PartialServiceConfig {
+                    url: Some("https://httpbin.org".to_string()),
+                    auth: Some(basic("default")),
+                    ..Default::default()
+                },
+            )),
+            auth_profiles: Some(crate::project::AuthProfiles(
+                [("profile".to_string(), basic("profile"))].into(),
+            )),
+        };
+        let insert = |index: &str, auth: Option<mars_config::MarsAuth>, profile: Option<&str>| {
+            let mut model = ActiveModel {
+                id: sea_orm::ActiveValue::NotSet,
+                project_id: sea_orm::ActiveValue::Set(1),
+                index: sea_orm::ActiveValue::Set(index.to_string()),
+                auth_profile: sea_orm::ActiveValue::Set(profile.map(str::to_string)),
+                ..Default::default()
+            };
+            let config = mars_config::ServiceConfig {
+                url: "json".to_string(),
+                method: mars_config::Method::GET,
+                query_params: vec![],
+                headers: vec![],
+                auth: auth.clone().unwrap_or_default(),
+                params: Default::default(),
+            };
+            model.set_service_config(config, auth.is_none());
+            model.insert(&db)
+        };
+        insert("inherits", None, None).await.unwrap();
+        insert("profile", None, Some("profile")).await.unwrap();
+        insert("no_auth", Some(Default::default()), None)
+            .await
+            .unwrap();
+
+        let stored = Entity::find().all(&db).await.unwrap();
+        let auth = |index: &str| {
+            let model = stored.iter().find(|x| x.index == index).unwrap();
+            model.inherited_config(&project).unwrap().auth
+        };
+        assert_eq!(auth("inherits"), basic("default"));
+        assert_eq!(auth("profile"), basic("profile"));
+        // explicit no_auth opts out of project defaults
+        assert_eq!(auth("no_auth"), mars_config::MarsAuth::default());
+    }
 }
@@ -0,0 +1,170 @@
+//! Revisions of subproject configs.
+//!
+//! Every saved config of a subproject is kept with a version counting up from 1. Rolling back
+//! restores config of an older version as a new version, so history only ever grows.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sea_orm::{
+    entity::prelude::*, ActiveValue::NotSet, ActiveValue::Set, ConnectionTrait,
+    FromJsonQueryResult, QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{audit_log, subproject};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct Config(pub mars_config::ServiceConfig);
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "subproject_versions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub subproject_id: i32,
+    /// counts from 1 per subproject
+    pub version: i32,
+    /// index subproject had with this config
+    pub index: String,
+    pub config: Config,
+    /// whether subproject inherited auth, auth of `config` is `no_auth` then
+    pub inherits_auth: bool,
+    /// auth profile subproject had with this config
+    pub auth_profile: Option<String>,
+    /// unix time in seconds
+    pub at: i64,
+    /// `user:<id>` of an admin, `cli`, or `unknown` for configs saved before history was kept
+    pub actor: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// versions of subproject, oldest first
+pub async fn list<C: ConnectionTrait>(db: &C, subproject_id: i32) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::SubprojectId.eq(subproject_id))
+        .order_by_asc(Column::Version)
+        .all(db)
+        .await
+}
+
+pub async fn find<C: ConnectionTrait>(
+    db: &C,
+    subproject_id: i32,
+    version: i32,
+) -> Result<Option<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::SubprojectId.eq(subproject_id))
+        .filter(Column::Version.eq(version))
+        .one(db)
+        .await
+}
+
+/// keeps config of `subproject` as its next version. `previous` is the config it replaced,
+/// which is kept as version 1 when subproject has no history yet
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    previous: Option<&subproject::Model>,
+    subproject: &subproject::Model,
+    actor: &str,
+) -> Result<Model, DbErr> {
+    let latest = Entity::find()
+        .filter(Column::SubprojectId.eq(subproject.id))
+        .order_by_desc(Column::Version)
+        .one(db)
+        .await?
+        .map(|x| x.version);
+    let mut next = latest.unwrap_or_default() + 1;
+    if let (None, Some(previous)) = (latest, previous) {
+        insert(db, previous, next, "unknown").await?;
+        next += 1;
+    }
+    insert(db, subproject, next, actor).await
+}
+
+async fn insert<C: ConnectionTrait>(
+    db: &C,
+    subproject: &subproject::Model,
+    version: i32,
+    actor: &str,
+) -> Result<Model, DbErr> {
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default();
+    ActiveModel {
+        id: NotSet,
+        subproject_id: Set(subproject.id),
+        version: Set(version),
+        index: Set(subproject.index.clone()),
+        config: Set(Config(subproject.service_config())),
+        inherits_auth: Set(subproject.auth.is_none()),
+        auth_profile: Set(subproject.auth_profile.clone()),
+        at: Set(at),
+        actor: Set(actor.to_string()),
+    }
+    .insert(db)
+    .await
+}
+
+/// replaces config and auth profile of `subproject` with those of `version`, keeping its index.
+/// rollback is kept as a new version and in the audit log
+pub async fn rollback<C: ConnectionTrait>(
+    db: &C,
+    subproject: subproject::Model,
+    version: &Model,
+    actor: &str,
+) -> Result<(subproject::Model, Model), DbErr> {
+    let mut model: subproject::ActiveModel = subproject.clone().into();
+    model.set_service_config(version.config.0.clone(), version.inherits_auth);
+    model.auth_profile = Set(version.auth_profile.clone());
+    let model = model.update(db).await?;
+    let restored = record(db, Some(&subproject), &model, actor).await?;
+    let key = format!(
+        "{}/{}",
+        project_index(db, model.project_id).await?,
+        model.index
+    );
+    audit_log::record(
+        db,
+        actor,
+        audit_log::Change::new("subproject", key, Some(&subproject), Some(&model)),
+    )
+    .await?;
+    Ok((model, restored))
+}
+
+async fn project_index<C: ConnectionTrait>(db: &C, project_id: i32) -> Result<String, DbErr> {
+    Ok(super::project::Entity::find_by_id(project_id)
+        .one(db)
+        .await?
+        .map(|x| x.index)
+        .unwrap_or_else(|| project_id.to_string()))
+}
+
+fn snapshot(version: &Model) -> Value {
+    let mut snapshot = json!(version.config.0);
+    snapshot["index"] = json!(version.index);
+    snapshot["auth_profile"] = json!(version.auth_profile);
+    if version.inherits_auth {
+        snapshot["auth"] = Value::Null;
+    }
+    snapshot
+}
+
+/// `{"path": .., "from": .., "to": ..}` of every field that differs, secrets are redacted
+pub fn diff(from: &Model, to: &Model) -> Vec<Value> {
+    let (from, to) = (snapshot(from), snapshot(to));
+    let changes = audit_log::changes(&from, &to);
+    let (from, to) = (audit_log::redact(from), audit_log::redact(to));
+    changes
+        .into_iter()
+        .map(|path| {
+            let pointer = format!("/{}", path.replace('.', "/"));
+            json!({"path": path, "from": from.pointer(&pointer), "to": to.pointer(&pointer)})
+        })
+        .collect()
+}
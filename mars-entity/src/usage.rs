@@ -0,0 +1,32 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::{Index, IndexCreateStatement};
+
+/// number of requests a principal (`token:<id>` or `user:<id>`) made within a period
+/// (`2022-10-18` for a day, `2022-10` for a month)
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "usage")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub principal: String,
+    pub period: String,
+    pub count: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// unique index of `(principal, period)`, replicas add to a count in place with an upsert, so
+/// neither inserts a row of its own
+pub fn unique_index() -> IndexCreateStatement {
+    Index::create()
+        .name("idx-usage-principal-period")
+        .table(Entity)
+        .col(Column::Principal)
+        .col(Column::Period)
+        .unique()
+        .if_not_exists()
+        .to_owned()
+}
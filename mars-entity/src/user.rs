@@ -1,6 +1,9 @@
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+pub use super::http_params::Quota;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "users")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -8,6 +11,10 @@ pub struct Model {
     pub user_type: i32,
     pub user_name: String,
     pub user_email: String,
+    /// client certificate identity bound to the user, `sha256:<fingerprint>`, `subject:<dn>` or `san:<name>`
+    pub cert_identity: Option<String>,
+    /// requests allowed to the user across all tokens per day and month
+    pub quota: Option<Quota>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
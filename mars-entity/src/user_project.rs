@@ -1,6 +1,9 @@
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+pub use super::http_params::Policy;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "user_projects")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -8,6 +11,8 @@ pub struct Model {
     pub user_id: i32,
     pub project_id: i32,
     pub permissions: i32,
+    /// restricts grant to subprojects, paths and methods. `None` allows whole project
+    pub policy: Option<Policy>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -28,10 +33,8 @@ impl RelationTrait for Relation {
                 .from(Column::ProjectId)
                 .to(super::project::Column::Id)
                 .into(),
-            Self::AuthToken => Entity::belongs_to(super::authtoken::Entity)
-                .from(Column::UserId)
-                .to(super::authtoken::Column::UserId)
-                .into(),
+            // tokens share user of the grant, `has_many` keeps it out of foreign keys
+            Self::AuthToken => Entity::has_many(super::authtoken::Entity).into(),
         }
     }
 }
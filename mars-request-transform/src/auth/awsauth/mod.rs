@@ -45,6 +45,7 @@ use tower::{Layer, Service};
 pub(crate) struct AwsAuth<S> {
     access_key: String,
     secret_key: String,
+    session_token: Option<String>,
     region: String,
     service_name: String,
     sign_content: bool,
@@ -54,6 +55,8 @@ pub(crate) struct AwsAuth<S> {
 pub(crate) struct AwsAuthLayer {
     access_key: String,
     secret_key: String,
+    /// of temporary credentials, aws secrets engine of vault hands them out
+    session_token: Option<String>,
     region: String,
     service_name: String,
     sign_content: bool,
@@ -66,9 +69,10 @@ impl<S> Layer<S> for AwsAuthLayer {
         AwsAuth {
             access_key: self.access_key.clone(),
             secret_key: self.secret_key.clone(),
+            session_token: self.session_token.clone(),
             region: self.region.clone(),
             service_name: self.service_name.clone(),
-            sign_content: self.sign_content.clone(),
+            sign_content: self.sign_content,
             inner,
         }
     }
@@ -101,10 +105,11 @@ where
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let access_key = self.access_key.clone();
         let secret_key = self.secret_key.clone();
+        let session_token = self.session_token.clone();
         let region = self.region.clone();
         let service_name = self.service_name.clone();
         let (parts, body) = req.into_parts();
-        let sign_content = self.sign_content.clone();
+        let sign_content = self.sign_content;
         let mut orig = self.inner.clone();
         Box::pin(async move {
             let mut settings = SigningSettings::default();
@@ -115,7 +120,8 @@ where
             };
             let body = body::to_bytes(body).await?;
             let sign_body = SignableBody::Bytes(&body);
-            let params = SignparamsBuilder::default();
+            let mut params = SignparamsBuilder::default();
+            params.set_security_token(session_token.as_deref());
             let params = params
                 .access_key(&access_key)
                 .secret_key(&secret_key)
@@ -148,6 +154,8 @@ pub mod service_config {
     struct AwsAuthParams {
         access_key: String,
         secret_key: String,
+        #[serde(default)]
+        session_token: Option<String>,
         region: String,
         service: String,
         #[serde(default)]
@@ -168,6 +176,7 @@ pub mod service_config {
             let aws_auth_layer = AwsAuthLayer {
                 access_key: aws_auth_params.access_key,
                 secret_key: aws_auth_params.secret_key,
+                session_token: aws_auth_params.session_token,
                 region: aws_auth_params.region,
                 service_name: aws_auth_params.service,
                 sign_content: aws_auth_params.sign_content,
@@ -67,7 +67,7 @@ where
         req.headers_mut()
             .append("Authorization", self.authentication.clone());
         let fut = self.inner.call(req);
-        Box::pin(async move { fut.await })
+        Box::pin(fut)
     }
 }
 
@@ -100,10 +100,10 @@ pub mod service_config {
                 basic_auth_params.password.as_str(),
             )
             .map_err(|_| {
-                MarsError::ServiceConfigError(format!(
-                "unable to construct `Authorization: Basic` header from username: {}, password: {}",
-                basic_auth_params.username, basic_auth_params.password
-            ))
+                MarsError::ServiceConfigError(
+                    "unable to construct `Authorization: Basic` header from username and password"
+                        .to_string(),
+                )
             })?;
             Ok(basic_auth_layer)
         }
@@ -68,7 +68,7 @@ where
             HeaderValue::from_str(&format!("Hawk {}", header)).unwrap(),
         );
         let fut = self.inner.call(req);
-        Box::pin(async move { fut.await })
+        Box::pin(fut)
     }
 }
 #[cfg(feature = "config")]
@@ -60,7 +60,7 @@ where
         let mut req = req;
         req.headers_mut().extend(self.header_map.clone());
         let fut = self.inner.call(req);
-        Box::pin(async move { fut.await })
+        Box::pin(fut)
     }
 }
 
@@ -97,8 +97,8 @@ pub mod service_config {
                 })?,
                 HeaderValue::from_str(&header_config.value).map_err(|x| {
                     MarsError::ServiceConfigError(format!(
-                        "unable to crate header value from {} because of {x}",
-                        header_config.value
+                        "unable to crate header value of {} because of {x}",
+                        header_config.key
                     ))
                 })?,
             ))
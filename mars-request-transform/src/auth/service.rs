@@ -47,7 +47,6 @@
 //!
 //! Note: This documentation is auto-generated and may not be up-to-date. Please refer to the source code for the latest documentation.
 use std::error::Error;
-use std::time::Duration;
 
 use http::{Request, Response};
 use hyper::client::HttpConnector;
@@ -88,76 +87,43 @@ pub type ProxyService =
 pub fn get_auth_service(
     service_config: ServiceConfig,
 ) -> Result<ProxyService, mars_config::MarsError> {
-    let timeout = service_config
-        .get_timeout()
-        .map(|x| Duration::from_secs(x as u64))
-        .map(TimeoutLayer::new);
-    let concurrency_limit = service_config
-        .get_concurrency_timeout()
-        .map(|x| x as usize)
-        .map(ConcurrencyLimitLayer::new);
+    // secret references are resolved only for the service being built
+    let service_config = service_config.resolve_secrets()?;
+    let params = service_config.params.parse()?;
+    for warning in params.warnings() {
+        log::warn!("{}", warning);
+    }
+    let timeout = params.timeout().map(TimeoutLayer::new);
+    let concurrency_limit = params.concurrency_limit.map(ConcurrencyLimitLayer::new);
     let (
         jolt_transform_layer,
         xml_transform_layer,
         yaml_transform_layer,
         yaml_to_json_trasnsform_layer,
     ) = if cfg!(feature = "transform") {
-        let jolt_transform_layer = if service_config
-            .get_params(crate::transform::jolt_service_config::TRANSFORM_JSON_TO_JSON_JOLT_REQUEST)
-            .is_some()
-            || service_config
-                .get_params(
-                    crate::transform::jolt_service_config::TRANSFORM_JSON_TO_JSON_JOLT_RESPONSE,
-                )
-                .is_some()
+        let jolt_transform_layer = if params.jolt_request_transform.is_some()
+            || params.jolt_response_transform.is_some()
         {
-            Some(crate::transform::JoltTransformLayer::try_from(
-                &service_config,
-            )?)
+            Some(crate::transform::JoltTransformLayer::try_from(&params)?)
         } else {
             None
         };
-        let xml_transform_layer = if service_config
-            .get_params(crate::transform::xml_service_config::TRANSFORM_XML_TO_JSON_RESPONSE)
-            .is_some()
-            || service_config
-                .get_params(crate::transform::xml_service_config::TRANSFORM_XML_JSON_REQUEST)
-                .is_some()
-        {
-            Some(crate::transform::XmlTransformJsonLayer::try_from(
-                &service_config,
-            )?)
+        let xml_transform_layer = if params.request_xml_to_json || params.response_xml_to_json {
+            Some(crate::transform::XmlTransformJsonLayer::try_from(&params)?)
         } else {
             None
         };
-        let yaml_transform_layer = if service_config
-            .get_params(crate::transform::yaml_service_config::TRANSFORM_YAML_JSON_REQUEST)
-            .is_some()
-            || service_config
-                .get_params(crate::transform::yaml_service_config::TRANSFORM_YAML_TO_JSON_RESPONSE)
-                .is_some()
-        {
-            Some(crate::transform::YamlTransformJsonLayer::try_from(
-                &service_config,
-            )?)
-        } else {
-            None
-        };
-        let json_to_yaml_transform_layer = if service_config
-            .get_params(crate::transform::json_to_yaml_service_config::TRANSFORM_JSON_YAML_REQUEST)
-            .is_some()
-            || service_config
-                .get_params(
-                    crate::transform::json_to_yaml_service_config::TRANSFORM_JSON_TO_YAML_RESPONSE,
-                )
-                .is_some()
-        {
-            Some(crate::transform::JsonTransformYamlLayer::try_from(
-                &service_config,
-            )?)
+        let yaml_transform_layer = if params.request_yaml_to_json || params.response_yaml_to_json {
+            Some(crate::transform::YamlTransformJsonLayer::try_from(&params)?)
         } else {
             None
         };
+        let json_to_yaml_transform_layer =
+            if params.request_json_to_yaml || params.response_json_to_yaml {
+                Some(crate::transform::JsonTransformYamlLayer::try_from(&params)?)
+            } else {
+                None
+            };
         (
             jolt_transform_layer,
             xml_transform_layer,
@@ -178,7 +144,7 @@ pub fn get_auth_service(
             .option_layer(jolt_transform_layer)
             .option_layer(yaml_transform_layer)
             .option_layer(yaml_to_json_trasnsform_layer)
-            .layer(basicauth::BasicAuthLayer::try_from(&service_config).unwrap())
+            .layer(basicauth::BasicAuthLayer::try_from(&service_config)?)
             .service(simple_hyper_https_client())),
         AuthType::HeaderAuth => Ok(ServiceBuilder::new()
             .layer(BoxCloneSyncService::layer())
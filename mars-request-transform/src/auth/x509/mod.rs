@@ -4,11 +4,13 @@ use http::{Request, Response};
 
 use tower::{Layer, Service};
 
+#[allow(unused)]
 #[derive(Clone)]
 pub(crate) struct SslAuth<S> {
     inner: S,
 }
 
+#[allow(unused)]
 struct SslAuthLayer;
 
 impl<S> Layer<S> for SslAuthLayer {
@@ -38,7 +40,7 @@ where
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let fut = self.inner.call(req);
-        Box::pin(async { fut.await })
+        Box::pin(fut)
     }
 }
 
@@ -104,9 +104,10 @@ fn update_headers(
             Action::Pass => {}
         }
     }
-    Ok(for header in HOP_HEADERS.iter() {
+    for header in HOP_HEADERS.iter() {
         headers_mut.remove(header);
-    })
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -229,7 +230,7 @@ where
         match self.get_updated_request(&url.expect("impossible to fail"), &mut req) {
             Ok(_) => {
                 let fut = self.inner.call(req);
-                Box::pin(async move { fut.await })
+                Box::pin(fut)
             }
             Err(error) => {
                 let message = format!("unable to transform request err: `{:?}`", error);
@@ -91,7 +91,7 @@ where
                         }
                     }
                 }
-                Err(error) => return Err(error),
+                Err(error) => Err(error),
             }
         })
     }
@@ -139,25 +139,19 @@ async fn get_transformed_request(
 
 #[cfg(feature = "config")]
 pub mod service_config {
-    use mars_config::{MarsError, ServiceConfig};
+    use mars_config::{MarsError, Params};
     pub use mars_config::{
         TRANSFORM_JSON_TO_JSON_JOLT_REQUEST, TRANSFORM_JSON_TO_JSON_JOLT_RESPONSE,
     };
 
     use super::JoltTransformLayer;
 
-    impl TryFrom<&ServiceConfig> for JoltTransformLayer {
+    impl TryFrom<&Params> for JoltTransformLayer {
         type Error = MarsError;
 
-        fn try_from(value: &ServiceConfig) -> Result<Self, Self::Error> {
-            let request_transform = value
-                .params
-                .get_value(TRANSFORM_JSON_TO_JSON_JOLT_REQUEST)
-                .cloned();
-            let response_transform = value
-                .params
-                .get_value(TRANSFORM_JSON_TO_JSON_JOLT_RESPONSE)
-                .cloned();
+        fn try_from(value: &Params) -> Result<Self, Self::Error> {
+            let request_transform = value.jolt_request_transform.clone();
+            let response_transform = value.jolt_response_transform.clone();
             let request_transform = match request_transform {
                 Some(reqquest_transform) => match serde_json::from_value(reqquest_transform) {
                     Ok(request_transform) => request_transform,
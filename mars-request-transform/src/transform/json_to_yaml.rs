@@ -27,8 +27,8 @@ impl<S> Layer<S> for JsonTransformYamlLayer {
     fn layer(&self, inner: S) -> Self::Service {
         JsonTransformYAML {
             inner,
-            response_transform: self.response_transform.clone(),
-            request_transform: self.request_transform.clone(),
+            response_transform: self.response_transform,
+            request_transform: self.request_transform,
         }
     }
 }
@@ -54,8 +54,8 @@ where
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let response_transfrom = self.response_transform.clone();
-        let request_transform = self.request_transform.clone();
+        let response_transfrom = self.response_transform;
+        let request_transform = self.request_transform;
         let mut original = self.inner.clone();
         Box::pin(async move {
             let req = match get_transformed_request(req, request_transform).await {
@@ -89,7 +89,7 @@ where
                         }
                     }
                 }
-                Err(error) => return Err(error),
+                Err(error) => Err(error),
             }
         })
     }
@@ -135,26 +135,18 @@ async fn get_transformed_request(
 
 #[cfg(feature = "config")]
 pub mod service_config {
-    use mars_config::{MarsError, ServiceConfig};
+    use mars_config::{MarsError, Params};
 
     use super::JsonTransformYamlLayer;
 
     pub use mars_config::{TRANSFORM_JSON_TO_YAML_RESPONSE, TRANSFORM_JSON_YAML_REQUEST};
 
-    impl TryFrom<&ServiceConfig> for JsonTransformYamlLayer {
+    impl TryFrom<&Params> for JsonTransformYamlLayer {
         type Error = MarsError;
 
-        fn try_from(value: &ServiceConfig) -> Result<Self, Self::Error> {
-            let request_transform = value
-                .params
-                .get_value(TRANSFORM_JSON_YAML_REQUEST)
-                .and_then(|x| x.as_bool())
-                .unwrap_or_default();
-            let response_transform = value
-                .params
-                .get_value(TRANSFORM_JSON_TO_YAML_RESPONSE)
-                .and_then(|x| x.as_bool())
-                .unwrap_or_default();
+        fn try_from(value: &Params) -> Result<Self, Self::Error> {
+            let request_transform = value.request_json_to_yaml;
+            let response_transform = value.response_json_to_yaml;
             if !request_transform && !response_transform {
                 return Err(MarsError::ServiceConfigError(
                     "both request_transform and response_transform failed not  avaiabile".into(),
@@ -27,8 +27,8 @@ impl<S> Layer<S> for YamlTransformJsonLayer {
     fn layer(&self, inner: S) -> Self::Service {
         YamlTransformJson {
             inner,
-            response_transform: self.response_transform.clone(),
-            request_transform: self.request_transform.clone(),
+            response_transform: self.response_transform,
+            request_transform: self.request_transform,
         }
     }
 }
@@ -54,8 +54,8 @@ where
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let response_transfrom = self.response_transform.clone();
-        let request_transform = self.request_transform.clone();
+        let response_transfrom = self.response_transform;
+        let request_transform = self.request_transform;
         let mut original = self.inner.clone();
         Box::pin(async move {
             let req = match get_transformed_request(req, request_transform).await {
@@ -89,7 +89,7 @@ where
                         }
                     }
                 }
-                Err(error) => return Err(error),
+                Err(error) => Err(error),
             }
         })
     }
@@ -135,27 +135,19 @@ async fn get_transformed_request(
 
 #[cfg(feature = "config")]
 pub mod service_config {
-    use mars_config::{MarsError, ServiceConfig};
+    use mars_config::{MarsError, Params};
 
     use super::YamlTransformJsonLayer;
 
     pub use mars_config::TRANSFORM_YAML_JSON_REQUEST;
     pub use mars_config::TRANSFORM_YAML_TO_JSON_RESPONSE;
 
-    impl TryFrom<&ServiceConfig> for YamlTransformJsonLayer {
+    impl TryFrom<&Params> for YamlTransformJsonLayer {
         type Error = MarsError;
 
-        fn try_from(value: &ServiceConfig) -> Result<Self, Self::Error> {
-            let request_transform = value
-                .params
-                .get_value(TRANSFORM_YAML_JSON_REQUEST)
-                .and_then(|x| x.as_bool())
-                .unwrap_or_default();
-            let response_transform = value
-                .params
-                .get_value(TRANSFORM_YAML_TO_JSON_RESPONSE)
-                .and_then(|x| x.as_bool())
-                .unwrap_or_default();
+        fn try_from(value: &Params) -> Result<Self, Self::Error> {
+            let request_transform = value.request_yaml_to_json;
+            let response_transform = value.response_yaml_to_json;
             if !request_transform && !response_transform {
                 return Err(MarsError::ServiceConfigError(
                     "both request_transform and response_transform failed not  avaiabile".into(),
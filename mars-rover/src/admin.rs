@@ -0,0 +1,1725 @@
+//! Admin API, create, read, update and delete projects, subprojects, users, grants and tokens
+//! stored in the database.
+//!
+//! Routes live under [`ADMIN_PREFIX`] and take and return json:
+//!
+//! ```text
+//! GET, POST           /_admin/projects
+//! GET, PUT, DELETE    /_admin/projects/<project>
+//! GET, POST           /_admin/projects/<project>/subprojects
+//! GET, PUT, DELETE    /_admin/projects/<project>/subprojects/<subproject>
+//! GET, POST           /_admin/users
+//! GET, PUT, DELETE    /_admin/users/<id>
+//! GET, POST           /_admin/grants
+//! PUT, DELETE         /_admin/grants/<id>
+//! GET, POST           /_admin/tokens
+//! DELETE              /_admin/tokens/<id>
+//! GET                 /_admin/projects/<project>/subprojects/<subproject>/versions
+//! GET                 /_admin/projects/<project>/subprojects/<subproject>/versions/<version>
+//! GET                 /_admin/projects/<project>/subprojects/<subproject>/versions/<a>/diff/<b>
+//! POST                /_admin/projects/<project>/subprojects/<subproject>/versions/<v>/rollback
+//! GET                 /_admin/audit?resource=&resource_key=&actor=&since=&until=&limit=
+//! ```
+//!
+//! Requests carry `Authorization: Bearer user:<token>` of an admin user. Reads need the read
+//! permission of the token, everything else the write permission. Subprojects are built with
+//! [`get_auth_service`] before they are saved, and projects cached by the proxy are dropped
+//! whenever they change.
+//!
+//! Every change to a project, subproject, grant or token is written to [`audit_log`] in the
+//! same transaction as the change itself. Grants are keyed `user:<id>/<project>` there.
+//! Saved subproject configs are kept as [`subproject_version`]s, rolling back to one of them
+//! restores its config under the subproject's current index.
+use std::{error::Error, fmt::Display, str::FromStr, sync::Arc};
+
+use http::{header::AUTHORIZATION, Method, Request, Response};
+use hyper::Body;
+use mars_config::{
+    AccessPolicy, AuthProfiles, IpNet, IpRules, MarsAuth, PartialServiceConfig, Quota,
+    ServiceConfig, SignatureScheme, TokenSource,
+};
+use mars_entity::{
+    audit_log::{self, Change},
+    authtoken::{self, AuthTokenPermissions},
+    group_member, group_project, project, subproject, subproject_version, user, user_project,
+};
+use mars_request_transform::{get_auth_service, response_from_status_message};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::NotSet, ActiveValue::Set, ColumnTrait, Database,
+    DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::project::ProjectManager;
+
+/// path prefix the admin API is served under
+pub const ADMIN_PREFIX: &str = "/_admin";
+
+#[derive(Debug)]
+enum AdminError {
+    BadRequest(String),
+    NotFound(String),
+    Db(DbErr),
+}
+
+impl Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::BadRequest(message) | AdminError::NotFound(message) => {
+                write!(f, "{message}")
+            }
+            AdminError::Db(error) => write!(f, "database error: {error}"),
+        }
+    }
+}
+
+impl From<DbErr> for AdminError {
+    fn from(error: DbErr) -> Self {
+        AdminError::Db(error)
+    }
+}
+
+impl AdminError {
+    fn status(&self) -> u16 {
+        match self {
+            AdminError::BadRequest(_) => 400,
+            AdminError::NotFound(_) => 404,
+            AdminError::Db(_) => 500,
+        }
+    }
+}
+
+type AdminResult = Result<(u16, Value), AdminError>;
+
+fn execute() -> i32 {
+    AuthTokenPermissions::Execute as i32
+}
+
+fn normal_user() -> i32 {
+    user::UserType::Normal as i32
+}
+
+#[derive(Deserialize)]
+struct ProjectBody {
+    index: String,
+    #[serde(default)]
+    needs_auth: bool,
+    #[serde(default)]
+    token_sources: Option<Vec<TokenSource>>,
+    #[serde(default)]
+    ip_rules: Option<IpRules>,
+    #[serde(default)]
+    signature: Option<SignatureScheme>,
+    #[serde(default)]
+    defaults: Option<PartialServiceConfig>,
+    #[serde(default)]
+    auth_profiles: Option<AuthProfiles>,
+}
+
+#[derive(Deserialize)]
+struct SubprojectBody {
+    index: String,
+    #[serde(default)]
+    auth_profile: Option<String>,
+    /// left out to inherit auth of `auth_profile` or project defaults, `no_auth` is no auth
+    #[serde(default)]
+    auth: Option<MarsAuth>,
+    #[serde(flatten)]
+    config: ServiceConfig,
+}
+
+impl SubprojectBody {
+    /// config of subproject along with whether it inherits auth
+    fn service_config(&self) -> (ServiceConfig, bool) {
+        let mut config = self.config.clone();
+        config.auth = self.auth.clone().unwrap_or_default();
+        (config, self.auth.is_none())
+    }
+}
+
+#[derive(Deserialize)]
+struct UserBody {
+    /// next free id when not given
+    #[serde(default)]
+    id: Option<i32>,
+    #[serde(default = "normal_user")]
+    user_type: i32,
+    user_name: String,
+    user_email: String,
+    #[serde(default)]
+    cert_identity: Option<String>,
+    #[serde(default)]
+    quota: Option<Quota>,
+}
+
+#[derive(Deserialize)]
+struct GrantBody {
+    user_id: i32,
+    project: String,
+    #[serde(default = "execute")]
+    permissions: i32,
+    #[serde(default)]
+    policy: Option<AccessPolicy>,
+}
+
+#[derive(Deserialize)]
+struct TokenBody {
+    /// user token, carries grants of the user
+    #[serde(default)]
+    user_id: Option<i32>,
+    /// project token, carries its own policy
+    #[serde(default)]
+    project: Option<String>,
+    /// read and write for admin users, execute otherwise
+    #[serde(default)]
+    permissions: Option<i32>,
+    #[serde(default)]
+    policy: Option<AccessPolicy>,
+    #[serde(default)]
+    quota: Option<Quota>,
+    #[serde(default)]
+    source_cidrs: Vec<IpNet>,
+}
+
+/// filters of audit records, newest `limit` records are returned
+struct AuditQuery {
+    resource: Option<String>,
+    resource_key: Option<String>,
+    actor: Option<String>,
+    /// unix time in seconds
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: u64,
+}
+
+impl FromStr for AuditQuery {
+    type Err = AdminError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let mut audit_query = AuditQuery {
+            resource: None,
+            resource_key: None,
+            actor: None,
+            since: None,
+            until: None,
+            limit: 100,
+        };
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            let number = || {
+                value.parse().map_err(|_| {
+                    AdminError::BadRequest(format!("`{key}` should be a number, not `{value}`"))
+                })
+            };
+            match key.as_ref() {
+                "resource" => audit_query.resource = Some(value.to_string()),
+                "resource_key" => audit_query.resource_key = Some(value.to_string()),
+                "actor" => audit_query.actor = Some(value.to_string()),
+                "since" => audit_query.since = Some(number()?),
+                "until" => audit_query.until = Some(number()?),
+                "limit" => audit_query.limit = number()?.max(0) as u64,
+                _ => {
+                    return Err(AdminError::BadRequest(format!(
+                        "`{key}` is not a filter of audit records"
+                    )))
+                }
+            }
+        }
+        Ok(audit_query)
+    }
+}
+
+/// `AdminApi` serves the admin routes against a database.
+pub struct AdminApi {
+    db_conn: DatabaseConnection,
+    project_manager: Option<Arc<Box<dyn ProjectManager>>>,
+}
+
+impl AdminApi {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        AdminApi {
+            db_conn,
+            project_manager: None,
+        }
+    }
+
+    /// projects cached by `project_manager` are invalidated whenever they are changed
+    pub fn with_project_manager(mut self, project_manager: Arc<Box<dyn ProjectManager>>) -> Self {
+        self.project_manager = Some(project_manager);
+        self
+    }
+
+    /// whether `path` is an admin route
+    pub fn handles(&self, path: &str) -> bool {
+        path == ADMIN_PREFIX || path.starts_with(&format!("{ADMIN_PREFIX}/"))
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Result<Response<Body>, Box<dyn Error>> {
+        let required = if request.method() == Method::GET {
+            AuthTokenPermissions::Read
+        } else {
+            AuthTokenPermissions::Write
+        };
+        let actor = match self.authenticate(&request, required).await {
+            Ok(user) => format!("user:{}", user.id),
+            Err((status, message)) => return response_from_status_message(status, message.into()),
+        };
+        let method = request.method().clone();
+        let path = request.uri().path()[ADMIN_PREFIX.len()..].to_string();
+        let query = request.uri().query().unwrap_or_default().to_string();
+        let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        match self.route(&actor, method, &segments, &query, &body).await {
+            Ok((status, value)) => Ok(Response::builder()
+                .status(status)
+                .header("content-type", "application/json")
+                .header("from-avalanche", "true")
+                .body(Body::from(value.to_string()))?),
+            Err(error) => {
+                if let AdminError::Db(error) = &error {
+                    log::error!("admin request ran into error: {}", error);
+                }
+                response_from_status_message(error.status(), error.to_string())
+            }
+        }
+    }
+
+    async fn route(
+        &self,
+        actor: &str,
+        method: Method,
+        segments: &[&str],
+        query: &str,
+        body: &[u8],
+    ) -> AdminResult {
+        match (method, segments) {
+            (Method::GET, ["projects"]) => self.list_projects().await,
+            (Method::POST, ["projects"]) => self.create_project(actor, parse(body)?).await,
+            (Method::GET, ["projects", index]) => self.get_project(index).await,
+            (Method::PUT, ["projects", index]) => {
+                self.update_project(actor, index, parse(body)?).await
+            }
+            (Method::DELETE, ["projects", index]) => self.delete_project(actor, index).await,
+            (Method::GET, ["projects", index, "subprojects"]) => self.list_subprojects(index).await,
+            (Method::POST, ["projects", index, "subprojects"]) => {
+                self.save_subproject(actor, index, None, parse(body)?).await
+            }
+            (Method::GET, ["projects", index, "subprojects", subproject]) => {
+                self.get_subproject(index, subproject).await
+            }
+            (Method::PUT, ["projects", index, "subprojects", subproject]) => {
+                self.save_subproject(actor, index, Some(subproject), parse(body)?)
+                    .await
+            }
+            (Method::DELETE, ["projects", index, "subprojects", subproject]) => {
+                self.delete_subproject(actor, index, subproject).await
+            }
+            (Method::GET, ["projects", index, "subprojects", subproject, "versions"]) => {
+                self.list_versions(index, subproject).await
+            }
+            (Method::GET, ["projects", index, "subprojects", subproject, "versions", version]) => {
+                let (_, version) = self.find_version(index, subproject, version).await?;
+                Ok((200, redacted(version)))
+            }
+            (
+                Method::GET,
+                ["projects", index, "subprojects", subproject, "versions", from, "diff", to],
+            ) => {
+                let (_, from) = self.find_version(index, subproject, from).await?;
+                let (_, to) = self.find_version(index, subproject, to).await?;
+                Ok((200, json!(subproject_version::diff(&from, &to))))
+            }
+            (
+                Method::POST,
+                ["projects", index, "subprojects", subproject, "versions", version, "rollback"],
+            ) => self.rollback(actor, index, subproject, version).await,
+            (Method::GET, ["users"]) => self.list_users().await,
+            (Method::POST, ["users"]) => self.save_user(None, parse(body)?).await,
+            (Method::GET, ["users", id]) => self.get_user(parse_id(id)?).await,
+            (Method::PUT, ["users", id]) => self.save_user(Some(parse_id(id)?), parse(body)?).await,
+            (Method::DELETE, ["users", id]) => self.delete_user(actor, parse_id(id)?).await,
+            (Method::GET, ["grants"]) => self.list_grants().await,
+            (Method::POST, ["grants"]) => self.save_grant(actor, None, parse(body)?).await,
+            (Method::PUT, ["grants", id]) => {
+                self.save_grant(actor, Some(parse_id(id)?), parse(body)?)
+                    .await
+            }
+            (Method::DELETE, ["grants", id]) => self.delete_grant(actor, parse_id(id)?).await,
+            (Method::GET, ["tokens"]) => self.list_tokens().await,
+            (Method::POST, ["tokens"]) => self.create_token(actor, parse(body)?).await,
+            (Method::DELETE, ["tokens", id]) => self.delete_token(actor, parse_id(id)?).await,
+            (Method::GET, ["audit"]) => self.list_audit(query.parse()?).await,
+            _ => Err(AdminError::NotFound("admin route not found".to_string())),
+        }
+    }
+
+    /// admin user behind bearer token of request, if it holds `required` permission
+    async fn authenticate(
+        &self,
+        request: &Request<Body>,
+        required: AuthTokenPermissions,
+    ) -> Result<user::Model, (u16, &'static str)> {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or((401, "admin token not provided"))?;
+        let uuid = token
+            .trim()
+            .strip_prefix("user:")
+            .and_then(|uuid| uuid::Uuid::from_str(uuid).ok())
+            .ok_or((401, "admin token not valid"))?;
+        let not_valid = |err: DbErr| {
+            log::error!("unable get data {}", err);
+            (401, "admin token not valid")
+        };
+        let token = authtoken::Entity::find()
+            .filter(authtoken::Column::AuthToken.eq(uuid))
+            .one(&self.db_conn)
+            .await
+            .map_err(not_valid)?
+            .ok_or((401, "admin token not valid"))?;
+        let user = match token.user_id {
+            Some(user_id) => user::Entity::find_by_id(user_id)
+                .one(&self.db_conn)
+                .await
+                .map_err(not_valid)?,
+            None => None,
+        }
+        .ok_or((401, "admin token not valid"))?;
+        // tokens of api users never reach admin api, whatever their permissions
+        if !is_admin(&user) {
+            return Err((403, "admin api needs an admin user"));
+        }
+        let required = required as i32;
+        if token.permissions & required != required {
+            return Err((403, "admin token does not have permission"));
+        }
+        Ok(user)
+    }
+
+    async fn invalidate(&self, project_index: &str) {
+        if let Some(project_manager) = &self.project_manager {
+            project_manager.invalidate(project_index).await;
+        }
+    }
+
+    async fn find_project(&self, index: &str) -> Result<project::Model, AdminError> {
+        project::Entity::find()
+            .filter(project::Column::Index.eq(index))
+            .one(&self.db_conn)
+            .await?
+            .ok_or_else(|| AdminError::NotFound(format!("project `{index}` not found")))
+    }
+
+    async fn list_projects(&self) -> AdminResult {
+        let projects = project::Entity::find()
+            .order_by_asc(project::Column::Id)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, redacted(projects)))
+    }
+
+    async fn get_project(&self, index: &str) -> AdminResult {
+        Ok((200, redacted(self.find_project(index).await?)))
+    }
+
+    async fn create_project(&self, actor: &str, body: ProjectBody) -> AdminResult {
+        check_index(&body.index)?;
+        if self.find_project(&body.index).await.is_ok() {
+            return Err(AdminError::BadRequest(format!(
+                "project `{}` already exists",
+                body.index
+            )));
+        }
+        let model = project::ActiveModel {
+            id: NotSet,
+            index: Set(body.index),
+            needs_auth: Set(body.needs_auth),
+            token_sources: Set(body.token_sources.map(project::TokenSources)),
+            ip_rules: Set(body.ip_rules.map(project::IpRules)),
+            signature: Set(body.signature.map(project::Signature)),
+            defaults: Set(body.defaults.map(project::Defaults)),
+            auth_profiles: Set(body.auth_profiles.map(project::AuthProfiles)),
+        };
+        let txn = self.db_conn.begin().await?;
+        let model = model.insert(&txn).await?;
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("project", &model.index, None, Some(&model)),
+        )
+        .await?;
+        txn.commit().await?;
+        Ok((201, redacted(model)))
+    }
+
+    async fn update_project(&self, actor: &str, index: &str, body: ProjectBody) -> AdminResult {
+        let existing = self.find_project(index).await?;
+        check_index(&body.index)?;
+        if body.index != index && self.find_project(&body.index).await.is_ok() {
+            return Err(AdminError::BadRequest(format!(
+                "project `{}` already exists",
+                body.index
+            )));
+        }
+        let mut model: project::ActiveModel = existing.clone().into();
+        model.index = Set(body.index.clone());
+        model.needs_auth = Set(body.needs_auth);
+        model.token_sources = Set(body.token_sources.map(project::TokenSources));
+        model.ip_rules = Set(body.ip_rules.map(project::IpRules));
+        model.signature = Set(body.signature.map(project::Signature));
+        model.defaults = Set(body.defaults.map(project::Defaults));
+        model.auth_profiles = Set(body.auth_profiles.map(project::AuthProfiles));
+        let txn = self.db_conn.begin().await?;
+        let model = model.update(&txn).await?;
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("project", index, Some(&existing), Some(&model)),
+        )
+        .await?;
+        txn.commit().await?;
+        self.invalidate(index).await;
+        self.invalidate(&body.index).await;
+        Ok((200, redacted(model)))
+    }
+
+    /// project goes along with its subprojects, grants and project tokens
+    async fn delete_project(&self, actor: &str, index: &str) -> AdminResult {
+        let project = self.find_project(index).await?;
+        let subprojects = subproject::Entity::find()
+            .filter(subproject::Column::ProjectId.eq(project.id))
+            .all(&self.db_conn)
+            .await?;
+        let grants = user_project::Entity::find()
+            .filter(user_project::Column::ProjectId.eq(project.id))
+            .all(&self.db_conn)
+            .await?;
+        let tokens = authtoken::Entity::find()
+            .filter(authtoken::Column::ProjectId.eq(project.id))
+            .all(&self.db_conn)
+            .await?;
+        let txn = self.db_conn.begin().await?;
+        subproject_version::Entity::delete_many()
+            .filter(
+                subproject_version::Column::SubprojectId
+                    .is_in(subprojects.iter().map(|x| x.id).collect::<Vec<_>>()),
+            )
+            .exec(&txn)
+            .await?;
+        subproject::Entity::delete_many()
+            .filter(subproject::Column::ProjectId.eq(project.id))
+            .exec(&txn)
+            .await?;
+        user_project::Entity::delete_many()
+            .filter(user_project::Column::ProjectId.eq(project.id))
+            .exec(&txn)
+            .await?;
+        group_project::Entity::delete_many()
+            .filter(group_project::Column::ProjectId.eq(project.id))
+            .exec(&txn)
+            .await?;
+        authtoken::Entity::delete_many()
+            .filter(authtoken::Column::ProjectId.eq(project.id))
+            .exec(&txn)
+            .await?;
+        project::Entity::delete_by_id(project.id).exec(&txn).await?;
+        let mut changes = vec![];
+        for subproject in &subprojects {
+            let key = format!("{index}/{}", subproject.index);
+            changes.push(Change::new("subproject", key, Some(subproject), None));
+        }
+        for grant in &grants {
+            let key = format!("user:{}/{index}", grant.user_id);
+            changes.push(Change::new("grant", key, Some(grant), None));
+        }
+        for token in &tokens {
+            changes.push(Change::new("token", token.id, Some(token), None));
+        }
+        changes.push(Change::new("project", index, Some(&project), None));
+        for change in changes {
+            audit_log::record(&txn, actor, change).await?;
+        }
+        txn.commit().await?;
+        self.invalidate(index).await;
+        Ok((200, json!({ "deleted": index })))
+    }
+
+    async fn find_subproject(
+        &self,
+        project: &project::Model,
+        index: &str,
+    ) -> Result<Option<subproject::Model>, AdminError> {
+        Ok(subproject::Entity::find()
+            .filter(subproject::Column::ProjectId.eq(project.id))
+            .filter(subproject::Column::Index.eq(index))
+            .one(&self.db_conn)
+            .await?)
+    }
+
+    async fn list_subprojects(&self, project_index: &str) -> AdminResult {
+        let project = self.find_project(project_index).await?;
+        let subprojects = subproject::Entity::find()
+            .filter(subproject::Column::ProjectId.eq(project.id))
+            .order_by_asc(subproject::Column::Id)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, redacted(subprojects)))
+    }
+
+    async fn get_subproject(&self, project_index: &str, index: &str) -> AdminResult {
+        let project = self.find_project(project_index).await?;
+        let subproject = self
+            .find_subproject(&project, index)
+            .await?
+            .ok_or_else(|| {
+                AdminError::NotFound(format!("subproject `{project_index}/{index}` not found"))
+            })?;
+        Ok((200, redacted(subproject)))
+    }
+
+    /// creates subproject, or replaces `existing` one. config must build into a service
+    async fn save_subproject(
+        &self,
+        actor: &str,
+        project_index: &str,
+        existing: Option<&str>,
+        body: SubprojectBody,
+    ) -> AdminResult {
+        let project = self.find_project(project_index).await?;
+        let (config, inherits_auth) = body.service_config();
+        let inherited = subproject::inherit(
+            config.clone(),
+            inherits_auth,
+            body.auth_profile.clone(),
+            &project,
+        );
+        if let Err(error) = inherited.and_then(get_auth_service) {
+            return Err(AdminError::BadRequest(format!(
+                "subproject `{project_index}/{}` is not valid: {error}",
+                body.index
+            )));
+        }
+        let current =
+            match existing {
+                Some(index) => Some(self.find_subproject(&project, index).await?.ok_or_else(
+                    || {
+                        AdminError::NotFound(format!(
+                            "subproject `{project_index}/{index}` not found"
+                        ))
+                    },
+                )?),
+                None => None,
+            };
+        if current.as_ref().map(|x| &x.index) != Some(&body.index)
+            && self.find_subproject(&project, &body.index).await?.is_some()
+        {
+            return Err(AdminError::BadRequest(format!(
+                "subproject `{project_index}/{}` already exists",
+                body.index
+            )));
+        }
+        let key = format!("{project_index}/{}", existing.unwrap_or(&body.index));
+        let mut model = match &current {
+            Some(current) => current.clone().into(),
+            None => subproject::ActiveModel {
+                id: NotSet,
+                project_id: Set(project.id),
+                ..Default::default()
+            },
+        };
+        model.index = Set(body.index);
+        model.set_service_config(config, inherits_auth);
+        model.auth_profile = Set(body.auth_profile);
+        let txn = self.db_conn.begin().await?;
+        let (status, model) = match &current {
+            Some(_) => (200, model.update(&txn).await?),
+            None => (201, model.insert(&txn).await?),
+        };
+        subproject_version::record(&txn, current.as_ref(), &model, actor).await?;
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("subproject", key, current.as_ref(), Some(&model)),
+        )
+        .await?;
+        txn.commit().await?;
+        self.invalidate(project_index).await;
+        Ok((status, redacted(model)))
+    }
+
+    async fn delete_subproject(
+        &self,
+        actor: &str,
+        project_index: &str,
+        index: &str,
+    ) -> AdminResult {
+        let project = self.find_project(project_index).await?;
+        let subproject = self
+            .find_subproject(&project, index)
+            .await?
+            .ok_or_else(|| {
+                AdminError::NotFound(format!("subproject `{project_index}/{index}` not found"))
+            })?;
+        let txn = self.db_conn.begin().await?;
+        subproject_version::Entity::delete_many()
+            .filter(subproject_version::Column::SubprojectId.eq(subproject.id))
+            .exec(&txn)
+            .await?;
+        subproject::Entity::delete_by_id(subproject.id)
+            .exec(&txn)
+            .await?;
+        let key = format!("{project_index}/{index}");
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("subproject", key, Some(&subproject), None),
+        )
+        .await?;
+        txn.commit().await?;
+        self.invalidate(project_index).await;
+        Ok((
+            200,
+            json!({ "deleted": format!("{project_index}/{index}") }),
+        ))
+    }
+
+    async fn list_versions(&self, project_index: &str, index: &str) -> AdminResult {
+        let project = self.find_project(project_index).await?;
+        let subproject = self
+            .find_subproject(&project, index)
+            .await?
+            .ok_or_else(|| {
+                AdminError::NotFound(format!("subproject `{project_index}/{index}` not found"))
+            })?;
+        let versions = subproject_version::list(&self.db_conn, subproject.id).await?;
+        Ok((200, redacted(versions)))
+    }
+
+    async fn find_version(
+        &self,
+        project_index: &str,
+        index: &str,
+        version: &str,
+    ) -> Result<(subproject::Model, subproject_version::Model), AdminError> {
+        let project = self.find_project(project_index).await?;
+        let subproject = self
+            .find_subproject(&project, index)
+            .await?
+            .ok_or_else(|| {
+                AdminError::NotFound(format!("subproject `{project_index}/{index}` not found"))
+            })?;
+        let version = parse_id(version)?;
+        let found = subproject_version::find(&self.db_conn, subproject.id, version)
+            .await?
+            .ok_or_else(|| {
+                AdminError::NotFound(format!(
+                    "subproject `{project_index}/{index}` has no version {version}"
+                ))
+            })?;
+        Ok((subproject, found))
+    }
+
+    /// restores config of `version`, which must still build into a service
+    async fn rollback(
+        &self,
+        actor: &str,
+        project_index: &str,
+        index: &str,
+        version: &str,
+    ) -> AdminResult {
+        let (subproject, version) = self.find_version(project_index, index, version).await?;
+        let project = self.find_project(project_index).await?;
+        let inherited = subproject::inherit(
+            version.config.0.clone(),
+            version.inherits_auth,
+            version.auth_profile.clone(),
+            &project,
+        );
+        if let Err(error) = inherited.and_then(get_auth_service) {
+            return Err(AdminError::BadRequest(format!(
+                "version {} of subproject `{project_index}/{index}` is not valid anymore: {error}",
+                version.version
+            )));
+        }
+        let txn = self.db_conn.begin().await?;
+        let (_, restored) = subproject_version::rollback(&txn, subproject, &version, actor).await?;
+        txn.commit().await?;
+        self.invalidate(project_index).await;
+        Ok((200, redacted(restored)))
+    }
+
+    async fn find_user(&self, id: i32) -> Result<user::Model, AdminError> {
+        user::Entity::find_by_id(id)
+            .one(&self.db_conn)
+            .await?
+            .ok_or_else(|| AdminError::NotFound(format!("user {id} not found")))
+    }
+
+    async fn list_users(&self) -> AdminResult {
+        let users = user::Entity::find()
+            .order_by_asc(user::Column::Id)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, json!(users)))
+    }
+
+    async fn get_user(&self, id: i32) -> AdminResult {
+        Ok((200, json!(self.find_user(id).await?)))
+    }
+
+    async fn save_user(&self, existing: Option<i32>, body: UserBody) -> AdminResult {
+        if ![user::UserType::Admin as i32, user::UserType::Normal as i32].contains(&body.user_type)
+        {
+            return Err(AdminError::BadRequest(format!(
+                "user_type should be {} (admin) or {} (normal)",
+                user::UserType::Admin as i32,
+                user::UserType::Normal as i32
+            )));
+        }
+        if let Some(identity) = &body.cert_identity {
+            if !mars_config::ClientIdentity::is_identity_key(identity) {
+                return Err(AdminError::BadRequest(
+                    "cert_identity should start with `sha256:`, `subject:` or `san:`".to_string(),
+                ));
+            }
+        }
+        let quota = body.quota.filter(Quota::is_limited).map(user::Quota);
+        if let Some(id) = existing {
+            let mut model: user::ActiveModel = self.find_user(id).await?.into();
+            model.user_type = Set(body.user_type);
+            model.user_name = Set(body.user_name);
+            model.user_email = Set(body.user_email);
+            model.cert_identity = Set(body.cert_identity);
+            model.quota = Set(quota);
+            return Ok((200, json!(model.update(&self.db_conn).await?)));
+        }
+        let id = match body.id {
+            Some(id) if self.find_user(id).await.is_ok() => {
+                return Err(AdminError::BadRequest(format!("user {id} already exists")))
+            }
+            Some(id) => id,
+            None => self.next_user_id().await?,
+        };
+        let model = user::ActiveModel {
+            id: Set(id),
+            user_type: Set(body.user_type),
+            user_name: Set(body.user_name),
+            user_email: Set(body.user_email),
+            cert_identity: Set(body.cert_identity),
+            quota: Set(quota),
+        }
+        .insert(&self.db_conn)
+        .await?;
+        Ok((201, json!(model)))
+    }
+
+    /// users table has no auto increment
+    async fn next_user_id(&self) -> Result<i32, AdminError> {
+        Ok(user::Entity::find()
+            .order_by_desc(user::Column::Id)
+            .one(&self.db_conn)
+            .await?
+            .map(|x| x.id + 1)
+            .unwrap_or(1))
+    }
+
+    /// user goes along with its grants, group memberships and tokens
+    async fn delete_user(&self, actor: &str, id: i32) -> AdminResult {
+        self.find_user(id).await?;
+        let grants = user_project::Entity::find()
+            .filter(user_project::Column::UserId.eq(id))
+            .all(&self.db_conn)
+            .await?;
+        let mut changes = vec![];
+        for grant in &grants {
+            let key = self.grant_key(grant).await?;
+            changes.push(Change::new("grant", key, Some(grant), None));
+        }
+        for token in authtoken::Entity::find()
+            .filter(authtoken::Column::UserId.eq(id))
+            .all(&self.db_conn)
+            .await?
+        {
+            changes.push(Change::new("token", token.id, Some(&token), None));
+        }
+        let txn = self.db_conn.begin().await?;
+        user_project::Entity::delete_many()
+            .filter(user_project::Column::UserId.eq(id))
+            .exec(&txn)
+            .await?;
+        group_member::Entity::delete_many()
+            .filter(group_member::Column::UserId.eq(id))
+            .exec(&txn)
+            .await?;
+        authtoken::Entity::delete_many()
+            .filter(authtoken::Column::UserId.eq(id))
+            .exec(&txn)
+            .await?;
+        user::Entity::delete_by_id(id).exec(&txn).await?;
+        for change in changes {
+            audit_log::record(&txn, actor, change).await?;
+        }
+        txn.commit().await?;
+        Ok((200, json!({ "deleted": id })))
+    }
+
+    async fn list_grants(&self) -> AdminResult {
+        let grants = user_project::Entity::find()
+            .order_by_asc(user_project::Column::Id)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, json!(grants)))
+    }
+
+    /// `user:<id>/<project>`
+    async fn grant_key(&self, grant: &user_project::Model) -> Result<String, AdminError> {
+        let project = project::Entity::find_by_id(grant.project_id)
+            .one(&self.db_conn)
+            .await?
+            .map(|x| x.index)
+            .unwrap_or_else(|| grant.project_id.to_string());
+        Ok(format!("user:{}/{project}", grant.user_id))
+    }
+
+    async fn save_grant(&self, actor: &str, existing: Option<i32>, body: GrantBody) -> AdminResult {
+        if is_admin(&self.find_user(body.user_id).await?) {
+            return Err(AdminError::BadRequest(format!(
+                "user {} is an admin, admins can't be granted projects",
+                body.user_id
+            )));
+        }
+        let project = self.find_project(&body.project).await?;
+        let policy = body.policy.map(user_project::Policy);
+        let key = format!("user:{}/{}", body.user_id, body.project);
+        match existing {
+            Some(id) => {
+                let current = user_project::Entity::find_by_id(id)
+                    .one(&self.db_conn)
+                    .await?
+                    .ok_or_else(|| AdminError::NotFound(format!("grant {id} not found")))?;
+                let mut model: user_project::ActiveModel = current.clone().into();
+                model.user_id = Set(body.user_id);
+                model.project_id = Set(project.id);
+                model.permissions = Set(body.permissions);
+                model.policy = Set(policy);
+                let txn = self.db_conn.begin().await?;
+                let model = model.update(&txn).await?;
+                audit_log::record(
+                    &txn,
+                    actor,
+                    Change::new("grant", key, Some(&current), Some(&model)),
+                )
+                .await?;
+                txn.commit().await?;
+                Ok((200, json!(model)))
+            }
+            None => {
+                if user_project::Entity::find()
+                    .filter(user_project::Column::UserId.eq(body.user_id))
+                    .filter(user_project::Column::ProjectId.eq(project.id))
+                    .one(&self.db_conn)
+                    .await?
+                    .is_some()
+                {
+                    return Err(AdminError::BadRequest(format!(
+                        "user {} already has a grant on project `{}`",
+                        body.user_id, body.project
+                    )));
+                }
+                let model = user_project::ActiveModel {
+                    id: NotSet,
+                    user_id: Set(body.user_id),
+                    project_id: Set(project.id),
+                    permissions: Set(body.permissions),
+                    policy: Set(policy),
+                };
+                let txn = self.db_conn.begin().await?;
+                let model = model.insert(&txn).await?;
+                audit_log::record(&txn, actor, Change::new("grant", key, None, Some(&model)))
+                    .await?;
+                txn.commit().await?;
+                Ok((201, json!(model)))
+            }
+        }
+    }
+
+    async fn delete_grant(&self, actor: &str, id: i32) -> AdminResult {
+        let grant = user_project::Entity::find_by_id(id)
+            .one(&self.db_conn)
+            .await?
+            .ok_or_else(|| AdminError::NotFound(format!("grant {id} not found")))?;
+        let key = self.grant_key(&grant).await?;
+        let txn = self.db_conn.begin().await?;
+        user_project::Entity::delete_by_id(id).exec(&txn).await?;
+        audit_log::record(&txn, actor, Change::new("grant", key, Some(&grant), None)).await?;
+        txn.commit().await?;
+        Ok((200, json!({ "deleted": id })))
+    }
+
+    /// tokens are listed without their secret, which is only returned when created
+    async fn list_tokens(&self) -> AdminResult {
+        let tokens = authtoken::Entity::find()
+            .order_by_asc(authtoken::Column::Id)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, Value::Array(tokens.iter().map(token_view).collect())))
+    }
+
+    async fn create_token(&self, actor: &str, body: TokenBody) -> AdminResult {
+        let (kind, user, project_id) = match (body.user_id, &body.project) {
+            (Some(user_id), None) => ("user", Some(self.find_user(user_id).await?), None),
+            (None, Some(project)) => ("project", None, Some(self.find_project(project).await?.id)),
+            _ => {
+                return Err(AdminError::BadRequest(
+                    "token should be for either `user_id` or `project`".to_string(),
+                ))
+            }
+        };
+        if kind == "user" && body.policy.is_some() {
+            return Err(AdminError::BadRequest(
+                "user tokens are restricted through the user's grants, not a policy".to_string(),
+            ));
+        }
+        let permissions = body.permissions.unwrap_or_else(|| match &user {
+            Some(user) if is_admin(user) => {
+                AuthTokenPermissions::Read as i32 | AuthTokenPermissions::Write as i32
+            }
+            _ => execute(),
+        });
+        let auth_token = uuid::Uuid::new_v4();
+        let model = authtoken::ActiveModel {
+            id: NotSet,
+            project_id: Set(project_id),
+            user_id: Set(user.map(|x| x.id)),
+            auth_token: Set(auth_token),
+            permissions: Set(permissions),
+            policy: Set(body.policy.map(authtoken::Policy)),
+            quota: Set(body.quota.filter(Quota::is_limited).map(authtoken::Quota)),
+            source_cidrs: Set((!body.source_cidrs.is_empty())
+                .then_some(authtoken::SourceCidrs(body.source_cidrs))),
+        };
+        let txn = self.db_conn.begin().await?;
+        let model = model.insert(&txn).await?;
+        audit_log::record(
+            &txn,
+            actor,
+            Change::new("token", model.id, None, Some(&model)),
+        )
+        .await?;
+        txn.commit().await?;
+        let mut view = token_view(&model);
+        view["token"] = json!(format!("{kind}:{auth_token}"));
+        Ok((201, view))
+    }
+
+    async fn delete_token(&self, actor: &str, id: i32) -> AdminResult {
+        let token = authtoken::Entity::find_by_id(id)
+            .one(&self.db_conn)
+            .await?
+            .ok_or_else(|| AdminError::NotFound(format!("token {id} not found")))?;
+        let txn = self.db_conn.begin().await?;
+        authtoken::Entity::delete_by_id(id).exec(&txn).await?;
+        audit_log::record(&txn, actor, Change::new("token", id, Some(&token), None)).await?;
+        txn.commit().await?;
+        Ok((200, json!({ "deleted": id })))
+    }
+
+    async fn list_audit(&self, query: AuditQuery) -> AdminResult {
+        let mut select = audit_log::Entity::find();
+        if let Some(resource) = query.resource {
+            select = select.filter(audit_log::Column::Resource.eq(resource));
+        }
+        if let Some(resource_key) = query.resource_key {
+            select = select.filter(audit_log::Column::ResourceKey.eq(resource_key));
+        }
+        if let Some(actor) = query.actor {
+            select = select.filter(audit_log::Column::Actor.eq(actor));
+        }
+        if let Some(since) = query.since {
+            select = select.filter(audit_log::Column::At.gte(since));
+        }
+        if let Some(until) = query.until {
+            select = select.filter(audit_log::Column::At.lte(until));
+        }
+        let records = select
+            .order_by_desc(audit_log::Column::Id)
+            .limit(query.limit)
+            .all(&self.db_conn)
+            .await?;
+        Ok((200, json!(records)))
+    }
+}
+
+fn is_admin(user: &user::Model) -> bool {
+    user.user_type == user::UserType::Admin as i32
+}
+
+/// `_admin` and `_oidc` paths are served by mars-rover itself, projects can't start with `_`
+fn check_index(index: &str) -> Result<(), AdminError> {
+    if index.is_empty() || index.starts_with('_') || index.contains(['/', '?']) {
+        return Err(AdminError::BadRequest(format!(
+            "`{index}` can't be used as index, it should be non empty, not start with `_` and not contain `/` or `?`"
+        )));
+    }
+    Ok(())
+}
+
+/// projects, subprojects and their versions as returned, secrets redacted as in audit log
+fn redacted(value: impl Serialize) -> Value {
+    audit_log::redact(json!(value))
+}
+
+fn token_view(token: &authtoken::Model) -> Value {
+    let mut view = json!(token);
+    if let Some(view) = view.as_object_mut() {
+        view.remove("auth_token");
+    }
+    view
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, AdminError> {
+    serde_json::from_slice(body)
+        .map_err(|err| AdminError::BadRequest(format!("unable to parse body: {err}")))
+}
+
+fn parse_id(id: &str) -> Result<i32, AdminError> {
+    id.parse()
+        .map_err(|_| AdminError::BadRequest(format!("`{id}` is not a valid id")))
+}
+
+/// Connects admin API to the database at `url`.
+pub async fn get_admin_api(url: &str) -> Result<AdminApi, Box<dyn Error>> {
+    Ok(AdminApi::new(Database::connect(url).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use http::Request;
+    use hyper::Body;
+    use mars_entity::{
+        audit_log::REDACTED, authtoken, group_member, group_project, project, subproject, user,
+        user_project,
+    };
+    use sea_orm::{ActiveValue::Set, ConnectionTrait, Database, EntityTrait, Schema};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use super::AdminApi;
+
+    async fn admin_api() -> (AdminApi, String, String) {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        macro_rules! create_table {
+            ($entity:expr) => {
+                db.execute(
+                    db.get_database_backend()
+                        .build(&schema.create_table_from_entity($entity)),
+                )
+                .await
+                .unwrap();
+            };
+        }
+        create_table!(project::Entity);
+        create_table!(subproject::Entity);
+        create_table!(user::Entity);
+        create_table!(user_project::Entity);
+        create_table!(authtoken::Entity);
+        create_table!(mars_entity::group::Entity);
+        create_table!(group_member::Entity);
+        create_table!(group_project::Entity);
+        create_table!(mars_entity::audit_log::Entity);
+        create_table!(mars_entity::subproject_version::Entity);
+
+        let mut tokens = vec![];
+        for (id, user_type, permissions) in [
+            (1, user::UserType::Admin, 0b11),
+            (2, user::UserType::Normal, 0b111),
+        ] {
+            user::Entity::insert(user::ActiveModel {
+                id: Set(id),
+                user_type: Set(user_type as i32),
+                user_name: Set(format!("user{id}")),
+                user_email: Set(format!("user{id}@example.com")),
+                cert_identity: Set(None),
+                quota: Set(None),
+            })
+            .exec(&db)
+            .await
+            .unwrap();
+            let token = Uuid::new_v4();
+            authtoken::Entity::insert(authtoken::ActiveModel {
+                id: Set(id),
+                project_id: Set(None),
+                user_id: Set(Some(id)),
+                auth_token: Set(token),
+                permissions: Set(permissions),
+                policy: Set(None),
+                quota: Set(None),
+                source_cidrs: Set(None),
+            })
+            .exec(&db)
+            .await
+            .unwrap();
+            tokens.push(format!("Bearer user:{token}"));
+        }
+        let normal = tokens.pop().unwrap();
+        (AdminApi::new(db), tokens.pop().unwrap(), normal)
+    }
+
+    async fn call(
+        admin: &AdminApi,
+        token: &str,
+        method: &str,
+        path: &str,
+        body: Value,
+    ) -> (u16, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("authorization", token)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = admin.handle(request).await.unwrap();
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_crud() {
+        let (admin, token, _) = admin_api().await;
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko", "needs_auth": true}),
+        )
+        .await;
+        assert_eq!(status, 201);
+
+        let subproject = json!({
+            "index": "json",
+            "url": "https://httpbin.org/json",
+            "method": "ANY",
+            "auth": {"auth_type": "header_auth", "params": [{"key": "x-api-key", "value": "secret"}]}
+        });
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            subproject,
+        )
+        .await;
+        assert_eq!(status, 201);
+
+        // config that can't be built is not saved
+        let broken = json!({
+            "index": "xml",
+            "url": "https://httpbin.org/xml",
+            "method": "ANY",
+            "auth": {"auth_type": "header_auth", "params": {}}
+        });
+        let (status, body) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            broken,
+        )
+        .await;
+        assert_eq!(status, 400, "{body}");
+        let (_, subprojects) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/projects/aviko/subprojects",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(subprojects.as_array().unwrap().len(), 1);
+
+        let (status, user) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/users",
+            json!({"user_name": "pluto", "user_email": "pluto@example.com"}),
+        )
+        .await;
+        assert_eq!(status, 201);
+        assert_eq!(user["id"], 3);
+        let (status, _) = call(&admin, &token, "POST", "/_admin/grants", json!({"user_id": 3, "project": "aviko", "policy": {"allow": [{"subproject": "json"}]}})).await;
+        assert_eq!(status, 201);
+        let (status, created) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/tokens",
+            json!({"user_id": 3}),
+        )
+        .await;
+        assert_eq!(status, 201);
+        assert!(created["token"].as_str().unwrap().starts_with("user:"));
+        let (_, tokens) = call(&admin, &token, "GET", "/_admin/tokens", Value::Null).await;
+        assert!(tokens
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|x| x.get("auth_token").is_none()));
+
+        let (status, _) = call(
+            &admin,
+            &token,
+            "DELETE",
+            "/_admin/projects/aviko",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (_, grants) = call(&admin, &token, "GET", "/_admin/grants", Value::Null).await;
+        assert!(grants.as_array().unwrap().is_empty());
+        let (status, _) = call(&admin, &token, "GET", "/_admin/projects/aviko", Value::Null).await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let (admin, token, normal) = admin_api().await;
+        let (status, _) = call(&admin, "", "GET", "/_admin/projects", Value::Null).await;
+        assert_eq!(status, 401);
+        // token of a normal user doesn't reach admin api, whatever its permissions
+        let (status, _) = call(&admin, &normal, "GET", "/_admin/projects", Value::Null).await;
+        assert_eq!(status, 403);
+        // admin token without execute bit can read and write
+        let (status, _) = call(&admin, &token, "GET", "/_admin/projects", Value::Null).await;
+        assert_eq!(status, 200);
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko"}),
+        )
+        .await;
+        assert_eq!(status, 201);
+
+        // project tokens are api tokens too
+        let (_, created) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/tokens",
+            json!({"project": "aviko", "permissions": 0b111}),
+        )
+        .await;
+        let project_token = format!("Bearer {}", created["token"].as_str().unwrap());
+        let (status, _) = call(
+            &admin,
+            &project_token,
+            "GET",
+            "/_admin/projects",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_admin_separation() {
+        let (admin, token, _) = admin_api().await;
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko"}),
+        )
+        .await;
+        // admin users can't be given access to proxied apis
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/grants",
+            json!({"user_id": 1, "project": "aviko"}),
+        )
+        .await;
+        assert_eq!(status, 400);
+        // paths served by mars-rover itself can't be shadowed by a project
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "_admin"}),
+        )
+        .await;
+        assert_eq!(status, 400);
+        // tokens of admin users default to read and write only
+        let (_, created) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/tokens",
+            json!({"user_id": 1}),
+        )
+        .await;
+        assert_eq!(created["permissions"], 0b11);
+        let (_, created) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/tokens",
+            json!({"user_id": 2}),
+        )
+        .await;
+        assert_eq!(created["permissions"], 0b100);
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let (admin, token, _) = admin_api().await;
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko"}),
+        )
+        .await;
+        let subproject = |key: &str| {
+            json!({
+                "index": "json",
+                "url": "https://httpbin.org/json",
+                "method": "ANY",
+                "auth": {"auth_type": "header_auth", "params": [{"key": "x-api-key", "value": key}]}
+            })
+        };
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            subproject("first-key"),
+        )
+        .await;
+        call(
+            &admin,
+            &token,
+            "PUT",
+            "/_admin/projects/aviko/subprojects/json",
+            subproject("rotated-key"),
+        )
+        .await;
+        let (_, created) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/tokens",
+            json!({"project": "aviko"}),
+        )
+        .await;
+        let uuid = created["token"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches("project:");
+
+        let (status, records) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/audit?resource=subproject&resource_key=aviko%2Fjson",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 200);
+        // newest first
+        assert_eq!(records[0]["action"], "update");
+        assert_eq!(records[0]["actor"], "user:1");
+        assert_eq!(records[0]["changes"], json!(["auth.params.0.value"]));
+        assert_eq!(records[1]["action"], "create");
+
+        // deleting project records what went along with it
+        call(
+            &admin,
+            &token,
+            "DELETE",
+            "/_admin/projects/aviko",
+            Value::Null,
+        )
+        .await;
+        let (_, records) = call(&admin, &token, "GET", "/_admin/audit", Value::Null).await;
+        let records = records.as_array().unwrap();
+        let summary: Vec<(&str, &str)> = records
+            .iter()
+            .map(|x| {
+                (
+                    x["action"].as_str().unwrap(),
+                    x["resource"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("delete", "project"),
+                ("delete", "token"),
+                ("delete", "subproject"),
+                ("create", "token"),
+                ("update", "subproject"),
+                ("create", "subproject"),
+                ("create", "project"),
+            ]
+        );
+        let all = serde_json::to_string(records).unwrap();
+        assert!(!all.contains("first-key") && !all.contains("rotated-key") && !all.contains(uuid));
+
+        let (status, _) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/audit?since=yesterday",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_redacted() {
+        let (admin, token, _) = admin_api().await;
+        let auth = json!({"auth_type": "basic_auth", "params": {"username": "u", "password": "p"}});
+        let (status, project) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko", "auth_profiles": {"httpbin": auth}}),
+        )
+        .await;
+        assert_eq!(status, 201);
+        assert_eq!(
+            project["auth_profiles"]["httpbin"]["params"]["password"],
+            REDACTED
+        );
+        let subproject = json!({
+            "index": "json", "url": "https://httpbin.org/json", "method": "ANY", "auth": auth
+        });
+        let (status, saved) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            subproject,
+        )
+        .await;
+        assert_eq!(status, 201);
+        assert_eq!(saved["auth"]["params"]["password"], REDACTED);
+        for (path, pointer) in [
+            (
+                "/_admin/projects",
+                "/0/auth_profiles/httpbin/params/username",
+            ),
+            (
+                "/_admin/projects/aviko",
+                "/auth_profiles/httpbin/params/username",
+            ),
+            (
+                "/_admin/projects/aviko/subprojects",
+                "/0/auth/params/password",
+            ),
+            (
+                "/_admin/projects/aviko/subprojects/json",
+                "/auth/params/password",
+            ),
+            (
+                "/_admin/projects/aviko/subprojects/json/versions",
+                "/0/config/auth/params/password",
+            ),
+            (
+                "/_admin/projects/aviko/subprojects/json/versions/1",
+                "/config/auth/params/password",
+            ),
+        ] {
+            let (status, body) = call(&admin, &token, "GET", path, Value::Null).await;
+            assert_eq!(status, 200, "{path}");
+            assert_eq!(body.pointer(pointer), Some(&json!(REDACTED)), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_versions() {
+        let (admin, token, _) = admin_api().await;
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({"index": "aviko"}),
+        )
+        .await;
+        let subproject = |url: &str| json!({"index": "json", "url": url, "method": "ANY"});
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            subproject("https://httpbin.org/json"),
+        )
+        .await;
+        call(
+            &admin,
+            &token,
+            "PUT",
+            "/_admin/projects/aviko/subprojects/json",
+            subproject("https://httpbin.org/broken"),
+        )
+        .await;
+        let (_, versions) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/projects/aviko/subprojects/json/versions",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(versions.as_array().unwrap().len(), 2);
+
+        let (status, diff) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/projects/aviko/subprojects/json/versions/1/diff/2",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            diff,
+            json!([{"path": "url", "from": "https://httpbin.org/json", "to": "https://httpbin.org/broken"}])
+        );
+
+        let (status, restored) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects/json/versions/1/rollback",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(restored["version"], 3);
+        let (_, current) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/projects/aviko/subprojects/json",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(current["url"], "https://httpbin.org/json");
+        let (_, records) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/audit?resource=subproject",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(records.as_array().unwrap().len(), 3);
+
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects/json/versions/7/rollback",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_auth_profile() {
+        let (admin, token, _) = admin_api().await;
+        let auth = |username: &str| json!({"auth_type": "basic_auth", "params": {"username": username, "password": "p"}});
+        call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects",
+            json!({
+                "index": "aviko",
+                "defaults": {"url": "https://httpbin.org/json"},
+                "auth_profiles": {"first": auth("first"), "second": auth("second")}
+            }),
+        )
+        .await;
+        // url is left out, so it is inherited from defaults of project
+        let subproject = |profile: &str| json!({"index": "json", "url": "", "method": "ANY", "auth_profile": profile});
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects",
+            subproject("first"),
+        )
+        .await;
+        assert_eq!(status, 201);
+        call(
+            &admin,
+            &token,
+            "PUT",
+            "/_admin/projects/aviko/subprojects/json",
+            subproject("second"),
+        )
+        .await;
+
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects/json/versions/1/rollback",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (_, current) = call(
+            &admin,
+            &token,
+            "GET",
+            "/_admin/projects/aviko/subprojects/json",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(current["auth_profile"], "first");
+
+        // profile of version 1 is gone, so it can't be served anymore
+        call(
+            &admin,
+            &token,
+            "PUT",
+            "/_admin/projects/aviko",
+            json!({
+                "index": "aviko",
+                "defaults": {"url": "https://httpbin.org/json"},
+                "auth_profiles": {"second": auth("second")}
+            }),
+        )
+        .await;
+        let (status, _) = call(
+            &admin,
+            &token,
+            "POST",
+            "/_admin/projects/aviko/subprojects/json/versions/1/rollback",
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+}
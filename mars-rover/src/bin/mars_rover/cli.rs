@@ -1,57 +1,415 @@
-use mars_rover::{db, file as json_project_manager, project::ProjectManager};
+use mars_rover::{
+    client_ip::{ForwardedHeader, TrustedProxies},
+    db, file::{self as json_project_manager, Overlay},
+    layered::{LayeredProjectManager, ProjectPrecedence, TokenPrecedence},
+    project::{ConfigError, ProjectManager},
+    remote::{self, Link},
+};
+#[cfg(feature = "sql")]
+use mars_rover::admin::{self, AdminApi};
+use mars_config::{overlay_path, ConfigFormat, IpNet};
+#[cfg(feature = "oidc")]
+use mars_rover::oidc::{OidcClient, OidcConfig};
+#[cfg(feature = "tls")]
+use mars_rover::tls::TlsConfig;
 use clap::Parser;
+use http::HeaderMap;
 /// This module contains the command-line interface (CLI) functionality for the Mars Rover project.
 /// It defines the `Args` struct which represents the command-line arguments and provides methods to retrieve a project manager.
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
 /// Represents the command-line arguments for the Mars Rover project.
 #[derive(Parser)]
 pub struct Args {
     #[clap(subcommand)]
-    pub(crate) subcommand: DbParams,
+    pub(crate) subcommand: Command,
     /// The address to bind the server to. Default value is "127.0.0.1:3000".
     #[clap(short, long, default_value = "127.0.0.1:3000")]
     pub(crate) addr: String,
+    /// Builds service of every subproject before serving, instead of on its first request. Exits listing every one that doesn't build.
+    #[clap(long)]
+    pub(crate) eager: bool,
+    /// Environment (dev, staging, prod, ..) config is overlaid for. `config.prod.json5` next to `config.json5` patches it for `prod`.
+    #[clap(long = "env", env = "MARS_ENV")]
+    pub(crate) environment: Option<String>,
+    /// Serves config as it is when overlay of `--env` doesn't exist, instead of refusing to start.
+    #[clap(long, requires = "environment")]
+    pub(crate) allow_missing_overlay: bool,
+    /// pem certificate chain, serves https when provided
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls-key")]
+    pub(crate) tls_cert: Option<String>,
+    /// pem private key for `--tls-cert`
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls-cert")]
+    pub(crate) tls_key: Option<String>,
+    /// pem CA bundle, clients must present a certificate signed by it (mutual-TLS)
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls-cert")]
+    pub(crate) client_ca: Option<String>,
+    /// CIDR of a proxy or load balancer whose forwarding header is trusted, can be repeated
+    #[clap(long)]
+    pub(crate) trusted_proxy: Vec<IpNet>,
+    /// header trusted proxies report source address in, `x-forwarded-for` or `forwarded`
+    #[clap(long, default_value = "x-forwarded-for")]
+    pub(crate) forwarded_header: ForwardedHeader,
+    /// json5 file with OpenID Connect provider, enables browser login for interactive users
+    #[cfg(feature = "oidc")]
+    #[clap(long)]
+    pub(crate) oidc_config: Option<String>,
+    /// serves admin api under `/_admin`, needs `db` project manager
+    #[cfg(feature = "sql")]
+    #[clap(long)]
+    pub(crate) admin: bool,
+    /// serves admin api on its own address (say an internal interface) instead of under
+    /// `/_admin` of `--addr`, implies `--admin`
+    #[cfg(feature = "sql")]
+    #[clap(long)]
+    pub(crate) admin_addr: Option<SocketAddr>,
+    /// serves only admin api on `--addr`, proxies run as other processes on the same db
+    #[cfg(feature = "sql")]
+    #[clap(long, conflicts_with = "admin-addr")]
+    pub(crate) admin_only: bool,
 }
 
 
 #[derive(clap::Subcommand, Clone, Debug)]
 pub enum DbParams{
     File{
-        /// The path to the configuration file. Default value is "config/config.json5".
+        /// The path or http(s) url of the configuration file. Default value is "config/config.json5".
         #[clap(short, long, default_value = "config/config.json5")]
         config: String,
+        /// The path or http(s) url of the tokens file.
         tokens: Option<String>,
+        /// Seconds between checks of config and tokens for changes. Either is reloaded on SIGHUP regardless.
+        #[clap(long)]
+        reload_interval: Option<u64>,
+        /// `Name: value` header sent when config or tokens are fetched over http(s), can be repeated
+        #[clap(long)]
+        remote_header: Vec<String>,
+        /// Format of config and tokens, `json5`, `yaml` or `toml`. Picked by extension of each when omitted.
+        #[clap(long)]
+        format: Option<ConfigFormat>,
     },
     /// The optional database URL. Only available when the "sql" feature is enabled.
     #[cfg(feature = "sql")]
     Db {
         #[clap(short, long)]
         url: String,
+    },
+    /// Projects and tokens kept in Redis, shared by replicas. Only available when the "redis" feature is enabled.
+    #[cfg(feature = "redis")]
+    Redis {
+        /// `redis://` url of the server.
+        #[clap(short, long)]
+        url: String,
+        /// Prefix of keys projects and tokens are kept under.
+        #[clap(long, default_value = mars_rover::redis::DEFAULT_PREFIX)]
+        prefix: String,
+    },
+    /// Projects of several sources served as one, a file of emergency projects ahead of a database for example.
+    Layered {
+        /// Source written as its subcommand, `--source "file -c config/overrides.json5"`. Arguments are quoted as in a shell. Can be repeated, in order of precedence.
+        #[clap(long = "source", required = true)]
+        sources: Vec<DbParams>,
+        /// Which of the sources having a project serves it, `first` or `last`.
+        #[clap(long, default_value = "first")]
+        project_precedence: ProjectPrecedence,
+        /// Which sources grant tokens access to a project, `owner` (the one serving it) or `any`.
+        #[clap(long, default_value = "owner")]
+        token_precedence: TokenPrecedence,
+    },
+}
+
+/// a single source, as given to `--source` of `layered`
+#[derive(Parser)]
+struct Source {
+    #[clap(subcommand)]
+    source: DbParams,
+}
+
+impl FromStr for DbParams {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // quoted the way a shell would, so that paths and headers may contain spaces
+        let words = shlex::split(value).ok_or_else(|| format!("unbalanced quotes in source `{value}`"))?;
+        let source = Source::try_parse_from(std::iter::once("source".to_string()).chain(words))
+            .map_err(|err| err.to_string())?;
+        match source.source {
+            DbParams::Layered { .. } => Err("layered sources can't be nested".to_string()),
+            source => Ok(source),
+        }
     }
 }
 
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum Command {
+    #[clap(flatten)]
+    Serve(DbParams),
+    /// Builds service of every subproject of config or database and exits, listing every one that doesn't build. Exits non-zero when any doesn't.
+    Validate {
+        #[clap(subcommand)]
+        source: DbParams,
+    },
+    /// Prints JSON Schema of config file, for editors to validate and autocomplete it with.
+    Schema,
+}
+
 
+/// config, its overlay for `environment` and tokens links, remote ones fetched with
+/// `remote_header`s
+fn links(
+    config: &str,
+    environment: Option<&str>,
+    allow_missing_overlay: bool,
+    tokens: Option<&str>,
+    remote_header: &[String],
+    format: Option<ConfigFormat>,
+) -> (Link, Option<Overlay>, Option<Link>) {
+    let mut headers = HeaderMap::new();
+    for header in remote_header {
+        let (name, value) = remote::parse_header(header).expect("unable to parse remote header");
+        headers.append(name, value);
+    }
+    let link = |link: &str| {
+        let link = Link::new(link, headers.clone()).expect("unable to load config");
+        match format {
+            Some(format) => link.with_format(format),
+            None => link,
+        }
+    };
+    let overlay = environment.map(|environment| Overlay {
+        environment: environment.to_string(),
+        link: link(&overlay_path(config, environment)),
+        allow_missing: allow_missing_overlay,
+    });
+    (link(config), overlay, tokens.map(link))
+}
 
 impl Args {
     /// Retrieves the project manager based on the command-line arguments.
     /// Returns an `Arc<Box<dyn ProjectManager>>`.
     pub async fn get_project_manager(&self) -> Arc<Box<dyn ProjectManager>> {
-        match &self.subcommand {
-            DbParams::File { config, tokens } => {
-                json_project_manager::get_file_project_manager(config.clone().into(), tokens.clone().into())
+        match self.source() {
+            DbParams::Layered { sources, project_precedence, token_precedence } => {
+                let mut backends = vec![];
+                for source in sources {
+                    backends.push(self.project_manager(source).await);
+                }
+                let project_manager = LayeredProjectManager::new(backends)
+                    .with_project_precedence(*project_precedence)
+                    .with_token_precedence(*token_precedence);
+                Arc::new(Box::new(project_manager))
+            }
+            source => self.project_manager(source).await,
+        }
+    }
+
+    /// project manager of a single source
+    async fn project_manager(&self, source: &DbParams) -> Arc<Box<dyn ProjectManager>> {
+        match source {
+            DbParams::File { config, tokens, reload_interval, remote_header, format } => {
+                let (config, overlay, tokens) = links(
+                    config,
+                    self.environment.as_deref(),
+                    self.allow_missing_overlay,
+                    tokens.as_deref(),
+                    remote_header,
+                    *format,
+                );
+                json_project_manager::get_reloading_file_project_manager(
+                    config,
+                    overlay,
+                    tokens,
+                    reload_interval.map(Duration::from_secs),
+                    self.eager,
+                )
                 .await
                 .expect("unable to load config")
             },
             #[cfg(feature = "sql")]
             DbParams::Db { url } => {
+                self.warn_environment();
                 db::get_db_project_manager(url)
                 .await
                 .expect("unable to connect to db")
             },
+            #[cfg(feature = "redis")]
+            DbParams::Redis { url, prefix } => {
+                self.warn_environment();
+                mars_rover::redis::get_redis_project_manager(url, prefix)
+                .await
+                .expect("unable to connect to redis")
+            },
+            DbParams::Layered { .. } => unreachable!("layered sources are not nested"),
+        }
+    }
+
+    /// Whether `validate` subcommand was given, server is not to be started.
+    pub fn validate_only(&self) -> bool {
+        matches!(self.subcommand, Command::Validate { .. })
+    }
+
+    /// Whether `schema` subcommand was given, server is not to be started.
+    pub fn schema_only(&self) -> bool {
+        matches!(self.subcommand, Command::Schema)
+    }
+
+    /// Builds service of every subproject, printing every one that doesn't build along with the
+    /// reason. Returns whether all of them built.
+    pub async fn validate(&self) -> bool {
+        let result = match self.source() {
+            DbParams::Layered { sources, .. } => async {
+                let mut errors = vec![];
+                for source in sources {
+                    errors.extend(self.validate_source(source).await?);
+                }
+                Ok(errors)
+            }
+            .await,
+            source => self.validate_source(source).await,
+        };
+        match result {
+            Ok(errors) if errors.is_empty() => {
+                println!("every subproject is valid");
+                true
+            }
+            Ok(errors) => {
+                for error in &errors {
+                    eprintln!("{error}");
+                }
+                eprintln!("errors in config: {}", errors.len());
+                false
+            }
+            Err(err) => {
+                eprintln!("unable to load config: {}", err.trim_end());
+                false
+            }
+        }
+    }
+
+    /// every subproject of a single source that doesn't build
+    async fn validate_source(&self, source: &DbParams) -> Result<Vec<ConfigError>, String> {
+        match source {
+            DbParams::File { config, tokens, remote_header, format, .. } => {
+                let (config, overlay, tokens) = links(
+                    config,
+                    self.environment.as_deref(),
+                    self.allow_missing_overlay,
+                    tokens.as_deref(),
+                    remote_header,
+                    *format,
+                );
+                json_project_manager::validate_file_config(
+                    &config,
+                    overlay.as_ref(),
+                    tokens.as_ref(),
+                )
+                .await
+                .map_err(|err| err.to_string())
+            },
+            #[cfg(feature = "sql")]
+            DbParams::Db { url } => {
+                self.warn_environment();
+                db::validate_db_config(url).await.map_err(|err| err.to_string())
+            },
+            #[cfg(feature = "redis")]
+            DbParams::Redis { url, prefix } => {
+                self.warn_environment();
+                mars_rover::redis::validate_redis_config(url, prefix)
+                    .await
+                    .map_err(|err| err.to_string())
+            },
+            DbParams::Layered { .. } => unreachable!("layered sources are not nested"),
+        }
+    }
+
+    /// Environments only overlay config files, database and redis have no overlays.
+    #[cfg(any(feature = "sql", feature = "redis"))]
+    fn warn_environment(&self) {
+        let overlaid = self
+            .sources()
+            .iter()
+            .any(|source| matches!(source, DbParams::File { .. }));
+        if let (Some(environment), false) = (&self.environment, overlaid) {
+            log::warn!("environment `{environment}` is ignored, only config files have overlays");
+        }
+    }
+
+    /// Source of projects, that of `validate` when given.
+    fn source(&self) -> &DbParams {
+        match &self.subcommand {
+            Command::Serve(source) | Command::Validate { source } => source,
+            Command::Schema => unreachable!("schema has no source of projects"),
+        }
+    }
+
+    /// Sources of projects, those of `layered` or the only one.
+    fn sources(&self) -> &[DbParams] {
+        match self.source() {
+            DbParams::Layered { sources, .. } => sources,
+            source => std::slice::from_ref(source),
+        }
+    }
+
+    /// Retrieves tls configuration, `None` when server should listen on plain http.
+    #[cfg(feature = "tls")]
+    pub fn get_tls_config(&self) -> Option<TlsConfig> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca: self.client_ca.as_ref().map(Into::into),
+            }),
+            _ => None,
         }
     }
 
+    /// Proxies trusted to report source address of requests.
+    pub fn get_trusted_proxies(&self) -> TrustedProxies {
+        TrustedProxies {
+            proxies: self.trusted_proxy.clone(),
+            header: self.forwarded_header,
+        }
+    }
+
+    /// Admin api against the database of `db` source, when enabled.
+    #[cfg(feature = "sql")]
+    pub async fn get_admin_api(
+        &self,
+        project_manager: Option<&Arc<Box<dyn ProjectManager>>>,
+    ) -> Option<AdminApi> {
+        if !(self.admin || self.admin_addr.is_some() || self.admin_only) {
+            return None;
+        }
+        let url = self
+            .sources()
+            .iter()
+            .find_map(|source| match source {
+                DbParams::Db { url } => Some(url),
+                _ => None,
+            })
+            .expect("admin api is only available with `db` source");
+        let admin = admin::get_admin_api(url)
+            .await
+            .expect("unable to connect to db");
+        Some(match project_manager {
+            Some(project_manager) => admin.with_project_manager(project_manager.clone()),
+            None => admin,
+        })
+    }
+
+    /// Browser login through OpenID Connect, when configured.
+    #[cfg(feature = "oidc")]
+    pub fn get_oidc_client(&self) -> Option<OidcClient> {
+        self.oidc_config.as_ref().map(|path| {
+            OidcConfig::from_file(path.as_ref())
+                .and_then(OidcClient::new)
+                .expect("unable to load oidc config")
+        })
+    }
+
     pub fn get_addr(&self) -> SocketAddr {
         let port_key = "FUNCTIONS_CUSTOMHANDLER_PORT";
         match std::env::var(port_key) {
@@ -1,5 +1,5 @@
 mod cli;
-use mars_rover::start_server;
+use mars_rover::{start_server, ServerOptions};
 use clap::Parser;
 
 #[tokio::main]
@@ -9,7 +9,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // incoming HTTP requests on said connection.
 
     let args = cli::Args::parse();
-    let project_handler = args.get_project_manager().await;
+    if args.schema_only() {
+        println!("{:#}", mars_config::config_schema());
+        return Ok(());
+    }
     let addr = args.get_addr();
 
     simple_logger::SimpleLogger::new()
@@ -17,5 +20,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with_level(log::LevelFilter::Info)
         .init()?;
 
-    start_server(addr, project_handler).await
+    // encrypted auth params, when master key is given through environment
+    if let Some(key) = mars_config::MasterKey::from_env()? {
+        mars_config::register_master_key(key);
+    }
+    // `${vault:..}` secret references, when vault is configured through environment
+    if let Some(config) = mars_rover::vault::VaultConfig::from_env() {
+        mars_rover::vault::register(config?);
+    }
+
+    // validate builds every subproject and exits, eager does so before serving
+    if args.validate_only() || args.eager {
+        if !args.validate().await {
+            std::process::exit(1);
+        }
+        if args.validate_only() {
+            return Ok(());
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    if args.admin_only {
+        let options = ServerOptions {
+            trusted_proxies: args.get_trusted_proxies(),
+            admin: args.get_admin_api(None).await,
+            #[cfg(feature = "tls")]
+            tls_config: args.get_tls_config(),
+            ..Default::default()
+        };
+        return mars_rover::start_admin_server(addr, options).await;
+    }
+
+    let project_handler = args.get_project_manager().await;
+
+    let options = ServerOptions {
+        trusted_proxies: args.get_trusted_proxies(),
+        #[cfg(feature = "oidc")]
+        oidc: args.get_oidc_client(),
+        #[cfg(feature = "sql")]
+        admin: args.get_admin_api(Some(&project_handler)).await,
+        #[cfg(feature = "sql")]
+        admin_addr: args.admin_addr,
+        #[cfg(feature = "tls")]
+        tls_config: args.get_tls_config(),
+    };
+    start_server(addr, project_handler, options).await
 }
@@ -0,0 +1,181 @@
+//! Source address of requests.
+//!
+//! When mars-rover runs behind load balancers or the Azure Functions host, the peer address is the
+//! one of the proxy. Proxies listed in [`TrustedProxies`] are looked through by walking the
+//! `X-Forwarded-For` or `Forwarded` chain from right to left, until an untrusted address is found.
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use http::{header::FORWARDED, HeaderMap};
+use mars_config::{IpNet, MarsError};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// `ClientIp` is the resolved source address of a request, added to the request's extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Header trusted proxies report the addresses they forward for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ForwardedHeader {
+    #[default]
+    XForwardedFor,
+    Forwarded,
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = MarsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            X_FORWARDED_FOR => Ok(ForwardedHeader::XForwardedFor),
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            _ => Err(MarsError::ServiceConfigError(format!(
+                "forwarded header should be `x-forwarded-for` or `forwarded`, not `{value}`"
+            ))),
+        }
+    }
+}
+
+/// Proxies whose forwarding headers are trusted. By default no proxy is trusted and the peer
+/// address is the source address.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    pub proxies: Vec<IpNet>,
+    pub header: ForwardedHeader,
+}
+
+impl TrustedProxies {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// source address of a request received from `peer`
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(client) {
+            return client;
+        }
+        for hop in self.hops(headers).into_iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = ip;
+                    if !self.is_trusted(ip) {
+                        break;
+                    }
+                }
+                // chain can't be followed further, trusted proxy is the closest known address
+                None => break,
+            }
+        }
+        client
+    }
+
+    /// addresses of forwarding chain, left most is the farthest
+    fn hops(&self, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+        let name = match self.header {
+            ForwardedHeader::XForwardedFor => X_FORWARDED_FOR,
+            ForwardedHeader::Forwarded => FORWARDED.as_str(),
+        };
+        let mut hops = vec![];
+        for value in headers.get_all(name) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => {
+                    hops.push(None);
+                    continue;
+                }
+            };
+            for element in value.split(',') {
+                let hop = match self.header {
+                    ForwardedHeader::XForwardedFor => parse_node(element),
+                    ForwardedHeader::Forwarded => element
+                        .split(';')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                        .and_then(|(_, node)| parse_node(node)),
+                };
+                hops.push(hop);
+            }
+        }
+        hops
+    }
+}
+
+/// parses `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`, `"[2001:db8::1]:80"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = IpAddr::from_str(node) {
+        return Some(ip);
+    }
+    if let Ok(addr) = SocketAddr::from_str(node) {
+        return Some(addr.ip());
+    }
+    IpAddr::from_str(node.trim_start_matches('[').trim_end_matches(']')).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use http::HeaderMap;
+
+    use super::{ForwardedHeader, TrustedProxies};
+
+    fn header_map(name: &'static str, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_untrusted_peer() {
+        let proxies = TrustedProxies::default();
+        let headers = header_map("x-forwarded-for", &["1.1.1.1"]);
+        assert_eq!(
+            proxies.client_ip("10.0.0.1".parse().unwrap(), &headers),
+            "10.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_x_forwarded_for() {
+        let proxies = TrustedProxies {
+            proxies: vec!["10.0.0.0/8".parse().unwrap(), "127.0.0.1/32".parse().unwrap()],
+            header: ForwardedHeader::XForwardedFor,
+        };
+        // spoofed left most entry is ignored, 2.2.2.2 is the address our proxies saw
+        let headers = header_map(
+            "x-forwarded-for",
+            &["6.6.6.6, 2.2.2.2:51234", "10.0.0.5"],
+        );
+        assert_eq!(
+            proxies.client_ip("127.0.0.1".parse().unwrap(), &headers),
+            "2.2.2.2".parse::<std::net::IpAddr>().unwrap()
+        );
+        // chain is not followed past an unparsable entry
+        let headers = header_map("x-forwarded-for", &["2.2.2.2, garbage, 10.0.0.5"]);
+        assert_eq!(
+            proxies.client_ip("127.0.0.1".parse().unwrap(), &headers),
+            "10.0.0.5".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let proxies = TrustedProxies {
+            proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            header: "Forwarded".parse().unwrap(),
+        };
+        let headers = header_map(
+            "forwarded",
+            &[r#"for=192.0.2.60;proto=http, for="[2001:db8:cafe::17]:4711";by=10.0.0.2"#],
+        );
+        assert_eq!(
+            proxies.client_ip("10.0.0.2".parse().unwrap(), &headers),
+            "2001:db8:cafe::17".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+}
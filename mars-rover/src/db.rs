@@ -2,17 +2,22 @@
 /// It includes structs for managing projects and services, as well as functions for retrieving project managers and database connections.
 use crate::{
     auth::{get_auth_service, ProxyService},
-    project::AuthToken,
+    project::{AuthToken, Grant},
+    quota::{Metered, UsageTracker, FLUSH_INTERVAL},
+    signature::SignatureVerifier,
 };
 use dashmap::{mapref::one::RefMut, DashMap};
 use mars_entity::user;
 use sea_orm::{ColumnTrait, Database, DatabaseConnection, EntityTrait, QueryFilter};
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{error::Error, str::FromStr};
 
-use crate::project::{AuthProjectRequestHandler, ProjectManager};
-use mars_config::{MarsError, ServiceConfig};
+use crate::project::{AuthProjectRequestHandler, ConfigError, ProjectManager};
+use mars_config::{
+    secrets_generation, ClientIdentity, IpRules, MarsError, TokenSource,
+};
 
 /// Represents a project in the database.
 #[derive(Clone)]
@@ -21,6 +26,11 @@ pub(crate) struct DbProject {
     project_id: i32,
     services: DashMap<String, ProxyService>,
     needs_auth: bool,
+    token_sources: Vec<TokenSource>,
+    ip_rules: IpRules,
+    signature_verifier: Option<Arc<SignatureVerifier>>,
+    /// project row, subprojects inherit its defaults
+    project: mars_entity::project::Model,
     db_con: DatabaseConnection,
 }
 
@@ -32,10 +42,10 @@ impl AuthProjectRequestHandler for DbProject {
     }
 
     /// Retrieves the service associated with the given path.
-    async fn get_service(
-        &self,
+    async fn get_service<'a>(
+        &'a self,
         path: String,
-    ) -> Result<Option<RefMut<String, ProxyService>>, Box<dyn Error>> {
+    ) -> Result<Option<RefMut<'a, String, ProxyService>>, Box<dyn Error>> {
         if self.services.contains_key(&path) {
             Ok(self.services.get_mut(&path))
         } else {
@@ -47,16 +57,10 @@ impl AuthProjectRequestHandler for DbProject {
                 .await?
             {
                 Some(subproject) => {
-                    let config = ServiceConfig {
-                        url: subproject.url,
-                        method: subproject.method.0,
-                        query_params: subproject.query_params.0,
-                        headers: subproject.headers.0,
-                        auth: subproject.auth.0,
-                        params: subproject.params.0,
-                    };
-                    println!("config is {:?}", config);
-                    match get_auth_service(config) {
+                    match subproject
+                        .inherited_config(&self.project)
+                        .and_then(get_auth_service)
+                    {
                         Ok(res) => {
                             self.services.insert(path.clone(), res);
                             Ok(self.services.get_mut(&path))
@@ -79,6 +83,21 @@ impl AuthProjectRequestHandler for DbProject {
     async fn auth_configured(&self) -> bool {
         self.needs_auth
     }
+
+    /// Token sources configured for the project, `avalanche-token` header when not configured.
+    async fn token_sources(&self) -> Vec<TokenSource> {
+        self.token_sources.clone()
+    }
+
+    /// Source addresses the project accepts requests from, any when not configured.
+    async fn ip_rules(&self) -> IpRules {
+        self.ip_rules.clone()
+    }
+
+    /// Verifier of signed requests, when the project requires them.
+    async fn signature_verifier(&self) -> Option<Arc<SignatureVerifier>> {
+        self.signature_verifier.clone()
+    }
 }
 
 /// Represents a project manager that interacts with the database.
@@ -86,6 +105,9 @@ impl AuthProjectRequestHandler for DbProject {
 pub(crate) struct DbProjectManager {
     db_conn: DatabaseConnection,
     projects: DashMap<String, Arc<Box<dyn AuthProjectRequestHandler>>>,
+    usage: Arc<UsageTracker>,
+    /// secrets generation cached projects were built in
+    generation: Arc<AtomicU64>,
 }
 
 #[async_trait::async_trait]
@@ -95,6 +117,11 @@ impl ProjectManager for DbProjectManager {
         &self,
         project_key: String,
     ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>> {
+        let generation = secrets_generation();
+        if self.generation.swap(generation, Ordering::AcqRel) != generation {
+            // services of cached projects hold secrets that were rotated since
+            self.projects.clear();
+        }
         match self.projects.get_mut(&project_key) {
             Some(service) => Ok(Some(service.clone())),
             None => {
@@ -110,6 +137,17 @@ impl ProjectManager for DbProjectManager {
                             project_id: project.id,
                             services: DashMap::default(),
                             needs_auth: project.needs_auth,
+                            token_sources: project
+                                .token_sources
+                                .clone()
+                                .map(|x| x.0)
+                                .unwrap_or_else(|| vec![TokenSource::default()]),
+                            ip_rules: project.ip_rules.clone().map(|x| x.0).unwrap_or_default(),
+                            signature_verifier: project
+                                .signature
+                                .clone()
+                                .map(|x| Arc::new(SignatureVerifier::new(x.0))),
+                            project,
                             db_con: self.db_conn.clone(),
                         };
                         self.projects
@@ -123,81 +161,273 @@ impl ProjectManager for DbProjectManager {
     }
 
     async fn exists(&self, token: &AuthToken, project_index: &str) -> bool {
+        self.grant(token, project_index).await.is_some()
+    }
+
+    async fn identity_exists(&self, identity: &ClientIdentity, project_index: &str) -> bool {
+        self.identity_grant(identity, project_index).await.is_some()
+    }
+
+    /// user tokens carry policy of user's grant on the project (direct or through a group),
+    /// project tokens their own policy. quotas of token and user both apply, and the token may be
+    /// bound to source addresses
+    async fn grant(&self, token: &AuthToken, project_index: &str) -> Option<Grant> {
         use mars_entity::authtoken;
         use mars_entity::project;
-        use mars_entity::user_project;
 
         let (token_type, auth_token) = match token.0.split_once(":") {
             Some((token_type, auth_token)) => (token_type, auth_token),
-            None => return false,
+            None => return None,
         };
         let auth_token = match uuid::Uuid::from_str(auth_token) {
             Ok(uuid) => uuid,
-            _ => return false,
+            _ => return None,
         };
-        match token_type {
+        let auth_token_obj = match authtoken::Entity::find()
+            .filter(authtoken::Column::AuthToken.eq(auth_token))
+            .one(&self.db_conn)
+            .await
+        {
+            Ok(Some(auth_token_obj)) => auth_token_obj,
+            Ok(None) => {
+                log::error!("no authtoken present in db");
+                return None;
+            }
+            Err(err) => {
+                log::error!("unable get data {}", err);
+                return None;
+            }
+        };
+        let mut grant = match token_type {
             "user" => {
-                // SELECT "user_projects"."id", "user_projects"."user_id", "user_projects"."project_id", "user_projects"."permissions" FROM "user_projects" INNER JOIN "project" ON "user_projects"."project_id" = "project"."id" INNER JOIN "users" ON "user_projects"."user_id" = "users"."id" INNER JOIN "authtoken" ON "authtoken"."user_id" = "user_projects"."id" WHERE "project"."index" = 'aviko' AND "authtoken"."auth_token" = '751d5169-204b-4c0f-9cbe-02abc6f5deb8';
-                match user_project::Entity::find()
-                    .filter(project::Column::Index.eq(project_index))
-                    .inner_join(project::Entity)
-                    .inner_join(user::Entity)
-                    .inner_join(authtoken::Entity)
-                    .filter(authtoken::Column::AuthToken.eq(auth_token))
-                    .one(&self.db_conn)
-                    .await
-                {
-                    Ok(Some(user_project_obj)) => {
-                        println!("user_project_obj is {:?}", user_project_obj);
-                        let execute = mars_entity::authtoken::AuthTokenPermissions::Execute as u8;
-                        (user_project_obj.permissions as u8 & execute) == execute
-                    }
+                let user_id = auth_token_obj.user_id?;
+                let user = match user::Entity::find_by_id(user_id).one(&self.db_conn).await {
+                    Ok(Some(user)) => user,
                     Ok(None) => {
-                        log::error!("no authtoken present in db");
-                        false
+                        log::error!("user {} of authtoken is not present in db", user_id);
+                        return None;
                     }
                     Err(err) => {
                         log::error!("unable get data {}", err);
-                        false
+                        return None;
                     }
+                };
+                if user.user_type == user::UserType::Admin as i32 {
+                    log::warn!("token of admin user {} can't be used for apis", user.id);
+                    return None;
                 }
+                let mut grant = self.user_grant(vec![user.id], project_index).await?;
+                grant.quotas.extend(user_quota(&user));
+                grant
             }
             "project" => {
-                match authtoken::Entity::find()
-                    .filter(authtoken::Column::AuthToken.eq(auth_token))
-                    .inner_join(project::Entity)
+                match project::Entity::find()
                     .filter(project::Column::Index.eq(project_index))
                     .one(&self.db_conn)
                     .await
                 {
-                    Ok(Some(auth_token_obj)) => {
-                        let execute = mars_entity::authtoken::AuthTokenPermissions::Execute as u8;
-                        (auth_token_obj.permissions as u8 & execute) == execute
-                    }
-                    Ok(None) => {
-                        log::error!("no authtoken present in db");
-                        false
-                    }
+                    Ok(Some(project_obj)) if auth_token_obj.project_id == Some(project_obj.id) => {}
+                    Ok(_) => return None,
                     Err(err) => {
                         log::error!("unable get data {}", err);
-                        false
+                        return None;
                     }
                 }
+                let execute = mars_entity::authtoken::AuthTokenPermissions::Execute as u8;
+                if (auth_token_obj.permissions as u8 & execute) != execute {
+                    return None;
+                }
+                Grant {
+                    policy: auth_token_obj.policy.map(|x| x.0).unwrap_or_default(),
+                    ..Default::default()
+                }
             }
-            _ => false,
+            _ => return None,
+        };
+        if let Some(source_cidrs) = auth_token_obj.source_cidrs {
+            grant.source_cidrs = source_cidrs.0;
         }
+        if let Some(quota) = auth_token_obj.quota {
+            grant.quotas.push(Metered {
+                principal: format!("token:{}", auth_token_obj.id),
+                quota: quota.0,
+            });
+        }
+        Some(grant)
+    }
+
+    async fn identity_grant(
+        &self,
+        identity: &ClientIdentity,
+        project_index: &str,
+    ) -> Option<Grant> {
+        self.users_grant(
+            user::Column::CertIdentity.is_in(identity.candidates()),
+            project_index,
+        )
+        .await
+    }
+
+    /// users are matched on `user_email`
+    async fn email_grant(&self, email: &str, project_index: &str) -> Option<Grant> {
+        self.users_grant(user::Column::UserEmail.eq(email), project_index)
+            .await
+    }
+
+    async fn consume_quota(&self, grant: &Grant) -> Option<u64> {
+        self.usage.consume(&grant.quotas).await
+    }
+
+    async fn invalidate(&self, project_index: &str) {
+        self.projects.remove(project_index);
     }
 }
 
+impl DbProjectManager {
+    /// grant of users matching `condition`, along with their quotas. admins are never matched
+    async fn users_grant(
+        &self,
+        condition: sea_orm::sea_query::SimpleExpr,
+        project_index: &str,
+    ) -> Option<Grant> {
+        let users = match user::Entity::find()
+            .filter(condition)
+            .filter(user::Column::UserType.ne(user::UserType::Admin as i32))
+            .all(&self.db_conn)
+            .await
+        {
+            Ok(users) if !users.is_empty() => users,
+            Ok(_) => return None,
+            Err(err) => {
+                log::error!("unable get data {}", err);
+                return None;
+            }
+        };
+        let mut grant = self
+            .user_grant(users.iter().map(|x| x.id).collect(), project_index)
+            .await?;
+        grant.quotas.extend(users.iter().filter_map(user_quota));
+        Some(grant)
+    }
+
+    /// grant on project given directly to any of the users, or else through their groups
+    async fn user_grant(&self, user_ids: Vec<i32>, project_index: &str) -> Option<Grant> {
+        use mars_entity::project;
+        use mars_entity::user_project;
+
+        match user_project::Entity::find()
+            .inner_join(project::Entity)
+            .filter(project::Column::Index.eq(project_index))
+            .filter(user_project::Column::UserId.is_in(user_ids.clone()))
+            .all(&self.db_conn)
+            .await
+        {
+            Ok(user_projects) => {
+                let execute = mars_entity::authtoken::AuthTokenPermissions::Execute as u8;
+                if let Some(user_project_obj) = user_projects
+                    .into_iter()
+                    .find(|user_project_obj| (user_project_obj.permissions as u8 & execute) == execute)
+                {
+                    return Some(Grant {
+                        policy: user_project_obj.policy.map(|x| x.0).unwrap_or_default(),
+                        ..Default::default()
+                    });
+                }
+            }
+            Err(err) => {
+                log::error!("unable get data {}", err);
+                return None;
+            }
+        }
+        self.group_grant(user_ids, project_index).await
+    }
+
+    /// grant on project through any group the users are member of
+    async fn group_grant(&self, user_ids: Vec<i32>, project_index: &str) -> Option<Grant> {
+        use mars_entity::group_member;
+        use mars_entity::group_project;
+        use mars_entity::project;
+
+        match group_project::Entity::find()
+            .inner_join(project::Entity)
+            .inner_join(group_member::Entity)
+            .filter(project::Column::Index.eq(project_index))
+            .filter(group_member::Column::UserId.is_in(user_ids))
+            .all(&self.db_conn)
+            .await
+        {
+            Ok(group_projects) => {
+                let execute = mars_entity::authtoken::AuthTokenPermissions::Execute as u8;
+                group_projects
+                    .into_iter()
+                    .find(|group_project_obj| {
+                        (group_project_obj.permissions as u8 & execute) == execute
+                    })
+                    .map(|group_project_obj| Grant {
+                        policy: group_project_obj.policy.map(|x| x.0).unwrap_or_default(),
+                        ..Default::default()
+                    })
+            }
+            Err(err) => {
+                log::error!("unable get data {}", err);
+                None
+            }
+        }
+    }
+}
+
+fn user_quota(user: &user::Model) -> Option<Metered> {
+    user.quota.as_ref().map(|quota| Metered {
+        principal: format!("user:{}", user.id),
+        quota: quota.0,
+    })
+}
+
+/// Builds service of every subproject in the database, returning each one that doesn't build.
+pub async fn validate_db_config(url: &str) -> Result<Vec<ConfigError>, Box<dyn Error>> {
+    use mars_entity::{project, subproject};
+    let db = Database::connect(url).await?;
+    let projects = project::Entity::find().all(&db).await?;
+    let subprojects = subproject::Entity::find().all(&db).await?;
+    let mut errors = vec![];
+    for subproject in subprojects {
+        let project = projects.iter().find(|x| x.id == subproject.project_id);
+        let config = match project {
+            Some(project) => subproject.inherited_config(project),
+            None => Ok(subproject.service_config()),
+        };
+        if let Err(err) = config.and_then(get_auth_service) {
+            let project = project
+                .map(|x| x.index.clone())
+                .unwrap_or_else(|| subproject.project_id.to_string());
+            errors.push(ConfigError::new(
+                format!("{project}/{}", subproject.index),
+                &err,
+            ));
+        }
+    }
+    Ok(errors)
+}
+
 /// Retrieves a project manager for the database connection.
 pub async fn get_db_project_manager(
     url: &str,
 ) -> Result<Arc<Box<dyn ProjectManager>>, Box<dyn Error>> {
     let db = Database::connect(url).await?;
 
+    let usage = Arc::new(UsageTracker::with_db(db.clone()));
+    let flusher = usage.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            flusher.flush().await;
+        }
+    });
     let project_manager = DbProjectManager {
         db_conn: db,
         projects: DashMap::default(),
+        usage,
+        generation: Default::default(),
     };
     Ok(Arc::new(Box::new(project_manager)))
 }
@@ -205,9 +435,149 @@ pub async fn get_db_project_manager(
 #[cfg(test)]
 mod test {
     use super::DbProjectManager;
-    use crate::project::ProjectManager;
+    use crate::project::{AuthToken, ProjectManager};
     use dashmap::DashMap;
-    use sea_orm::Database;
+    use mars_entity::{authtoken, group, group_member, group_project, project, user};
+    use sea_orm::{
+        ActiveModelTrait, ActiveValue::Set, ConnectionTrait, Database, EntityTrait, Schema,
+    };
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_group_grant() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        macro_rules! create_table {
+            ($entity:expr) => {
+                db.execute(
+                    db.get_database_backend()
+                        .build(&schema.create_table_from_entity($entity)),
+                )
+                .await
+                .unwrap();
+            };
+        }
+        create_table!(project::Entity);
+        create_table!(user::Entity);
+        create_table!(mars_entity::user_project::Entity);
+        create_table!(authtoken::Entity);
+        create_table!(group::Entity);
+        create_table!(group_member::Entity);
+        create_table!(group_project::Entity);
+
+        project::Entity::insert(project::ActiveModel {
+            id: Set(1),
+            index: Set("aviko".to_string()),
+            needs_auth: Set(true),
+            token_sources: Set(None),
+            ip_rules: Set(None),
+            signature: Set(None),
+            defaults: Set(None),
+            auth_profiles: Set(None),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+        user::Entity::insert(user::ActiveModel {
+            id: Set(7),
+            user_type: Set(2),
+            user_name: Set("neptune".to_string()),
+            user_email: Set("neptune@example.com".to_string()),
+            cert_identity: Set(Some("san:neptune.internal".to_string())),
+            quota: Set(Some(user::Quota(mars_config::Quota {
+                daily: Some(10),
+                monthly: None,
+            }))),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+        let token = Uuid::new_v4();
+        authtoken::Entity::insert(authtoken::ActiveModel {
+            id: Set(1),
+            project_id: Set(None),
+            user_id: Set(Some(7)),
+            auth_token: Set(token),
+            permissions: Set(authtoken::AuthTokenPermissions::Execute as i32),
+            policy: Set(None),
+            quota: Set(None),
+            source_cidrs: Set(None),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let project_manager = DbProjectManager {
+            db_conn: db.clone(),
+            projects: DashMap::default(),
+            usage: Default::default(),
+            generation: Default::default(),
+        };
+        let token = AuthToken(format!("user:{token}"));
+        assert!(project_manager.grant(&token, "aviko").await.is_none());
+
+        group::Entity::insert(group::ActiveModel {
+            id: Set(1),
+            name: Set("payments".to_string()),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+        group_member::Entity::insert(group_member::ActiveModel {
+            id: Set(1),
+            group_id: Set(1),
+            user_id: Set(7),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+        group_project::Entity::insert(group_project::ActiveModel {
+            id: Set(1),
+            group_id: Set(1),
+            project_id: Set(1),
+            permissions: Set(authtoken::AuthTokenPermissions::Execute as i32),
+            policy: Set(Some(group_project::Policy(
+                serde_json::from_str(r#"{"allow": [{"subproject": "json"}]}"#).unwrap(),
+            ))),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let grant = project_manager.grant(&token, "aviko").await.unwrap();
+        assert!(grant.policy.permits("json", "get", "GET"));
+        assert!(!grant.policy.permits("xml", "get", "GET"));
+        assert_eq!(grant.quotas.len(), 1);
+        assert_eq!(grant.quotas[0].principal, "user:7");
+        assert!(project_manager.grant(&token, "other").await.is_none());
+
+        let identity = mars_config::ClientIdentity {
+            fingerprint: "00".to_string(),
+            subject: "CN=neptune".to_string(),
+            sans: vec!["neptune.internal".to_string()],
+        };
+        assert!(project_manager.identity_exists(&identity, "aviko").await);
+        assert!(project_manager
+            .email_grant("neptune@example.com", "aviko")
+            .await
+            .is_some());
+        assert!(project_manager
+            .email_grant("pluto@example.com", "aviko")
+            .await
+            .is_none());
+
+        // admins only reach admin api, even when granted a project
+        let mut admin: user::ActiveModel = user::Entity::find_by_id(7)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap()
+            .into();
+        admin.user_type = Set(user::UserType::Admin as i32);
+        admin.update(&db).await.unwrap();
+        assert!(project_manager.grant(&token, "aviko").await.is_none());
+        assert!(!project_manager.identity_exists(&identity, "aviko").await);
+    }
 
     #[ignore]
     #[tokio::test]
@@ -221,6 +591,8 @@ mod test {
         let project_manager = DbProjectManager {
             db_conn: db,
             projects: DashMap::default(),
+            usage: Default::default(),
+            generation: Default::default(),
         };
         let project = project_manager.get_project("test".to_string()).await;
         match project {
@@ -1,22 +1,27 @@
 use crate::auth::{get_auth_service, ProxyService};
-use crate::project::AuthToken;
+use crate::project::{AuthToken, Grant};
 
 use async_trait::async_trait;
 use dashmap::{mapref::one::RefMut, DashMap};
 
-use http::Request;
-use hyper::Client;
-use hyper_tls::HttpsConnector;
-use serde_json::Value;
+use http::HeaderMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
-use std::fs;
-use std::io::Read;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use std::{convert::TryFrom, error::Error};
 
-use crate::project::{AuthProjectRequestHandler, ProjectManager};
-use mars_config::{MarsError, ServiceConfig};
+use crate::project::{AuthProjectRequestHandler, ConfigError, ProjectManager};
+use crate::quota::{Metered, UsageTracker};
+use crate::remote::Link;
+use crate::signature::SignatureVerifier;
+use mars_config::{
+    secrets_generation, AccessPolicy, AuthProfiles, ClientIdentity, ConfigFormat, IpNet, IpRules,
+    MarsError, PartialServiceConfig, Quota, ServiceConfig, TokenSource,
+};
 
 /// `FileBasedProject` represents a project that is configured based on a file.
 ///
@@ -24,53 +29,188 @@ use mars_config::{MarsError, ServiceConfig};
 /// a name, and a map of services. The `try_from` method is used to create an instance of `FileBasedProject`
 /// from a JSON configuration.
 #[derive(Clone)]
-struct FileBasedProject {
+pub(crate) struct FileBasedProject {
     name: String,
     service_config_map: HashMap<String, ServiceConfig>,
     services: DashMap<String, ProxyService>,
     needs_auth: bool,
+    token_sources: Vec<TokenSource>,
+    ip_rules: IpRules,
+    signature_verifier: Option<Arc<SignatureVerifier>>,
 }
 
 #[async_trait]
 impl AuthProjectRequestHandler for FileBasedProject {
     async fn is_project(&self, path: &str) -> bool {
-        return self.name == path;
+        self.name == path
     }
 
     async fn auth_configured(&self) -> bool {
         self.needs_auth
     }
 
-    async fn get_service(
-        &self,
+    async fn token_sources(&self) -> Vec<TokenSource> {
+        self.token_sources.clone()
+    }
+
+    async fn ip_rules(&self) -> IpRules {
+        self.ip_rules.clone()
+    }
+
+    async fn signature_verifier(&self) -> Option<Arc<SignatureVerifier>> {
+        self.signature_verifier.clone()
+    }
+
+    async fn get_service<'a>(
+        &'a self,
         path: String,
-    ) -> Result<Option<RefMut<String, ProxyService>>, Box<dyn Error>> {
+    ) -> Result<Option<RefMut<'a, String, ProxyService>>, Box<dyn Error>> {
         if self.services.contains_key(&path) {
             Ok(self.services.get_mut(&path))
         } else if let Some(config) = self.service_config_map.get(&path).cloned() {
-            if let Ok(res) = get_auth_service(config) {
-                self.services.insert(path.clone(), res);
-                Ok(self.services.get_mut(&path))
-            } else {
-                // TODO
-                // need to handle error scenarios
-                Ok(None)
-            }
+            let service = get_auth_service(config).map_err(|err| {
+                MarsError::ServiceConfigError(format!("subproject `{path}` is not valid: {err}"))
+            })?;
+            self.services.insert(path.clone(), service);
+            Ok(self.services.get_mut(&path))
         } else {
             Ok(None)
         }
     }
 }
 
+impl FileBasedProject {
+    /// builds service of every subproject up front, instead of on its first request
+    fn build_services(&self) -> Result<(), MarsError> {
+        for (key, config) in &self.service_config_map {
+            let service = get_auth_service(config.clone()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("subproject `{key}` is not valid: {err}"))
+            })?;
+            self.services.insert(key.clone(), service);
+        }
+        Ok(())
+    }
+
+    /// builds every subproject of `project_config`, returning each error of the project and of
+    /// its subprojects instead of stopping at the first one
+    pub(crate) fn validate(project_key: &str, mut project_config: Value) -> Vec<ConfigError> {
+        let mut errors = vec![];
+        // subprojects are checked one by one, rest of project on its own
+        let subprojects = match project_config.get_mut("subprojects") {
+            Some(Value::Object(subprojects)) => std::mem::take(subprojects),
+            _ => Default::default(),
+        };
+        // defaults that are not valid are reported with project, subprojects go without them
+        let (defaults, profiles) = project_config
+            .as_object()
+            .and_then(|config| FileBasedProject::inheritance(config).ok())
+            .unwrap_or_default();
+        if let Err(err) = FileBasedProject::try_from(project_config) {
+            errors.push(ConfigError::new(project_key, &err));
+        }
+        for (key, config) in subprojects {
+            let result = serde_json::from_value::<PartialServiceConfig>(config)
+                .map_err(|err| {
+                    MarsError::ServiceConfigError(format!("serviceconfig is not parsable: {err}"))
+                })
+                .and_then(|config| config.inherit(&defaults, &profiles))
+                .and_then(get_auth_service);
+            if let Err(err) = result {
+                errors.push(ConfigError::new(format!("{project_key}/{key}"), &err));
+            }
+        }
+        errors
+    }
+
+    /// `defaults` and `auth_profiles` of project, subprojects inherit from
+    fn inheritance(
+        project_config: &Map<String, Value>,
+    ) -> Result<(PartialServiceConfig, AuthProfiles), MarsError> {
+        let defaults = match project_config.get("defaults") {
+            Some(defaults) => serde_json::from_value(defaults.clone()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("defaults is not parsable: {err}"))
+            })?,
+            None => PartialServiceConfig::default(),
+        };
+        let profiles = match project_config.get("auth_profiles") {
+            Some(profiles) => serde_json::from_value(profiles.clone()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("auth_profiles is not parsable: {err}"))
+            })?,
+            None => AuthProfiles::default(),
+        };
+        Ok((defaults, profiles))
+    }
+}
+
+/// Value of a tokens file entry.
+///
+/// Either index of the project, or index of the project along with an access policy and quota:
+///
+/// ```json
+/// {
+///     "project:1": "aviko",
+///     "project:2": {"project": "aviko", "allow": [{"subproject": "json", "methods": ["GET"]}]},
+///     "project:3": {"project": "aviko", "quota": {"daily": 1000}},
+///     "project:4": {"project": "aviko", "source_cidrs": ["10.0.0.0/8"]}
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum TokenGrant {
+    Project(String),
+    Restricted {
+        project: String,
+        #[serde(default)]
+        quota: Option<Quota>,
+        #[serde(default)]
+        source_cidrs: Vec<IpNet>,
+        #[serde(flatten)]
+        policy: AccessPolicy,
+    },
+}
+
+impl TokenGrant {
+    /// grant on `project_index`, usage is counted against `principal`
+    pub(crate) fn grant_for(&self, principal: &str, project_index: &str) -> Option<Grant> {
+        match self {
+            TokenGrant::Project(project) if project == project_index => Some(Grant::default()),
+            TokenGrant::Restricted {
+                project,
+                quota,
+                source_cidrs,
+                policy,
+            } if project == project_index => Some(Grant {
+                policy: policy.clone(),
+                quotas: quota
+                    .map(|quota| Metered {
+                        principal: principal.to_string(),
+                        quota,
+                    })
+                    .into_iter()
+                    .collect(),
+                source_cidrs: source_cidrs.clone(),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
 /// `FileProjectManager` is responsible for managing `FileBasedProject`s.
 ///
 /// It contains a map of projects, where each project is an instance of a type that implements the
 /// `AuthProjectRequestHandler` trait. The `try_from` method is used to create an instance of `FileProjectManager`
 /// from a JSON configuration.
+///
+/// Keys of the tokens file that look like certificate identities (`sha256:..`, `subject:..`, `san:..`)
+/// are kept apart from tokens, so they can only be matched by a verified client certificate.
+/// Usage against quotas is only kept in memory.
 #[derive(Clone)]
 pub struct FileProjectManager {
     projects: DashMap<String, Arc<Box<dyn AuthProjectRequestHandler>>>,
-    pub(crate) project_tokens: DashMap<AuthToken, String>,
+    project_tokens: DashMap<AuthToken, TokenGrant>,
+    project_identities: DashMap<String, TokenGrant>,
+    usage: Arc<UsageTracker>,
 }
 
 impl FileProjectManager {
@@ -94,12 +234,33 @@ impl ProjectManager for FileProjectManager {
     }
 
     async fn exists(&self, auth_token: &AuthToken, project_index: &str) -> bool {
-        let allowed_project = self.project_tokens.get(&auth_token);
-        if let Some(allowed_project) = allowed_project {
-            *allowed_project == project_index
-        } else {
-            false
-        }
+        self.grant(auth_token, project_index).await.is_some()
+    }
+
+    async fn identity_exists(&self, identity: &ClientIdentity, project_index: &str) -> bool {
+        self.identity_grant(identity, project_index).await.is_some()
+    }
+
+    async fn grant(&self, auth_token: &AuthToken, project_index: &str) -> Option<Grant> {
+        self.project_tokens
+            .get(auth_token)
+            .and_then(|allowed| allowed.grant_for(&format!("token:{}", auth_token.0), project_index))
+    }
+
+    async fn identity_grant(
+        &self,
+        identity: &ClientIdentity,
+        project_index: &str,
+    ) -> Option<Grant> {
+        identity.candidates().iter().find_map(|candidate| {
+            self.project_identities
+                .get(candidate)
+                .and_then(|allowed| allowed.grant_for(candidate, project_index))
+        })
+    }
+
+    async fn consume_quota(&self, grant: &Grant) -> Option<u64> {
+        self.usage.consume(&grant.quotas).await
     }
 }
 
@@ -116,6 +277,28 @@ impl TryFrom<Value> for FileBasedProject {
             .unwrap_or(&Value::Bool(true))
             .as_bool()
             .unwrap_or(true);
+        let token_sources = match project_config.get_mut("token_sources") {
+            Some(token_sources) => serde_json::from_value(token_sources.take()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("token_sources is not parsable: {err}"))
+            })?,
+            None => vec![TokenSource::default()],
+        };
+        let ip_rules = match project_config.get_mut("ip_rules") {
+            Some(ip_rules) => serde_json::from_value(ip_rules.take()).map_err(|err| {
+                MarsError::ServiceConfigError(format!("ip_rules is not parsable: {err}"))
+            })?,
+            None => IpRules::default(),
+        };
+        let signature_verifier = match project_config.get_mut("signature") {
+            Some(signature) => {
+                let scheme = serde_json::from_value(signature.take()).map_err(|err| {
+                    MarsError::ServiceConfigError(format!("signature is not parsable: {err}"))
+                })?;
+                Some(Arc::new(SignatureVerifier::new(scheme)))
+            }
+            None => None,
+        };
+        let (defaults, profiles) = FileBasedProject::inheritance(project_config)?;
         let service_map = DashMap::new();
         let mut service_config_map = HashMap::new();
         let sub_project_config = project_config
@@ -130,17 +313,27 @@ impl TryFrom<Value> for FileBasedProject {
 
         for (service_key, service_config_value) in sub_project_config.into_iter() {
             let service_config = service_config_value.take();
-            let service_config: ServiceConfig =
+            let service_config: PartialServiceConfig =
                 serde_json::from_value(service_config).map_err(|_| {
                     MarsError::ServiceConfigError(format!(
                         "serviceconfig for `{service_key}` is not parsable "
                     ))
                 })?;
+            let service_config = service_config
+                .inherit(&defaults, &profiles)
+                .map_err(|err| {
+                    MarsError::ServiceConfigError(format!(
+                        "serviceconfig for `{service_key}` is not valid: {err}"
+                    ))
+                })?;
             service_config_map.insert(service_key.to_string(), service_config);
         }
         Ok(FileBasedProject {
             service_config_map,
             needs_auth,
+            token_sources,
+            ip_rules,
+            signature_verifier,
             name: "no meaning as of now".to_string(),
             services: service_map,
         })
@@ -150,7 +343,14 @@ impl TryFrom<Value> for FileBasedProject {
 impl TryFrom<Value> for FileProjectManager {
     type Error = MarsError;
 
-    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        FileProjectManager::from_config(value, false)
+    }
+}
+
+impl FileProjectManager {
+    /// services are built when first requested, or right away when `eager`
+    fn from_config(mut value: Value, eager: bool) -> Result<Self, MarsError> {
         let all_config = value
             .as_object_mut()
             .ok_or_else(|| MarsError::ServiceConfigError("config is not object".to_string()))?;
@@ -158,64 +358,773 @@ impl TryFrom<Value> for FileProjectManager {
         for (project_key, project_config) in all_config {
             let project_config = project_config.take();
             let project = FileBasedProject::try_from(project_config)?;
+            if eager {
+                project.build_services().map_err(|err| {
+                    MarsError::ServiceConfigError(format!("project `{project_key}`: {err}"))
+                })?;
+            }
             let project: Box<dyn AuthProjectRequestHandler> = Box::new(project);
             projects.insert(project_key.to_string(), Arc::new(project));
         }
         Ok(FileProjectManager {
             projects,
             project_tokens: Default::default(),
+            project_identities: Default::default(),
+            usage: Default::default(),
         })
     }
 }
 
+/// builds every subproject of config, returning each one that doesn't build along with any
+/// project that doesn't parse. errors only when config or tokens can't be read or parsed at all
+pub async fn validate_file_config(
+    config: &Link,
+    overlay: Option<&Overlay>,
+    tokens: Option<&Link>,
+) -> Result<Vec<ConfigError>, MarsError> {
+    let sources = Sources::read(config, overlay, tokens).await?;
+    let (value, _) = sources.parse()?;
+    let projects = match value {
+        Value::Object(projects) => projects,
+        _ => return Err(MarsError::ServiceConfigError("config is not object".to_string())),
+    };
+    Ok(projects
+        .into_iter()
+        .flat_map(|(project_key, project_config)| {
+            FileBasedProject::validate(&project_key, project_config)
+        })
+        .collect())
+}
+
 pub async fn get_file_project_manager(
     path: PathBuf,
     tokens: Option<String>
 ) -> Result<Arc<Box<dyn ProjectManager>>, MarsError> {
-    let value: Value = json5::from_str(&get_as_string_from_link(path).await?)
-        .map_err(|err| MarsError::ServiceConfigError(format!("ran into error {}", err)))?;
-    let tokens: HashMap<String, String> = match tokens{
-        Some(path) => json5::from_str(&get_as_string_from_link(path.into()).await?)
-                .map_err(|err| MarsError::ServiceConfigError(format!("ran into error {}", err)))?,
-        None => HashMap::new(),
-    };
-    let mut project_manager = FileProjectManager::try_from(value)?;
-    project_manager.project_tokens.extend(tokens.into_iter().map(|(key, value)|( AuthToken(key), value)));
+    let config = Link::new(&path.to_string_lossy(), HeaderMap::new())?;
+    let tokens = tokens
+        .map(|tokens| Link::new(&tokens, HeaderMap::new()))
+        .transpose()?;
+    let sources = Sources::read(&config, None, tokens.as_ref()).await?;
+    Ok(Arc::new(Box::new(sources.project_manager(false)?)))
+}
+
+/// Same as [`get_file_project_manager`], but config and tokens are loaded again on `SIGHUP` and,
+/// when `interval` is given, whenever they change. See [`ReloadingProjectManager`]. Services are
+/// built right away when `eager`, instead of on their first request. `overlay`, when given,
+/// patches config, see [`mars_config::merge_patch`].
+pub async fn get_reloading_file_project_manager(
+    config: Link,
+    overlay: Option<Overlay>,
+    tokens: Option<Link>,
+    interval: Option<Duration>,
+    eager: bool,
+) -> Result<Arc<Box<dyn ProjectManager>>, MarsError> {
+    let project_manager =
+        Arc::new(ReloadingProjectManager::load(config, overlay, tokens, eager).await?);
+    project_manager.clone().watch(interval);
     Ok(Arc::new(Box::new(project_manager)))
 }
 
-async fn get_as_string_from_link(path: PathBuf) -> Result<String, MarsError> {
-    let body_str = if path.starts_with("http://") || path.starts_with("https://") {
-        let body = get_data_from_remote().await?;
-        String::from_utf8(body.to_vec()).map_err(|error| {
-            MarsError::ServiceConfigError(format!("unable to download, {error}"))
-        })?
-    } else {
-        let mut file = fs::File::open(path)
-            .map_err(|err| MarsError::ServiceConfigError(format!("ran into error {}", err)))?;
-        let mut body_str = String::new();
-        file.read_to_string(&mut body_str)
-            .map_err(|err| MarsError::ServiceConfigError(format!("ran into error {}", err)))?;
-        body_str
-    };
-    Ok(body_str)
+/// `Overlay` patches config for an environment, see [`mars_config::overlay_path`].
+pub struct Overlay {
+    pub environment: String,
+    pub link: Link,
+    /// whether config is served as it is when overlay doesn't exist, instead of failing to load
+    pub allow_missing: bool,
+}
+
+/// contents of config, its environment overlay and tokens, with format each is written in.
+/// overlay that doesn't exist is left out when it is allowed to be missing
+#[derive(PartialEq, Eq)]
+struct Sources {
+    config: (String, ConfigFormat),
+    overlay: Option<(String, ConfigFormat)>,
+    tokens: Option<(String, ConfigFormat)>,
+}
+
+impl Sources {
+    async fn read(
+        config: &Link,
+        overlay: Option<&Overlay>,
+        tokens: Option<&Link>,
+    ) -> Result<Self, MarsError> {
+        Ok(Sources {
+            config: (config.fetch().await?, config.format()),
+            overlay: match overlay {
+                Some(overlay) => match overlay.link.fetch_if_exists().await? {
+                    Some(contents) => Some((contents, overlay.link.format())),
+                    None if overlay.allow_missing => {
+                        log::warn!(
+                            "overlay {} of environment `{}` doesn't exist, config is served as it is",
+                            overlay.link,
+                            overlay.environment
+                        );
+                        None
+                    }
+                    None => {
+                        return Err(MarsError::ServiceConfigError(format!(
+                            "overlay {} of environment `{}` doesn't exist",
+                            overlay.link, overlay.environment
+                        )))
+                    }
+                },
+                None => None,
+            },
+            tokens: Sources::fetch(tokens).await?,
+        })
+    }
+
+    async fn fetch(link: Option<&Link>) -> Result<Option<(String, ConfigFormat)>, MarsError> {
+        match link {
+            Some(link) => Ok(Some((link.fetch().await?, link.format()))),
+            None => Ok(None),
+        }
+    }
+
+    fn parse(&self) -> Result<(Value, HashMap<String, TokenGrant>), MarsError> {
+        let (config, format) = &self.config;
+        let mut value: Value = format.parse(config)?;
+        if let Some((overlay, format)) = &self.overlay {
+            mars_config::merge_patch(&mut value, format.parse(overlay)?);
+        }
+        let tokens: HashMap<String, TokenGrant> = match &self.tokens {
+            Some((tokens, format)) => format.parse(tokens)?,
+            None => HashMap::new(),
+        };
+        Ok((value, tokens))
+    }
+
+    fn project_manager(&self, eager: bool) -> Result<FileProjectManager, MarsError> {
+        let (value, tokens) = self.parse()?;
+        let project_manager = FileProjectManager::from_config(value, eager)?;
+        for (key, value) in tokens.into_iter() {
+            if ClientIdentity::is_identity_key(&key) {
+                project_manager.project_identities.insert(key, value);
+            } else {
+                project_manager.project_tokens.insert(AuthToken(key), value);
+            }
+        }
+        Ok(project_manager)
+    }
+}
+
+/// how often [`ReloadingProjectManager`] checks whether secrets its services hold were rotated
+const ROTATION_TICK: Duration = Duration::from_secs(1);
+
+/// `ReloadingProjectManager` serves a [`FileProjectManager`] that is swapped for a new one when
+/// config or tokens change, or secrets its services hold are rotated.
+///
+/// A new config replaces the current one only once it parses and every one of its services
+/// builds, otherwise the error is logged and the current one stays. Each request is served
+/// start to end by the manager it started with, so requests in flight finish on old services.
+/// Usage against quotas is carried over. New managers are built in background, requests only
+/// read the current one.
+pub struct ReloadingProjectManager {
+    config: Link,
+    overlay: Option<Overlay>,
+    tokens: Option<Link>,
+    current: RwLock<Arc<FileProjectManager>>,
+    /// sources last loaded, or tried to
+    sources: tokio::sync::Mutex<Sources>,
+    /// secrets generation current manager was built in
+    generation: AtomicU64,
+}
+
+impl ReloadingProjectManager {
+    /// services of first config are built right away when `eager`, those of later ones always are
+    pub async fn load(
+        config: Link,
+        overlay: Option<Overlay>,
+        tokens: Option<Link>,
+        eager: bool,
+    ) -> Result<Self, MarsError> {
+        let generation = AtomicU64::new(secrets_generation());
+        let sources = Sources::read(&config, overlay.as_ref(), tokens.as_ref()).await?;
+        let current = sources.project_manager(eager)?;
+        Ok(ReloadingProjectManager {
+            config,
+            overlay,
+            tokens,
+            current: RwLock::new(Arc::new(current)),
+            sources: tokio::sync::Mutex::new(sources),
+            generation,
+        })
+    }
+
+    fn current(&self) -> Arc<FileProjectManager> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// builds current manager again when secrets its services hold were rotated
+    async fn rebuild_if_rotated(&self) {
+        let generation = secrets_generation();
+        if self.generation.swap(generation, Ordering::AcqRel) != generation {
+            self.reload_logged(true).await;
+        }
+    }
+
+    /// loads config and tokens again, when they differ from last attempt or `force` is set.
+    /// returns whether a new config is being served. remote sources that haven't changed since
+    /// last fetch respond with `304 Not Modified`
+    pub async fn reload(&self, force: bool) -> Result<bool, MarsError> {
+        let mut last = self.sources.lock().await;
+        let sources =
+            Sources::read(&self.config, self.overlay.as_ref(), self.tokens.as_ref()).await?;
+        if !force && sources == *last {
+            return Ok(false);
+        }
+        let result = sources.project_manager(true);
+        // broken config is not tried again until it changes
+        *last = sources;
+        let mut project_manager = result?;
+        project_manager.usage = self.current().usage.clone();
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(project_manager);
+        Ok(true)
+    }
+
+    /// reloads on `SIGHUP`, every `interval` when given and once secrets were rotated, for as
+    /// long as process runs
+    pub fn watch(self: Arc<Self>, interval: Option<Duration>) {
+        let project_manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(ROTATION_TICK);
+            loop {
+                ticks.tick().await;
+                project_manager.rebuild_if_rotated().await;
+            }
+        });
+        if let Some(interval) = interval {
+            let project_manager = self.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    ticks.tick().await;
+                    project_manager.reload_logged(false).await;
+                }
+            });
+        }
+        #[cfg(unix)]
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(err) => {
+                    log::error!("unable to listen for SIGHUP, {}", err);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                self.reload_logged(true).await;
+            }
+        });
+    }
+
+    async fn reload_logged(&self, force: bool) {
+        match self.reload(force).await {
+            Ok(true) => log::info!("reloaded config from {}", self.config),
+            Ok(false) => {}
+            Err(err) => log::error!(
+                "keeping current config, unable to reload {}: {}",
+                self.config,
+                err
+            ),
+        }
+    }
 }
 
-async fn get_data_from_remote() -> Result<hyper::body::Bytes, MarsError> {
-    let https = HttpsConnector::new();
-    let client = Client::builder().build::<_, hyper::Body>(https);
-    let request = Request::builder()
-        .uri("https://httpbin.org/basic-auth/prasanth/prasanth")
-        .body(hyper::Body::empty())
+#[async_trait]
+impl ProjectManager for Arc<ReloadingProjectManager> {
+    async fn handle_request(
+        &self,
+        request: hyper::Request<hyper::Body>,
+    ) -> Result<hyper::Response<hyper::Body>, Box<dyn Error>> {
+        self.current().handle_request(request).await
+    }
+
+    async fn get_project(
+        &self,
+        project_key: String,
+    ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>> {
+        self.current().get_project(project_key).await
+    }
+
+    async fn exists(&self, auth_token: &AuthToken, project_index: &str) -> bool {
+        self.current().exists(auth_token, project_index).await
+    }
+
+    async fn identity_exists(&self, identity: &ClientIdentity, project_index: &str) -> bool {
+        self.current().identity_exists(identity, project_index).await
+    }
+
+    async fn grant(&self, auth_token: &AuthToken, project_index: &str) -> Option<Grant> {
+        self.current().grant(auth_token, project_index).await
+    }
+
+    async fn identity_grant(
+        &self,
+        identity: &ClientIdentity,
+        project_index: &str,
+    ) -> Option<Grant> {
+        self.current().identity_grant(identity, project_index).await
+    }
+
+    async fn consume_quota(&self, grant: &Grant) -> Option<u64> {
+        self.current().consume_quota(grant).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    use http::Request;
+    use hyper::Body;
+    use mars_config::AvalancheTrace;
+    use serde_json::json;
+
+    use super::{FileProjectManager, Link, Overlay, ReloadingProjectManager, TokenGrant};
+    use crate::client_ip::ClientIp;
+    use crate::project::{AuthToken, ProjectManager};
+
+    fn project_manager() -> FileProjectManager {
+        let project_manager = FileProjectManager::try_from(json!({
+            "aviko": {
+                "subprojects": {
+                    "json": {"url": "http://localhost:1/json", "method": "ANY"},
+                    "xml": {"url": "http://localhost:1/xml", "method": "ANY"}
+                }
+            },
+            "internal": {
+                "needs_auth": false,
+                "ip_rules": {"allow": ["10.0.0.0/8"], "deny": ["10.1.0.0/16"]},
+                "subprojects": {
+                    "json": {"url": "http://localhost:1/json", "method": "ANY"}
+                }
+            },
+            "hooks": {
+                "needs_auth": false,
+                "signature": {"type": "github", "secret": "secret"},
+                "subprojects": {
+                    "json": {"url": "http://localhost:1/json", "method": "ANY"}
+                }
+            }
+        }))
         .unwrap();
-    let res = client
-        .request(request)
+        let tokens: HashMap<String, TokenGrant> = json5::from_str(
+            r#"{
+                "project:1": "aviko",
+                "project:2": {"project": "aviko", "allow": [{"subproject": "json", "methods": ["GET"]}]},
+                "project:3": {"project": "aviko", "quota": {"daily": 1}},
+                "project:4": {"project": "aviko", "source_cidrs": ["192.168.0.0/16"]},
+            }"#,
+        )
+        .unwrap();
+        for (key, value) in tokens {
+            project_manager.project_tokens.insert(AuthToken(key), value);
+        }
+        project_manager
+    }
+
+    #[tokio::test]
+    async fn test_token_grant() {
+        let project_manager = project_manager();
+        let grant = project_manager
+            .grant(&AuthToken("project:1".to_string()), "aviko")
+            .await
+            .unwrap();
+        assert!(grant.policy.permits("xml", "", "DELETE"));
+        let grant = project_manager
+            .grant(&AuthToken("project:2".to_string()), "aviko")
+            .await
+            .unwrap();
+        assert!(grant.policy.permits("json", "get", "GET"));
+        assert!(!grant.policy.permits("xml", "get", "GET"));
+        assert!(
+            !project_manager
+                .exists(&AuthToken("project:2".to_string()), "other")
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_is_enforced() {
+        let project_manager = project_manager();
+        for (method, subproject) in [("GET", "xml"), ("POST", "json")] {
+            let request = Request::builder()
+                .method(method)
+                .uri(format!("/aviko/{subproject}/get"))
+                .header("avalanche-token", "project:2")
+                .body(Body::empty())
+                .unwrap();
+            let response = project_manager.handle_request(request).await.unwrap();
+            assert_eq!(response.status(), 403);
+        }
+        let request = Request::builder()
+            .uri("/aviko/json/get")
+            .header("avalanche-token", "project:5")
+            .body(Body::empty())
+            .unwrap();
+        let response = project_manager.handle_request(request).await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_quota_is_enforced() {
+        let project_manager = project_manager();
+        let request = |uri| {
+            Request::builder()
+                .uri(uri)
+                .header("avalanche-token", "project:3")
+                .extension(AvalancheTrace("quota".to_string()))
+                .body(Body::empty())
+                .unwrap()
+        };
+        // request to a subproject that doesn't exist isn't counted
+        assert!(project_manager
+            .handle_request(request("/aviko/missing/get"))
+            .await
+            .is_err());
+        let response = project_manager
+            .handle_request(request("/aviko/json/get"))
+            .await
+            .unwrap();
+        assert_ne!(response.status(), 429);
+        let response = project_manager
+            .handle_request(request("/aviko/json/get"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().get("retry-after").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_source_address_is_enforced() {
+        let project_manager = project_manager();
+        for (uri, token, client_ip) in [
+            ("/internal/json/get", None, Some("192.168.1.1")),
+            ("/internal/json/get", None, Some("10.1.0.1")),
+            ("/internal/json/get", None, None),
+            ("/aviko/json/get", Some("project:4"), Some("10.0.0.1")),
+            ("/aviko/json/get", Some("project:4"), None),
+        ] {
+            let mut request = Request::builder().uri(uri);
+            if let Some(token) = token {
+                request = request.header("avalanche-token", token);
+            }
+            if let Some(client_ip) = client_ip {
+                request = request.extension(ClientIp(client_ip.parse().unwrap()));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            let response = project_manager.handle_request(request).await.unwrap();
+            assert_eq!(response.status(), 403, "{uri} from {client_ip:?}");
+        }
+        let grant = project_manager
+            .grant(&AuthToken("project:4".to_string()), "aviko")
+            .await
+            .unwrap();
+        assert_eq!(grant.source_cidrs, vec!["192.168.0.0/16".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_not_forwarded() {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::convert::Infallible;
+
+        // upstream echoing back what it received of avalanche token
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let header = request.headers().get("avalanche-token").is_some();
+                let uri = request.uri().to_string();
+                Ok::<_, Infallible>(hyper::Response::new(Body::from(format!("{header} {uri}"))))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let project_manager = FileProjectManager::try_from(json!({
+            "open": {
+                "needs_auth": false,
+                "token_sources": [{"type": "header", "name": "avalanche-token"}, {"type": "query", "name": "token"}],
+                "subprojects": {"echo": {"url": format!("http://{addr}/"), "method": "ANY"}}
+            }
+        }))
+        .unwrap();
+        let request = Request::builder()
+            .uri("/open/echo/get?token=secret&page=1")
+            .header("avalanche-token", "secret")
+            .extension(AvalancheTrace("forward".to_string()))
+            .body(Body::empty())
+            .unwrap();
+        let response = project_manager.handle_request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("false "), "{body}");
+        assert!(!body.contains("secret"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_signature_is_enforced() {
+        let project_manager = project_manager();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/hooks/json/post")
+            .header("x-hub-signature-256", "sha256=00")
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = project_manager.handle_request(request).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        // upstream isn't reachable, so delivery isn't used up and its retry gets through
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"{}");
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        for _ in 0..2 {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/hooks/json/post")
+                .header("x-hub-signature-256", &signature)
+                .extension(AvalancheTrace("hooks".to_string()))
+                .body(Body::from("{}"))
+                .unwrap();
+            let response = project_manager.handle_request(request).await.unwrap();
+            assert_eq!(response.status(), 500);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("mars-rover-reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (config, tokens) = (dir.join("config.json5"), dir.join("tokens.json5"));
+        let project = json!({"subprojects": {"json": {"url": "http://localhost:1/json", "method": "ANY"}}});
+        std::fs::write(&config, json!({"aviko": project}).to_string()).unwrap();
+        std::fs::write(&tokens, r#"{"project:1": "aviko"}"#).unwrap();
+        let link = |path: &std::path::PathBuf| {
+            Link::new(&path.display().to_string(), Default::default()).unwrap()
+        };
+        let project_manager = std::sync::Arc::new(
+            ReloadingProjectManager::load(link(&config), None, Some(link(&tokens)), false)
+                .await
+                .unwrap(),
+        );
+        let token = AuthToken("project:1".to_string());
+        assert!(!project_manager.reload(false).await.unwrap());
+
+        let old = project_manager.current();
+        std::fs::write(&config, json!({"aviko": project, "other": project}).to_string()).unwrap();
+        std::fs::write(&tokens, r#"{"project:1": "other"}"#).unwrap();
+        assert!(project_manager.reload(false).await.unwrap());
+        assert!(project_manager.exists(&token, "other").await);
+        assert!(!project_manager.exists(&token, "aviko").await);
+        // requests holding the old config keep being served by it
+        assert!(old.exists(&token, "aviko").await);
+
+        std::fs::write(&config, "{aviko: ").unwrap();
+        assert!(project_manager.reload(false).await.is_err());
+        assert!(project_manager.exists(&token, "other").await);
+        // broken config is reported once, not on every poll
+        assert!(!project_manager.reload(false).await.unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_rebuilds_in_background() {
+        let config = std::env::temp_dir().join(format!("mars-rover-rotation-{}.json5", uuid::Uuid::new_v4()));
+        let project = json!({"subprojects": {"json": {"url": "http://localhost:1/json", "method": "ANY"}}});
+        std::fs::write(&config, json!({"aviko": project}).to_string()).unwrap();
+        let link = Link::new(&config.display().to_string(), Default::default()).unwrap();
+        let project_manager = std::sync::Arc::new(
+            ReloadingProjectManager::load(link, None, None, false)
+                .await
+                .unwrap(),
+        );
+        let old = project_manager.current();
+        mars_config::secrets_rotated();
+        // requests keep being served by current manager
+        assert!(project_manager.get_project("aviko".to_string()).await.unwrap().is_some());
+        assert!(std::sync::Arc::ptr_eq(&old, &project_manager.current()));
+        project_manager.rebuild_if_rotated().await;
+        assert!(!std::sync::Arc::ptr_eq(&old, &project_manager.current()));
+        std::fs::remove_file(config).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_formats() {
+        let dir = std::env::temp_dir().join(format!("mars-rover-formats-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (config, tokens) = (dir.join("config.yaml"), dir.join("tokens.toml"));
+        std::fs::write(
+            &config,
+            "aviko:\n  subprojects:\n    json: {url: 'http://localhost:1/json', method: ANY}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &tokens,
+            "\"project:1\" = \"aviko\"\n\n[\"project:2\"]\nproject = \"aviko\"\nquota = {daily = 1}\n",
+        )
+        .unwrap();
+        let project_manager = super::get_file_project_manager(
+            config.clone(),
+            Some(tokens.display().to_string()),
+        )
+        .await
+        .unwrap();
+        assert!(project_manager.exists(&AuthToken("project:1".to_string()), "aviko").await);
+        let grant = project_manager
+            .grant(&AuthToken("project:2".to_string()), "aviko")
+            .await
+            .unwrap();
+        assert_eq!(grant.quotas.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overlay() {
+        let dir = std::env::temp_dir().join(format!("mars-rover-overlay-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.json5");
+        // overlay can be written in a format of its own
+        let overlay = dir.join("config.prod.yaml");
+        let project = json!({"subprojects": {"json": {"url": "http://localhost:1/json", "method": "ANY"}}});
+        std::fs::write(&config, json!({"aviko": project, "debug": project}).to_string()).unwrap();
+        std::fs::write(&overlay, "debug: null\nstaging:\n  subprojects: {}\n").unwrap();
+        let link = |path: &std::path::PathBuf| {
+            Link::new(&path.display().to_string(), Default::default()).unwrap()
+        };
+        let overlaid = |path: &std::path::PathBuf, allow_missing| Overlay {
+            environment: "prod".to_string(),
+            link: link(path),
+            allow_missing,
+        };
+        // environment without overlay fails to load, unless it is allowed to be missing
+        let missing = dir.join("config.dev.yaml");
+        let error = ReloadingProjectManager::load(
+            link(&config),
+            Some(overlaid(&missing, false)),
+            None,
+            false,
+        )
         .await
-        .map_err(|error| MarsError::ServiceConfigError(format!("ran into error {}", error)))?;
-    let body = hyper::body::to_bytes(res.into_body())
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("config.dev.yaml") && error.contains("`prod`"));
+        let project_manager = ReloadingProjectManager::load(
+            link(&config),
+            Some(overlaid(&missing, true)),
+            None,
+            false,
+        )
         .await
-        .map_err(|error| {
-            MarsError::ServiceConfigError(format!("unable to download config, {error}"))
-        })?;
-    Ok(body)
+        .unwrap();
+        assert!(project_manager.current().projects.contains_key("debug"));
+        std::fs::write(&missing, "debug: null\n").unwrap();
+        assert!(project_manager.reload(false).await.unwrap());
+        assert!(!project_manager.current().projects.contains_key("debug"));
+
+        let project_manager = ReloadingProjectManager::load(
+            link(&config),
+            Some(overlaid(&overlay, false)),
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+        let current = project_manager.current();
+        assert!(current.projects.contains_key("aviko"));
+        assert!(current.projects.contains_key("staging"));
+        assert!(!current.projects.contains_key("debug"));
+
+        // changes of overlay are reloaded as those of config are
+        std::fs::write(&overlay, "aviko:\n  subprojects:\n    json: {method: FETCH}\n").unwrap();
+        assert!(project_manager.reload(false).await.is_err());
+        let errors =
+            super::validate_file_config(&link(&config), Some(&overlaid(&overlay, false)), None)
+                .await
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "aviko/json");
+        assert!(super::validate_file_config(&link(&config), None, None)
+            .await
+            .unwrap()
+            .is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_defaults() {
+        let project = super::FileBasedProject::try_from(json!({
+            "defaults": {
+                "url": "http://localhost:1/",
+                "method": "ANY",
+                "headers": [{"key": "x-team", "value": "platform", "action": "Add"}],
+                "auth_profile": "httpbin"
+            },
+            "auth_profiles": {
+                "httpbin": {"auth_type": "basic_auth", "params": {"username": "u", "password": "p"}}
+            },
+            "subprojects": {
+                "json": {"url": "json"},
+                "xml": {"url": "xml", "headers": [{"key": "x-team", "value": "", "action": "Discard"}]}
+            }
+        }))
+        .unwrap();
+        let json = &project.service_config_map["json"];
+        assert_eq!(json.url, "http://localhost:1/json");
+        assert_eq!(json.headers[0].value, "platform");
+        assert_eq!(json.auth.auth_type(), &mars_config::AuthType::BasicAuth);
+        let xml = &project.service_config_map["xml"];
+        assert_eq!(xml.url, "http://localhost:1/xml");
+        assert_eq!(xml.headers.len(), 1);
+        assert_eq!(xml.headers[0].action, mars_config::Action::Discard);
+
+        assert!(super::FileBasedProject::try_from(json!({
+            "defaults": {"auth_profile": "missing"},
+            "subprojects": {"json": {"url": "http://localhost:1/json", "method": "ANY"}}
+        }))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let file = std::env::temp_dir().join(format!("mars-rover-validate-{}", uuid::Uuid::new_v4()));
+        let valid = json!({"url": "http://localhost:1/json", "method": "ANY"});
+        std::fs::write(
+            &file,
+            json!({
+                "aviko": {"subprojects": {
+                    "json": valid,
+                    "basic": {"url": "http://localhost:1/json", "method": "ANY", "auth": {"auth_type": "basic_auth", "params": {"username": "u"}}},
+                    "method": {"url": "http://localhost:1/json", "method": "FETCH"},
+                }},
+                "internal": {"ip_rules": {"allow": ["not a cidr"]}, "subprojects": {"json": valid}},
+                "other": {"subprojects": {"json": valid}},
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let link = Link::new(&file.display().to_string(), Default::default()).unwrap();
+        let mut paths: Vec<_> = super::validate_file_config(&link, None, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["aviko/basic", "aviko/method", "internal"]);
+
+        std::fs::write(&file, "{aviko: ").unwrap();
+        assert!(super::validate_file_config(&link, None, None).await.is_err());
+        std::fs::remove_file(file).unwrap();
+    }
 }
@@ -0,0 +1,413 @@
+//! Projects served from an ordered list of backends, a file of a few emergency or static projects
+//! ahead of a database holding the rest for example.
+//!
+//! A project is served by the first backend that has it, or by the last one with
+//! [`ProjectPrecedence::Last`]. Backends that fail to look a project up are skipped, so one that
+//! is down doesn't take projects of others with it. Tokens, certificate identities and users are
+//! checked against the backend serving the project, or with [`TokenPrecedence::Any`] against
+//! every backend in the same order, the first one granting access wins.
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use mars_config::{ClientIdentity, MarsError};
+
+use crate::project::{AuthProjectRequestHandler, AuthToken, Grant, ProjectManager};
+
+type Backend = Arc<Box<dyn ProjectManager>>;
+
+/// which of the backends having a project serves it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProjectPrecedence {
+    /// backend listed first
+    #[default]
+    First,
+    /// backend listed last
+    Last,
+}
+
+/// which backends are asked whether a token, identity or user is granted a project
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TokenPrecedence {
+    /// only the backend serving the project
+    #[default]
+    Owner,
+    /// every backend, in order of project precedence
+    Any,
+}
+
+impl FromStr for ProjectPrecedence {
+    type Err = MarsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "first" => Ok(ProjectPrecedence::First),
+            "last" => Ok(ProjectPrecedence::Last),
+            _ => Err(MarsError::ServiceConfigError(format!(
+                "project precedence should be `first` or `last`, not `{value}`"
+            ))),
+        }
+    }
+}
+
+impl FromStr for TokenPrecedence {
+    type Err = MarsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "owner" => Ok(TokenPrecedence::Owner),
+            "any" => Ok(TokenPrecedence::Any),
+            _ => Err(MarsError::ServiceConfigError(format!(
+                "token precedence should be `owner` or `any`, not `{value}`"
+            ))),
+        }
+    }
+}
+
+impl Display for ProjectPrecedence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectPrecedence::First => write!(f, "first"),
+            ProjectPrecedence::Last => write!(f, "last"),
+        }
+    }
+}
+
+impl Display for TokenPrecedence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenPrecedence::Owner => write!(f, "owner"),
+            TokenPrecedence::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// `LayeredProjectManager` serves projects of several backends as one.
+///
+/// Usage against quotas is counted by the backend that granted access, which is recorded on the
+/// grant.
+pub struct LayeredProjectManager {
+    backends: Vec<Backend>,
+    projects: ProjectPrecedence,
+    tokens: TokenPrecedence,
+    /// backend serving each project, by position in `backends`, as last looked up
+    owners: DashMap<String, usize>,
+}
+
+impl LayeredProjectManager {
+    pub fn new(backends: Vec<Backend>) -> Self {
+        LayeredProjectManager {
+            backends,
+            projects: Default::default(),
+            tokens: Default::default(),
+            owners: Default::default(),
+        }
+    }
+
+    pub fn with_project_precedence(mut self, precedence: ProjectPrecedence) -> Self {
+        self.projects = precedence;
+        self
+    }
+
+    pub fn with_token_precedence(mut self, precedence: TokenPrecedence) -> Self {
+        self.tokens = precedence;
+        self
+    }
+
+    /// positions of backends in order of project precedence
+    fn order(&self) -> Vec<usize> {
+        match self.projects {
+            ProjectPrecedence::First => (0..self.backends.len()).collect(),
+            ProjectPrecedence::Last => (0..self.backends.len()).rev().collect(),
+        }
+    }
+
+    /// position of backend serving project, along with project
+    async fn owner(
+        &self,
+        project_key: &str,
+    ) -> Result<Option<(usize, Arc<Box<dyn AuthProjectRequestHandler>>)>, Box<dyn Error>> {
+        let mut error = None;
+        let mut missing = false;
+        for position in self.order() {
+            match self.backends[position]
+                .get_project(project_key.to_string())
+                .await
+            {
+                Ok(Some(project)) => {
+                    self.owners.insert(project_key.to_string(), position);
+                    return Ok(Some((position, project)));
+                }
+                Ok(None) => missing = true,
+                Err(err) => {
+                    log::debug!("backend {position} has no project `{project_key}`: {err}");
+                    error = Some(err.to_string());
+                }
+            }
+        }
+        self.owners.remove(project_key);
+        match error {
+            Some(err) if !missing => Err(err.into()),
+            _ => Ok(None),
+        }
+    }
+
+    /// backends to ask for a grant of project, in order. owner is usually known already, as
+    /// project is looked up before access to it is
+    async fn granting(&self, project: &str) -> Vec<usize> {
+        match self.tokens {
+            TokenPrecedence::Any => self.order(),
+            TokenPrecedence::Owner => {
+                if let Some(position) = self.owners.get(project) {
+                    return vec![*position];
+                }
+                match self.owner(project).await {
+                    Ok(Some((position, _))) => vec![position],
+                    _ => vec![],
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ProjectManager for LayeredProjectManager {
+    async fn get_project(
+        &self,
+        project_key: String,
+    ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>> {
+        Ok(self.owner(&project_key).await?.map(|(_, project)| project))
+    }
+
+    async fn exists(&self, token: &AuthToken, project: &str) -> bool {
+        for position in self.granting(project).await {
+            if self.backends[position].exists(token, project).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn identity_exists(&self, identity: &ClientIdentity, project: &str) -> bool {
+        for position in self.granting(project).await {
+            if self.backends[position]
+                .identity_exists(identity, project)
+                .await
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn grant(&self, token: &AuthToken, project: &str) -> Option<Grant> {
+        for position in self.granting(project).await {
+            if let Some(grant) = self.backends[position].grant(token, project).await {
+                return Some(Grant {
+                    backend: Some(position),
+                    ..grant
+                });
+            }
+        }
+        None
+    }
+
+    async fn consume_quota(&self, grant: &Grant) -> Option<u64> {
+        // grants not made by this manager are counted by backend serving projects first
+        let position = grant.backend.or_else(|| self.order().first().copied())?;
+        self.backends.get(position)?.consume_quota(grant).await
+    }
+
+    async fn identity_grant(&self, identity: &ClientIdentity, project: &str) -> Option<Grant> {
+        for position in self.granting(project).await {
+            if let Some(grant) = self.backends[position]
+                .identity_grant(identity, project)
+                .await
+            {
+                return Some(Grant {
+                    backend: Some(position),
+                    ..grant
+                });
+            }
+        }
+        None
+    }
+
+    async fn email_grant(&self, email: &str, project: &str) -> Option<Grant> {
+        for position in self.granting(project).await {
+            if let Some(grant) = self.backends[position].email_grant(email, project).await {
+                return Some(Grant {
+                    backend: Some(position),
+                    ..grant
+                });
+            }
+        }
+        None
+    }
+
+    async fn invalidate(&self, project: &str) {
+        self.owners.remove(project);
+        for backend in &self.backends {
+            backend.invalidate(project).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    use super::{LayeredProjectManager, ProjectPrecedence, TokenPrecedence};
+    use crate::file::FileProjectManager;
+    use crate::project::{AuthProjectRequestHandler, AuthToken, Grant, ProjectManager};
+
+    /// projects of config, each granted to `token` alone
+    struct Backend {
+        projects: FileProjectManager,
+        token: &'static str,
+        lookups: Arc<AtomicUsize>,
+        counted: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ProjectManager for Backend {
+        async fn get_project(
+            &self,
+            project_key: String,
+        ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            self.projects.get_project(project_key).await
+        }
+
+        async fn exists(&self, token: &AuthToken, _project: &str) -> bool {
+            token.0 == self.token
+        }
+
+        async fn consume_quota(&self, _grant: &Grant) -> Option<u64> {
+            self.counted.fetch_add(1, Ordering::SeqCst);
+            None
+        }
+    }
+
+    fn backend(url: &str, token: &'static str) -> Arc<Box<dyn ProjectManager>> {
+        counted_backend(url, token, Default::default(), Default::default())
+    }
+
+    fn counted_backend(
+        url: &str,
+        token: &'static str,
+        lookups: Arc<AtomicUsize>,
+        counted: Arc<AtomicUsize>,
+    ) -> Arc<Box<dyn ProjectManager>> {
+        let projects = FileProjectManager::try_from(json!({
+            "shared": {"subprojects": {"json": {"url": url, "method": "ANY"}}},
+            token: {"subprojects": {"json": {"url": url, "method": "ANY"}}}
+        }))
+        .unwrap();
+        Arc::new(Box::new(Backend {
+            projects,
+            token,
+            lookups,
+            counted,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_layered() {
+        let layered = || {
+            LayeredProjectManager::new(vec![
+                backend("http://localhost:1/", "overrides"),
+                backend("http://localhost:2/", "db"),
+            ])
+        };
+        let token = |token: &str| AuthToken(token.to_string());
+
+        let first = layered();
+        let project = first.get_project("shared".to_string()).await.unwrap();
+        assert!(project.is_some());
+        assert!(first.get_project("db".to_string()).await.unwrap().is_some());
+        assert!(first.get_project("missing".to_string()).await.is_err());
+        // shared is served by overrides, token of db is not checked against it
+        assert!(first.exists(&token("overrides"), "shared").await);
+        assert!(!first.exists(&token("db"), "shared").await);
+        assert!(first.exists(&token("db"), "db").await);
+        assert!(!first.exists(&token("db"), "missing").await);
+
+        let last = layered().with_project_precedence(ProjectPrecedence::Last);
+        assert!(last.exists(&token("db"), "shared").await);
+        assert!(!last.exists(&token("overrides"), "shared").await);
+
+        let any = layered().with_token_precedence(TokenPrecedence::Any);
+        assert!(any.exists(&token("db"), "shared").await);
+        assert!(any.grant(&token("overrides"), "db").await.is_some());
+        assert!(any.grant(&token("other"), "db").await.is_none());
+
+        assert_eq!(
+            "LAST".parse::<ProjectPrecedence>().unwrap(),
+            ProjectPrecedence::Last
+        );
+        assert_eq!(
+            "any".parse::<TokenPrecedence>().unwrap(),
+            TokenPrecedence::Any
+        );
+        assert!("owner".parse::<ProjectPrecedence>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_layered_grants() {
+        let lookups: Vec<Arc<AtomicUsize>> = vec![Default::default(), Default::default()];
+        let counted: Vec<Arc<AtomicUsize>> = vec![Default::default(), Default::default()];
+        let layered = || {
+            LayeredProjectManager::new(vec![
+                counted_backend(
+                    "http://localhost:1/",
+                    "overrides",
+                    lookups[0].clone(),
+                    counted[0].clone(),
+                ),
+                counted_backend(
+                    "http://localhost:2/",
+                    "db",
+                    lookups[1].clone(),
+                    counted[1].clone(),
+                ),
+            ])
+        };
+        let token = |token: &str| AuthToken(token.to_string());
+        let count = |counters: &[Arc<AtomicUsize>]| {
+            counters
+                .iter()
+                .map(|counter| counter.load(Ordering::SeqCst))
+                .collect::<Vec<_>>()
+        };
+
+        // owner found while looking project up is not looked up again for its grant
+        let owner = layered();
+        assert!(owner.get_project("db".to_string()).await.unwrap().is_some());
+        assert_eq!(count(&lookups), vec![1, 1]);
+        let grant = owner.grant(&token("db"), "db").await.unwrap();
+        assert_eq!(count(&lookups), vec![1, 1]);
+        assert_eq!(grant.backend, Some(1));
+        // but is once project is invalidated
+        owner.invalidate("db").await;
+        assert!(owner.grant(&token("db"), "db").await.is_some());
+        assert_eq!(count(&lookups), vec![2, 2]);
+
+        // quotas are counted by backend that granted access
+        let any = layered().with_token_precedence(TokenPrecedence::Any);
+        let grant = any.grant(&token("db"), "shared").await.unwrap();
+        assert_eq!(any.consume_quota(&grant).await, None);
+        assert_eq!(count(&counted), vec![0, 1]);
+        // and by the first one, when grant wasn't made by layered manager
+        any.consume_quota(&Grant::default()).await;
+        assert_eq!(count(&counted), vec![1, 1]);
+    }
+}
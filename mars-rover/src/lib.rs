@@ -19,9 +19,24 @@
 //!
 //!
 #[cfg(feature = "sql")]
+pub mod admin;
+pub mod client_ip;
+#[cfg(feature = "sql")]
 pub mod db;
 pub mod file;
+pub mod layered;
+#[cfg(feature = "oidc")]
+pub mod oidc;
 pub mod project;
+pub mod quota;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod remote;
+pub mod signature;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod token;
+pub mod vault;
 
 use std::convert::Infallible;
 use std::net::SocketAddr;
@@ -33,35 +48,71 @@ use http::{header::HeaderName, HeaderValue, Request, Response};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Body;
 use hyper::Server;
-use mars_config::{AvalancheTrace, AVALANCHE_TRACE};
+use mars_config::{AvalancheTrace, ClientIdentity, AVALANCHE_TRACE};
+use client_ip::{ClientIp, TrustedProxies};
+use hyper::server::conn::AddrStream;
 use project::ProjectManager;
 
 pub use mars_request_transform as auth;
 
+/// Optional parts of the server, everything is off by default.
+#[derive(Default)]
+pub struct ServerOptions {
+    /// proxies trusted to report source address of requests
+    pub trusted_proxies: TrustedProxies,
+    /// browser login through OpenID Connect
+    #[cfg(feature = "oidc")]
+    pub oidc: Option<oidc::OidcClient>,
+    /// admin api, served under [`admin::ADMIN_PREFIX`]
+    #[cfg(feature = "sql")]
+    pub admin: Option<admin::AdminApi>,
+    /// serves admin api on this address instead of along with projects
+    #[cfg(feature = "sql")]
+    pub admin_addr: Option<SocketAddr>,
+    /// terminates tls, with client certificates when a client CA is configured
+    #[cfg(feature = "tls")]
+    pub tls_config: Option<tls::TlsConfig>,
+}
+
+/// What every request of a listener is served with.
+struct Shared {
+    /// `None` on admin only listeners
+    project_handler: Option<Arc<Box<dyn ProjectManager>>>,
+    trusted_proxies: TrustedProxies,
+    #[cfg(feature = "oidc")]
+    oidc: Option<oidc::OidcClient>,
+    #[cfg(feature = "sql")]
+    admin: Option<admin::AdminApi>,
+}
+
 /// `hyper_service_fn` is an asynchronous function that processes incoming HTTP requests.
 ///
 /// It takes in several parameters:
 /// - `request`: The incoming HTTP request. It's mutable because the function modifies its headers.
-/// - `project_handler`: An instance of a type that implements the `ProjectManager` trait. This object is responsible for handling the request.
-/// - `user_token_store` and `auth_token_store`: Instances of types that implement the `UserTokenStore` and `AuthTokenStore` traits, respectively. They are likely used for storing and retrieving user and authentication tokens.
+/// - `shared`: the `ProjectManager` responsible for handling the request, along with the optional parts of the server from `ServerOptions`.
+/// - `remote_addr`: peer address of the connection. The source address resolved through trusted proxies is added to the request's extensions as `ClientIp`.
+/// - `identity`: identity of the client certificate, when the connection is mutual-TLS. It is added to the request's extensions.
+///
+/// Admin and login endpoints are answered here, when configured. Otherwise the user of the session cookie is added to the request's extensions.
 ///
 /// Inside the function, a new UUID is generated and added as a header to the request. This UUID is likely used as a trace ID for logging and debugging purposes. The UUID is also added to the request's extensions, which is a way to attach additional data to the request.
 ///
-/// The `handle_request` method of the `project_handler` is then called with the request and the token stores as parameters. This method is awaited because it's asynchronous, which means it might perform some IO operations, such as sending a network request or querying a database.
+/// The `handle_request` method of the `project_handler` is then called with the request. This method is awaited because it's asynchronous, which means it might perform some IO operations, such as sending a network request or querying a database.
 ///
 /// # Examples
 ///
 /// ```rust
-/// let response = hyper_service_fn(request, project_handler, user_token_store, auth_token_store).await;
+/// let response = hyper_service_fn(request, shared, remote_addr, None).await;
 /// ```
 ///
 /// # Errors
 ///
 /// This function will return an error if the request cannot be processed, for example due to invalid tokens or network issues.
-
 async fn hyper_service_fn(
     mut request: Request<Body>,
-    project_handler: Arc<Box<dyn ProjectManager>>,
+    shared: Arc<Shared>,
+    remote_addr: SocketAddr,
+    identity: Option<ClientIdentity>,
 ) -> Result<Response<Body>, Infallible> {
     // using uuid as trace
     let trace = uuid::Uuid::new_v4().to_string();
@@ -73,8 +124,15 @@ async fn hyper_service_fn(
     request
         .extensions_mut()
         .insert(AvalancheTrace(trace.clone()));
+    let client_ip = shared
+        .trusted_proxies
+        .client_ip(remote_addr.ip(), request.headers());
+    request.extensions_mut().insert(ClientIp(client_ip));
+    if let Some(identity) = identity {
+        request.extensions_mut().insert(identity);
+    }
 
-    match project_handler.handle_request(request).await {
+    match handle(request, &shared).await {
         Ok(result) => {
             log::info!(
                 "[{}] request completed with status {:?}",
@@ -90,21 +148,134 @@ async fn hyper_service_fn(
     }
 }
 
-pub async fn start_server(addr: SocketAddr, project_handler:  Arc<Box<dyn ProjectManager>>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// routes request to admin api, login endpoints or the project
+async fn handle(
+    mut request: Request<Body>,
+    shared: &Shared,
+) -> Result<Response<Body>, Box<dyn std::error::Error>> {
+    #[cfg(feature = "sql")]
+    if let Some(admin) = &shared.admin {
+        if admin.handles(request.uri().path()) {
+            return admin.handle(request).await;
+        }
+    }
+    let project_handler = match &shared.project_handler {
+        Some(project_handler) => project_handler,
+        None => return response_from_status_message(404, "not found".into()),
+    };
+    #[cfg(feature = "oidc")]
+    if let Some(oidc) = &shared.oidc {
+        if oidc.handles(request.uri().path()) {
+            return oidc.handle(request).await;
+        }
+        if let Some(user) = oidc.take_session(&mut request) {
+            request.extensions_mut().insert(user);
+        }
+        request.extensions_mut().insert(oidc::LoginEnabled);
+    }
+    project_handler.handle_request(request).await
+}
 
+/// Serves projects of `project_handler` at `addr`. Admin api is served along, unless
+/// `options.admin_addr` gives it an address of its own.
+pub async fn start_server(
+    addr: SocketAddr,
+    project_handler: Arc<Box<dyn ProjectManager>>,
+    options: ServerOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    #[cfg(feature = "sql")]
+    let (admin, separate_admin) = match options.admin_addr {
+        Some(admin_addr) => (None, options.admin.map(|admin| (admin_addr, admin))),
+        None => (options.admin, None),
+    };
+    let shared = Arc::new(Shared {
+        project_handler: Some(project_handler),
+        trusted_proxies: options.trusted_proxies.clone(),
+        #[cfg(feature = "oidc")]
+        oidc: options.oidc,
+        #[cfg(feature = "sql")]
+        admin,
+    });
+    #[cfg(feature = "sql")]
+    if let Some((admin_addr, admin)) = separate_admin {
+        let admin = Arc::new(Shared {
+            project_handler: None,
+            trusted_proxies: options.trusted_proxies,
+            #[cfg(feature = "oidc")]
+            oidc: None,
+            admin: Some(admin),
+        });
+        let proxy_server = serve(
+            addr,
+            shared,
+            #[cfg(feature = "tls")]
+            options.tls_config.clone(),
+        );
+        let admin_server = serve(
+            admin_addr,
+            admin,
+            #[cfg(feature = "tls")]
+            options.tls_config,
+        );
+        tokio::try_join!(proxy_server, admin_server)?;
+        return Ok(());
+    }
+    serve(
+        addr,
+        shared,
+        #[cfg(feature = "tls")]
+        options.tls_config,
+    )
+    .await
+}
 
-    let make_svc = make_service_fn(|_conn| {
+/// Serves only the admin api of `options` at `addr`, for admin processes running apart from
+/// the proxies.
+#[cfg(feature = "sql")]
+pub async fn start_admin_server(
+    addr: SocketAddr,
+    options: ServerOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let admin = options.admin.ok_or("admin api is not configured")?;
+    let shared = Arc::new(Shared {
+        project_handler: None,
+        trusted_proxies: options.trusted_proxies,
+        #[cfg(feature = "oidc")]
+        oidc: None,
+        admin: Some(admin),
+    });
+    serve(
+        addr,
+        shared,
+        #[cfg(feature = "tls")]
+        options.tls_config,
+    )
+    .await
+}
+
+async fn serve(
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    #[cfg(feature = "tls")] tls_config: Option<tls::TlsConfig>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = tls_config {
+        return start_tls_server(addr, shared, tls_config).await;
+    }
+
+    let make_svc = make_service_fn(|conn: &AddrStream| {
         // This is the `Service` that will handle the connection.
         // `service_fn` is a helper to convert a function that
         // returns a Response into a `Service`.
-        let project_handler = project_handler.clone();
+        let shared = shared.clone();
+        let remote_addr = conn.remote_addr();
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
                 //
-                let project_handler = Arc::clone(&project_handler);
+                let shared = Arc::clone(&shared);
                 async move {
                     //
-                    hyper_service_fn(req, project_handler).await
+                    hyper_service_fn(req, shared, remote_addr, None).await
                 }
             }))
         }
@@ -118,3 +289,106 @@ pub async fn start_server(addr: SocketAddr, project_handler:  Arc<Box<dyn Projec
 
     Ok(())
 }
+
+/// Terminates TLS and serves every connection with the identity of its client certificate.
+#[cfg(feature = "tls")]
+async fn start_tls_server(
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls_config: tls::TlsConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let acceptor = tls_config.acceptor()?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    println!("Listening on https://{}", addr);
+
+    loop {
+        let (stream, remote_addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(error) => {
+                    log::error!("tls handshake with {} failed: {}", remote_addr, error);
+                    return;
+                }
+            };
+            let identity = match stream.get_ref().1.peer_certificates() {
+                Some([certificate, ..]) => match tls::client_identity(&certificate.0) {
+                    Ok(identity) => Some(identity),
+                    Err(error) => {
+                        log::error!("client certificate of {} ignored: {}", remote_addr, error);
+                        None
+                    }
+                },
+                _ => None,
+            };
+            let service = service_fn(move |req| {
+                let shared = Arc::clone(&shared);
+                let identity = identity.clone();
+                async move { hyper_service_fn(req, shared, remote_addr, identity).await }
+            });
+            if let Err(error) = hyper::server::conn::Http::new()
+                .serve_connection(stream, service)
+                .await
+            {
+                log::error!("connection with {} ran into error: {}", remote_addr, error);
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "sql"))]
+mod test {
+    use std::sync::Arc;
+
+    use http::Request;
+    use hyper::Body;
+    use sea_orm::Database;
+
+    use crate::{admin::AdminApi, client_ip::TrustedProxies, file::FileProjectManager, Shared};
+
+    async fn shared(with_projects: bool, with_admin: bool) -> Shared {
+        let project_handler: Arc<Box<dyn crate::project::ProjectManager>> = Arc::new(Box::new(
+            FileProjectManager::try_from(serde_json::json!({})).unwrap(),
+        ));
+        let admin = AdminApi::new(Database::connect("sqlite::memory:").await.unwrap());
+        Shared {
+            project_handler: with_projects.then_some(project_handler),
+            trusted_proxies: TrustedProxies::default(),
+            #[cfg(feature = "oidc")]
+            oidc: None,
+            admin: with_admin.then_some(admin),
+        }
+    }
+
+    async fn message(shared: &Shared, path: &str) -> (u16, String) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = super::handle(request, shared).await.unwrap();
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, body["message"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_admin_listener() {
+        // admin only listener doesn't proxy
+        let admin = shared(false, true).await;
+        assert_eq!(
+            message(&admin, "/_admin/projects").await,
+            (401, "admin token not provided".to_string())
+        );
+        assert_eq!(message(&admin, "/aviko/json/get").await.0, 404);
+
+        // proxy listener without admin api hands admin routes to projects
+        let proxy = shared(true, false).await;
+        let request = Request::get("/_admin/projects").body(Body::empty()).unwrap();
+        let error = super::handle(request, &proxy).await.unwrap_err();
+        assert!(error.to_string().contains("project `_admin` is missing"));
+
+        let both = shared(true, true).await;
+        assert_eq!(message(&both, "/_admin/projects").await.0, 401);
+    }
+}
@@ -0,0 +1,546 @@
+//! Browser login through an OpenID Connect provider.
+//!
+//! Browsers hitting a project without credentials are sent to [`LOGIN_PATH`], which starts the
+//! authorization-code flow (with PKCE) at the provider. The callback exchanges the code, reads the
+//! user's email from the userinfo endpoint and keeps it in an encrypted session cookie. Requests
+//! carrying the cookie are granted through [`ProjectManager::email_grant`].
+//!
+//! [`ProjectManager::email_grant`]: crate::project::ProjectManager::email_grant
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use http::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, SET_COOKIE},
+    HeaderValue, Method, Request, Response,
+};
+use hyper::{client::HttpConnector, Body, Client};
+use hyper_tls::HttpsConnector;
+use mars_config::MarsError;
+use mars_request_transform::response_from_status_message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{project::SessionUser, token::take_cookie};
+
+/// starts login, `next` query parameter is where the browser returns afterwards
+pub const LOGIN_PATH: &str = "/_oidc/login";
+/// redirect uri to register with the provider
+pub const CALLBACK_PATH: &str = "/_oidc/callback";
+/// forgets the session
+pub const LOGOUT_PATH: &str = "/_oidc/logout";
+
+const SESSION_COOKIE: &str = "avalanche_session";
+const STATE_COOKIE: &str = "avalanche_oidc_state";
+/// seconds a started login can take to complete
+const STATE_TTL: u64 = 600;
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".to_string(), "email".to_string()]
+}
+
+fn default_session_ttl() -> u64 {
+    8 * 60 * 60
+}
+
+/// Endpoints and client registration of the OpenID Connect provider.
+///
+/// ```json
+/// {
+///     "authorization_endpoint": "https://idp.example.com/authorize",
+///     "token_endpoint": "https://idp.example.com/oauth/token",
+///     "userinfo_endpoint": "https://idp.example.com/userinfo",
+///     "client_id": "avalanche",
+///     "client_secret": "..",
+///     "redirect_url": "https://avalanche.example.com/_oidc/callback",
+///     "cookie_key": "<64 hex characters>"
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct OidcConfig {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// absolute url of [`CALLBACK_PATH`], as registered with the provider
+    pub redirect_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// hex encoded 256 bit key session cookies are encrypted with
+    pub cookie_key: String,
+    /// seconds a session lasts, 8 hours by default
+    #[serde(default = "default_session_ttl")]
+    pub session_ttl: u64,
+}
+
+impl OidcConfig {
+    pub fn from_file(path: &Path) -> Result<Self, MarsError> {
+        let content = std::fs::read_to_string(path).map_err(|err| {
+            MarsError::ServiceConfigError(format!("unable to read {}: {err}", path.display()))
+        })?;
+        json5::from_str(&content).map_err(|err| {
+            MarsError::ServiceConfigError(format!("invalid oidc config {}: {err}", path.display()))
+        })
+    }
+}
+
+/// Added to request's extensions when browsers without credentials are to be sent to login.
+#[derive(Clone, Copy, Debug)]
+pub struct LoginEnabled;
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+    email: String,
+    expires: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoginState {
+    state: String,
+    verifier: String,
+    next: String,
+    expires: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct UserInfo {
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+/// `OidcClient` runs the login endpoints and reads sessions off requests.
+pub struct OidcClient {
+    config: OidcConfig,
+    cipher: Aes256Gcm,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Result<Self, MarsError> {
+        let cipher = hex::decode(&config.cookie_key)
+            .ok()
+            .and_then(|key| Aes256Gcm::new_from_slice(&key).ok())
+            .ok_or_else(|| {
+                MarsError::ServiceConfigError("cookie_key should be 64 hex characters".to_string())
+            })?;
+        Ok(OidcClient {
+            config,
+            cipher,
+            client: Client::builder().build(HttpsConnector::new()),
+        })
+    }
+
+    /// whether `path` is one of the login endpoints
+    pub fn handles(&self, path: &str) -> bool {
+        matches!(path, LOGIN_PATH | CALLBACK_PATH | LOGOUT_PATH)
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Result<Response<Body>, Box<dyn Error>> {
+        match request.uri().path() {
+            LOGIN_PATH => self.login(&request),
+            CALLBACK_PATH => self.callback(request).await,
+            _ => {
+                let mut response = response_from_status_message(200, "logged out".into())?;
+                response
+                    .headers_mut()
+                    .insert(SET_COOKIE, self.cookie(SESSION_COOKIE, "", 0)?);
+                Ok(response)
+            }
+        }
+    }
+
+    /// strips session cookie from request, returns its user while the session is valid
+    pub fn take_session(&self, request: &mut Request<Body>) -> Option<SessionUser> {
+        let sealed = take_cookie(request, SESSION_COOKIE).ok()??;
+        let session: Session = self.open(&sealed)?;
+        (session.expires > now()).then_some(SessionUser {
+            email: session.email,
+        })
+    }
+
+    fn login(&self, request: &Request<Body>) -> Result<Response<Body>, Box<dyn Error>> {
+        let next = query_params(request)
+            .remove("next")
+            .filter(|next| is_local_path(next))
+            .unwrap_or_else(|| "/".to_string());
+        let state = random_string();
+        let verifier = random_string();
+        let challenge = base64::encode_config(Sha256::digest(&verifier), base64::URL_SAFE_NO_PAD);
+        let mut url = url::Url::parse(&self.config.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        let login_state = self.seal(&LoginState {
+            state,
+            verifier,
+            next,
+            expires: now() + STATE_TTL,
+        });
+        redirect(url.as_str(), self.cookie(STATE_COOKIE, &login_state, STATE_TTL)?)
+    }
+
+    async fn callback(&self, mut request: Request<Body>) -> Result<Response<Body>, Box<dyn Error>> {
+        let params = query_params(&request);
+        if let Some(error) = params.get("error") {
+            return response_from_status_message(401, format!("login failed: {error}"));
+        }
+        let login_state = take_cookie(&mut request, STATE_COOKIE)?
+            .and_then(|sealed| self.open::<LoginState>(&sealed))
+            .filter(|login_state| login_state.expires > now());
+        let login_state = match (login_state, params.get("state")) {
+            (Some(login_state), Some(state)) if &login_state.state == state => login_state,
+            _ => return response_from_status_message(400, "login state not valid".into()),
+        };
+        let code = match params.get("code") {
+            Some(code) => code,
+            None => {
+                return response_from_status_message(400, "authorization code not provided".into())
+            }
+        };
+        let email = match self.email(code, &login_state.verifier).await {
+            Ok(email) => email,
+            Err(error) => {
+                log::error!("oidc login failed: {}", error);
+                return response_from_status_message(401, "login failed".into());
+            }
+        };
+        let session = self.seal(&Session {
+            email,
+            expires: now() + self.config.session_ttl,
+        });
+        let mut response = redirect(
+            &login_state.next,
+            self.cookie(SESSION_COOKIE, &session, self.config.session_ttl)?,
+        )?;
+        response
+            .headers_mut()
+            .append(SET_COOKIE, self.cookie(STATE_COOKIE, "", 0)?);
+        Ok(response)
+    }
+
+    /// exchanges authorization code and looks up email of the user
+    async fn email(&self, code: &str, verifier: &str) -> Result<String, MarsError> {
+        let form = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", code)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("client_secret", &self.config.client_secret)
+            .append_pair("code_verifier", verifier)
+            .finish();
+        let token: TokenResponse = self
+            .fetch_json(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(&self.config.token_endpoint)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .header(ACCEPT, "application/json")
+                    .body(Body::from(form)),
+            )
+            .await?;
+        let user_info: UserInfo = self
+            .fetch_json(
+                Request::builder()
+                    .uri(&self.config.userinfo_endpoint)
+                    .header(AUTHORIZATION, format!("Bearer {}", token.access_token))
+                    .header(ACCEPT, "application/json")
+                    .body(Body::empty()),
+            )
+            .await?;
+        if user_info.email_verified == Some(false) {
+            return Err(MarsError::Error("email of user is not verified".into()));
+        }
+        user_info
+            .email
+            .ok_or_else(|| MarsError::Error("provider did not return email".into()))
+    }
+
+    async fn fetch_json<T: DeserializeOwned>(
+        &self,
+        request: Result<Request<Body>, http::Error>,
+    ) -> Result<T, MarsError> {
+        let request = request.map_err(|err| MarsError::UrlError(err.to_string()))?;
+        let uri = request.uri().clone();
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|err| MarsError::Error(format!("request to {uri} failed: {err}").into()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| MarsError::Error(format!("unable to read response of {uri}: {err}").into()))?;
+        if !status.is_success() {
+            return Err(MarsError::Error(format!("{uri} responded with {status}").into()));
+        }
+        serde_json::from_slice(&body)
+            .map_err(|err| MarsError::Error(format!("unexpected response from {uri}: {err}").into()))
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64) -> Result<HeaderValue, MarsError> {
+        let secure = if self.config.redirect_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        HeaderValue::from_str(&format!(
+            "{name}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}"
+        ))
+        .map_err(|err| MarsError::Error(format!("invalid cookie: {err}").into()))
+    }
+
+    fn seal<T: Serialize>(&self, value: &T) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(value).expect("cookie payload is always serializable");
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .expect("encryption of cookie never fails");
+        base64::encode_config([nonce.as_slice(), &ciphertext].concat(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn open<T: DeserializeOwned>(&self, sealed: &str) -> Option<T> {
+        let sealed = base64::decode_config(sealed, base64::URL_SAFE_NO_PAD).ok()?;
+        if sealed.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+}
+
+/// redirect to login for browsers, when login is enabled for the request
+pub(crate) fn login_redirect(request: &Request<Body>) -> Option<Response<Body>> {
+    request.extensions().get::<LoginEnabled>()?;
+    let accepts_html = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("text/html"))
+        .unwrap_or(false);
+    if request.method() != Method::GET || !accepts_html {
+        return None;
+    }
+    let next = request
+        .uri()
+        .path_and_query()
+        .map(|x| x.as_str())
+        .unwrap_or("/");
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("next", next)
+        .finish();
+    let location = format!("{LOGIN_PATH}?{query}");
+    Response::builder()
+        .status(302)
+        .header(LOCATION, location)
+        .body(Body::empty())
+        .ok()
+}
+
+fn redirect(location: &str, cookie: HeaderValue) -> Result<Response<Body>, Box<dyn Error>> {
+    Ok(Response::builder()
+        .status(302)
+        .header(LOCATION, location)
+        .header(SET_COOKIE, cookie)
+        .body(Body::empty())?)
+}
+
+fn query_params(request: &Request<Body>) -> HashMap<String, String> {
+    url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// only paths of this host are followed after login, `//host` and `/\host` leave it
+fn is_local_path(next: &str) -> bool {
+    next.starts_with('/') && !next.starts_with("//") && !next.contains('\\')
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, convert::Infallible, net::SocketAddr};
+
+    use http::{header::SET_COOKIE, Request, Response};
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Server,
+    };
+
+    use super::{login_redirect, LoginEnabled, OidcClient, OidcConfig};
+
+    /// stand-in provider accepting code `code-1` for `neptune@example.com`
+    async fn start_provider() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let response = match request.uri().path() {
+                    "/token" => {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let form: HashMap<String, String> =
+                            url::form_urlencoded::parse(&body).into_owned().collect();
+                        if form.get("code").map(String::as_str) == Some("code-1")
+                            && form.get("client_secret").map(String::as_str) == Some("secret")
+                            && form.contains_key("code_verifier")
+                        {
+                            Response::new(Body::from(r#"{"access_token": "at-1"}"#))
+                        } else {
+                            Response::builder().status(400).body(Body::empty()).unwrap()
+                        }
+                    }
+                    "/userinfo" if request.headers()["authorization"] == "Bearer at-1" => {
+                        Response::new(Body::from(
+                            r#"{"email": "neptune@example.com", "email_verified": true}"#,
+                        ))
+                    }
+                    _ => Response::builder().status(401).body(Body::empty()).unwrap(),
+                };
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn oidc_client(provider: SocketAddr) -> OidcClient {
+        OidcClient::new(OidcConfig {
+            authorization_endpoint: format!("http://{provider}/authorize"),
+            token_endpoint: format!("http://{provider}/token"),
+            userinfo_endpoint: format!("http://{provider}/userinfo"),
+            client_id: "avalanche".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "http://localhost:3000/_oidc/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            cookie_key: "00".repeat(32),
+            session_ttl: 60,
+        })
+        .unwrap()
+    }
+
+    fn cookie_value(response: &Response<Body>) -> String {
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_login() {
+        let client = oidc_client(start_provider().await);
+
+        let login = Request::get("/_oidc/login?next=%2Faviko%2Fjson%2Fget")
+            .body(Body::empty())
+            .unwrap();
+        let response = client.handle(login).await.unwrap();
+        assert_eq!(response.status(), 302);
+        let location = url::Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        assert_eq!(location.path(), "/authorize");
+        let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+        let state_cookie = cookie_value(&response);
+
+        // callback of a login started elsewhere is rejected
+        let forged = Request::get("/_oidc/callback?code=code-1&state=other")
+            .header("cookie", &state_cookie)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(client.handle(forged).await.unwrap().status(), 400);
+
+        let callback = Request::get(format!(
+            "/_oidc/callback?code=code-1&state={}",
+            params["state"]
+        ))
+        .header("cookie", &state_cookie)
+        .body(Body::empty())
+        .unwrap();
+        let response = client.handle(callback).await.unwrap();
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.headers()["location"], "/aviko/json/get");
+
+        let mut request = Request::get("/aviko/json/get")
+            .header("cookie", format!("theme=dark; {}", cookie_value(&response)))
+            .body(Body::empty())
+            .unwrap();
+        let user = client.take_session(&mut request).unwrap();
+        assert_eq!(user.email, "neptune@example.com");
+        assert_eq!(request.headers()["cookie"], "theme=dark");
+
+        let mut tampered = Request::get("/aviko/json/get")
+            .header("cookie", format!("{}x", cookie_value(&response)))
+            .body(Body::empty())
+            .unwrap();
+        assert!(client.take_session(&mut tampered).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_login_redirect() {
+        let client = oidc_client("127.0.0.1:9".parse().unwrap());
+        let login = Request::get("/_oidc/login?next=%2F%2Fevil.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = client.handle(login).await.unwrap();
+        let location = url::Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        let state = location
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .unwrap()
+            .1;
+        let login_state: super::LoginState = client
+            .open(cookie_value(&response).split_once('=').unwrap().1)
+            .unwrap();
+        assert_eq!(login_state.state, state);
+        assert_eq!(login_state.next, "/");
+
+        let mut browser = Request::get("/aviko/json/get?a=1")
+            .header("accept", "text/html,application/xhtml+xml")
+            .body(Body::empty())
+            .unwrap();
+        assert!(login_redirect(&browser).is_none());
+        browser.extensions_mut().insert(LoginEnabled);
+        let response = login_redirect(&browser).unwrap();
+        assert_eq!(
+            response.headers()["location"],
+            "/_oidc/login?next=%2Faviko%2Fjson%2Fget%3Fa%3D1"
+        );
+
+        let mut api = Request::get("/aviko/json/get")
+            .header("accept", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        api.extensions_mut().insert(LoginEnabled);
+        assert!(login_redirect(&api).is_none());
+    }
+}
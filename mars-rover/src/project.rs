@@ -4,19 +4,76 @@ use async_trait::async_trait;
 use clap::Result;
 use dashmap::mapref::one::RefMut;
 use dyn_clone::{clone_trait_object, DynClone};
-use http::Response;
+use http::{header::RETRY_AFTER, HeaderValue, Response};
 use hyper::Body;
-use mars_config::{MarsError, AVALANCHE_TOKEN};
+use mars_config::{AccessPolicy, ClientIdentity, IpNet, IpRules, MarsError, TokenSource};
 
 use hyper::service::Service;
 use mars_request_transform::{response_from_status_message, ProxyService, ProxyUrlPath};
 
+use crate::{client_ip::ClientIp, quota::Metered, signature::SignatureVerifier};
+
 /// `AuthToken` represents an authentication token.
 ///
 /// It is used for authentication purposes.
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
 pub struct AuthToken(pub String);
 
+/// `SessionUser` is the user of a browser session, added to the request's extensions after login.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SessionUser {
+    pub email: String,
+}
+
+/// `Grant` describes what a token or client identity is allowed to do within a project.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Grant {
+    /// subprojects, paths and methods reachable with this grant
+    pub policy: AccessPolicy,
+    /// quotas every request made with this grant is counted against
+    pub quotas: Vec<Metered>,
+    /// source addresses the grant can be used from, any when empty
+    pub source_cidrs: Vec<IpNet>,
+    /// position of backend that granted it, when projects are served by several backends
+    pub backend: Option<usize>,
+}
+
+impl Grant {
+    fn permits_source(&self, client_ip: Option<&ClientIp>) -> bool {
+        self.source_cidrs.is_empty()
+            || client_ip
+                .map(|ClientIp(ip)| self.source_cidrs.iter().any(|net| net.contains(ip)))
+                .unwrap_or(false)
+    }
+}
+
+/// `ConfigError` is a project, or a subproject of it, that can't be served, as reported when
+/// config is validated.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ConfigError {
+    /// `<project>` or `<project>/<subproject>`
+    pub path: String,
+    pub reason: String,
+}
+
+impl ConfigError {
+    pub fn new(path: impl Into<String>, error: &MarsError) -> Self {
+        let reason = match error {
+            MarsError::UrlError(reason) | MarsError::ServiceConfigError(reason) => reason.clone(),
+            error => error.to_string().trim_end().to_string(),
+        };
+        ConfigError {
+            path: path.into(),
+            reason,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
 
 /// `AuthProjectRequestHandler` is responsible for handling authentication requests for a project.
 ///
@@ -39,12 +96,27 @@ pub struct AuthToken(pub String);
 pub trait AuthProjectRequestHandler: Sync + Send + DynClone {
     async fn is_project(&self, path: &str) -> bool;
 
-    async fn get_service(
-        &self,
+    async fn get_service<'a>(
+        &'a self,
         path: String,
-    ) -> Result<Option<RefMut<String, ProxyService>>, Box<dyn Error>>;
+    ) -> Result<Option<RefMut<'a, String, ProxyService>>, Box<dyn Error>>;
 
     async fn auth_configured(&self) -> bool;
+
+    /// where avalanche token is looked up for this project, defaults to `avalanche-token` header
+    async fn token_sources(&self) -> Vec<TokenSource> {
+        vec![TokenSource::default()]
+    }
+
+    /// source addresses requests to this project are accepted from, defaults to any
+    async fn ip_rules(&self) -> IpRules {
+        IpRules::default()
+    }
+
+    /// verifier of signed requests, when the project requires them
+    async fn signature_verifier(&self) -> Option<Arc<SignatureVerifier>> {
+        None
+    }
 }
 
 clone_trait_object!(AuthProjectRequestHandler);
@@ -87,27 +159,87 @@ pub trait ProjectManager: Sync + Send {
         let project = self.get_project(project_key.to_string()).await?;
         match project {
             Some(project) => {
-                if project.auth_configured().await {
-                    let avalanche_token = if let Some(avalanche_token) =
-                        request.headers().get(AVALANCHE_TOKEN).cloned()
-                    {
-                        avalanche_token
-                    } else {
-                        return response_from_status_message(
-                            401,
-                            "avalanche token not provided".into(),
-                        );
-                    };
-                    let avalanche_token =
-                        AuthToken(String::from_utf8(avalanche_token.as_bytes().to_vec())?);
-                    if !(self.exists(&avalanche_token, project_key).await) {
-                        return response_from_status_message(
-                            401,
-                            "avalanche token not valid".into(),
-                        );
-                    }
+                let client_ip = request.extensions().get::<ClientIp>().copied();
+                let ip_rules = project.ip_rules().await;
+                if !ip_rules.is_empty()
+                    && !client_ip
+                        .map(|ClientIp(ip)| ip_rules.permits(ip))
+                        .unwrap_or(false)
+                {
+                    return response_from_status_message(
+                        403,
+                        "source address not allowed".into(),
+                    );
                 }
+                // token carriers are stripped even when project doesn't need auth, so that
+                // avalanche token is never forwarded upstream
+                let token_sources = project.token_sources().await;
+                let avalanche_token = crate::token::take_token(&mut request, &token_sources)?;
+                let grant = if project.auth_configured().await {
+                    match avalanche_token {
+                        Some(avalanche_token) => {
+                            match self.grant(&avalanche_token, project_key).await {
+                                Some(grant) => Some(grant),
+                                None => {
+                                    return response_from_status_message(
+                                        401,
+                                        "avalanche token not valid".into(),
+                                    )
+                                }
+                            }
+                        }
+                        None => match request.extensions().get::<ClientIdentity>() {
+                            Some(identity) => match self.identity_grant(identity, project_key).await
+                            {
+                                Some(grant) => Some(grant),
+                                None => {
+                                    return response_from_status_message(
+                                        401,
+                                        "client certificate not allowed".into(),
+                                    )
+                                }
+                            },
+                            None => match request.extensions().get::<SessionUser>() {
+                                Some(user) => match self.email_grant(&user.email, project_key).await
+                                {
+                                    Some(grant) => Some(grant),
+                                    None => {
+                                        return response_from_status_message(
+                                            403,
+                                            format!("user `{}` not allowed", user.email),
+                                        )
+                                    }
+                                },
+                                None => {
+                                    #[cfg(feature = "oidc")]
+                                    if let Some(response) = crate::oidc::login_redirect(&request) {
+                                        return Ok(response);
+                                    }
+                                    return response_from_status_message(
+                                        401,
+                                        "avalanche token not provided".into(),
+                                    );
+                                }
+                            },
+                        },
+                    }
+                } else {
+                    None
+                };
+                let verified = match project.signature_verifier().await {
+                    Some(verifier) => match verifier.verify(&mut request).await {
+                        Ok(verified) => Some((verifier, verified)),
+                        Err(error) => {
+                            return response_from_status_message(error.status(), error.to_string())
+                        }
+                    },
+                    None => None,
+                };
 
+                // token may have been stripped from query, so rest of the url is taken from updated request
+                let uri = request.uri().clone();
+                let uri = uri.path_and_query().unwrap().as_str();
+                let mut url_split = uri.splitn(4, '/').skip(2);
                 // TODO, service has not extra backslash ('/'), service contains `?` also, which messes up everything
                 let service_key = url_split.next().ok_or_else(||MarsError::UrlError(
                     "marsrover url should contain https://<host>/<project>/<subproject>/<rest>. subproject is missing".to_string()
@@ -121,6 +253,23 @@ pub trait ProjectManager: Sync + Send {
                     let url_rest = url_split.next().unwrap_or("");
                     (service_key, url_rest.to_owned())
                 };
+                if let Some(grant) = &grant {
+                    if !grant.permits_source(client_ip.as_ref()) {
+                        return response_from_status_message(
+                            403,
+                            "avalanche token not allowed from source address".into(),
+                        );
+                    }
+                    if !grant
+                        .policy
+                        .permits(service, &url_rest, request.method().as_str())
+                    {
+                        return response_from_status_message(
+                            403,
+                            format!("access to subproject `{service}` is not allowed"),
+                        );
+                    }
+                }
                 let mut service_pair =
                     project
                         .get_service(service.to_string())
@@ -131,6 +280,17 @@ pub trait ProjectManager: Sync + Send {
                                 project_key, service_key
                             ))
                         })?;
+                // usage is only counted once request can be served by subproject
+                if let Some(grant) = &grant {
+                    if let Some(retry_after) = self.consume_quota(grant).await {
+                        let mut response =
+                            response_from_status_message(429, "quota exceeded".into())?;
+                        response
+                            .headers_mut()
+                            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                        return Ok(response);
+                    }
+                }
                 request.extensions_mut().insert(ProxyUrlPath(url_rest));
                 let service = service_pair.value_mut();
                 // TODO Handle errors or waits
@@ -142,7 +302,16 @@ pub trait ProjectManager: Sync + Send {
                         500,
                         format!("request to proxy ran into error: `{}`", resp),
                     ),
-                    Ok(resp) => Ok(resp),
+                    Ok(resp) => {
+                        // signature is used up only once upstream took the request, webhooks
+                        // retry failed deliveries with the same one
+                        if let Some((verifier, verified)) = verified {
+                            if !resp.status().is_server_error() {
+                                verifier.remember(verified);
+                            }
+                        }
+                        Ok(resp)
+                    }
                 }
             }
             None => return response_from_status_message(404, "project not found".into()),
@@ -154,4 +323,42 @@ pub trait ProjectManager: Sync + Send {
     ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>>;
 
     async fn exists(&self, token: &AuthToken, project: &str) -> bool;
+
+    /// whether client certificate identity is granted access to project
+    async fn identity_exists(&self, _identity: &ClientIdentity, _project: &str) -> bool {
+        false
+    }
+
+    /// grant of token for project, `None` when token is not allowed.
+    /// defaults to unrestricted access whenever `exists` allows the token
+    async fn grant(&self, token: &AuthToken, project: &str) -> Option<Grant> {
+        if self.exists(token, project).await {
+            Some(Grant::default())
+        } else {
+            None
+        }
+    }
+
+    /// counts request against quotas of grant, returns seconds to wait when a quota is exhausted.
+    /// quotas are not enforced by default
+    async fn consume_quota(&self, _grant: &Grant) -> Option<u64> {
+        None
+    }
+
+    /// grant of client certificate identity for project, `None` when identity is not allowed
+    async fn identity_grant(&self, identity: &ClientIdentity, project: &str) -> Option<Grant> {
+        if self.identity_exists(identity, project).await {
+            Some(Grant::default())
+        } else {
+            None
+        }
+    }
+
+    /// grant of user logged in with `email` through the browser, nobody by default
+    async fn email_grant(&self, _email: &str, _project: &str) -> Option<Grant> {
+        None
+    }
+
+    /// forgets whatever is cached of project, so that its next request sees current config
+    async fn invalidate(&self, _project: &str) {}
 }
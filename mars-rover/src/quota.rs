@@ -0,0 +1,357 @@
+//! Daily and monthly request quotas per token or user.
+//!
+//! Usage is counted in memory. When backed by a database, counts are added to the `usage` table in
+//! batches every [`FLUSH_INTERVAL`], which also picks up usage recorded by other instances. The
+//! table needs its unique index on `(principal, period)`, which `mars-entity orm` creates.
+use std::time::Duration;
+
+use dashmap::DashMap;
+use mars_config::Quota;
+use time::{Date, Month, OffsetDateTime};
+
+/// how often pending usage is written to the database
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `Metered` is a quota along with principal (`token:<id>`, `user:<id>`) whose usage it limits.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Metered {
+    pub principal: String,
+    pub quota: Quota,
+}
+
+#[derive(Hash, PartialEq, Eq, Debug, Clone)]
+struct UsageKey {
+    principal: String,
+    period: String,
+}
+
+/// `UsageTracker` counts requests of principals and tells when a quota is exhausted.
+#[derive(Default)]
+pub struct UsageTracker {
+    counts: DashMap<UsageKey, u64>,
+    pending: DashMap<UsageKey, u64>,
+    #[cfg(feature = "sql")]
+    db_conn: Option<sea_orm::DatabaseConnection>,
+}
+
+impl UsageTracker {
+    /// usage tracker persisting counts to `usage` table
+    #[cfg(feature = "sql")]
+    pub fn with_db(db_conn: sea_orm::DatabaseConnection) -> Self {
+        UsageTracker {
+            db_conn: Some(db_conn),
+            ..Default::default()
+        }
+    }
+
+    /// counts a request against every quota, unless one of them is exhausted.
+    /// in that case, returns seconds after which the exhausted quota resets
+    pub async fn consume(&self, metered: &[Metered]) -> Option<u64> {
+        self.consume_at(metered, OffsetDateTime::now_utc()).await
+    }
+
+    async fn consume_at(&self, metered: &[Metered], now: OffsetDateTime) -> Option<u64> {
+        let mut limits = vec![];
+        for Metered { principal, quota } in metered {
+            let periods = [
+                (quota.daily, day_period(now), seconds_to_next_day(now)),
+                (quota.monthly, month_period(now), seconds_to_next_month(now)),
+            ];
+            for (limit, period, retry_after) in periods {
+                let limit = match limit {
+                    Some(limit) => limit,
+                    None => continue,
+                };
+                let key = UsageKey {
+                    principal: principal.clone(),
+                    period,
+                };
+                self.load_once(&key).await;
+                limits.push((key, limit, retry_after));
+            }
+        }
+        // every count is checked and incremented under the lock of its entry, so concurrent
+        // requests can't go past a limit. counts already taken are given back when a later
+        // quota is exhausted
+        let mut counted = vec![];
+        for (key, limit, retry_after) in &limits {
+            let mut count = self.counts.entry(key.clone()).or_default();
+            if *count >= *limit {
+                drop(count);
+                for key in counted {
+                    if let Some(mut count) = self.counts.get_mut(key) {
+                        *count = count.saturating_sub(1);
+                    }
+                }
+                return Some(*retry_after);
+            }
+            *count += 1;
+            counted.push(key);
+        }
+        if self.is_persistent() {
+            for key in counted {
+                *self.pending.entry(key.clone()).or_default() += 1;
+            }
+        }
+        None
+    }
+
+    /// usage of `key` recorded earlier or by other instances, unless it is being counted already
+    async fn load_once(&self, key: &UsageKey) {
+        if self.counts.contains_key(key) {
+            return;
+        }
+        let count = self.load(key).await;
+        self.counts.entry(key.clone()).or_insert(count);
+    }
+
+    fn is_persistent(&self) -> bool {
+        #[cfg(feature = "sql")]
+        {
+            self.db_conn.is_some()
+        }
+        #[cfg(not(feature = "sql"))]
+        {
+            false
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    async fn load(&self, key: &UsageKey) -> u64 {
+        use mars_entity::usage;
+        use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+        let db_conn = match &self.db_conn {
+            Some(db_conn) => db_conn,
+            None => return 0,
+        };
+        match usage::Entity::find()
+            .filter(usage::Column::Principal.eq(key.principal.clone()))
+            .filter(usage::Column::Period.eq(key.period.clone()))
+            .one(db_conn)
+            .await
+        {
+            Ok(usage) => usage.map(|x| x.count as u64).unwrap_or(0),
+            Err(err) => {
+                log::error!("unable to load usage of {}: {}", key.principal, err);
+                0
+            }
+        }
+    }
+
+    #[cfg(not(feature = "sql"))]
+    async fn load(&self, _key: &UsageKey) -> u64 {
+        0
+    }
+
+    /// writes pending usage to database and forgets usage of past periods
+    pub async fn flush(&self) {
+        #[cfg(feature = "sql")]
+        if let Some(db_conn) = &self.db_conn {
+            let keys: Vec<UsageKey> = self.pending.iter().map(|x| x.key().clone()).collect();
+            for key in keys {
+                let count = match self.pending.remove(&key) {
+                    Some((_, count)) => count,
+                    None => continue,
+                };
+                match persist(db_conn, &key, count).await {
+                    Ok(total) => {
+                        let pending = self.pending.get(&key).map(|x| *x).unwrap_or(0);
+                        self.counts.insert(key, total + pending);
+                    }
+                    Err(err) => {
+                        log::error!("unable to persist usage of {}: {}", key.principal, err);
+                        *self.pending.entry(key).or_default() += count;
+                    }
+                }
+            }
+        }
+        let now = OffsetDateTime::now_utc();
+        let current = [day_period(now), month_period(now)];
+        self.counts.retain(|key, _| current.contains(&key.period));
+    }
+}
+
+/// adds `count` to usage of `key` and returns total usage
+#[cfg(feature = "sql")]
+async fn persist(
+    db_conn: &sea_orm::DatabaseConnection,
+    key: &UsageKey,
+    count: u64,
+) -> Result<u64, sea_orm::DbErr> {
+    use mars_entity::usage;
+    use sea_orm::{
+        sea_query::{Expr, OnConflict},
+        ActiveValue, ColumnTrait, EntityTrait, QueryFilter,
+    };
+
+    // a single statement, so that replicas flushing a new period at once add to the same row
+    usage::Entity::insert(usage::ActiveModel {
+        id: ActiveValue::NotSet,
+        principal: ActiveValue::Set(key.principal.clone()),
+        period: ActiveValue::Set(key.period.clone()),
+        count: ActiveValue::Set(count as i64),
+    })
+    .on_conflict(
+        OnConflict::columns([usage::Column::Principal, usage::Column::Period])
+            .update_expr((
+                usage::Column::Count,
+                Expr::tbl(usage::Entity, usage::Column::Count).add(count as i64),
+            ))
+            .to_owned(),
+    )
+    .exec(db_conn)
+    .await?;
+    Ok(usage::Entity::find()
+        .filter(usage::Column::Principal.eq(key.principal.clone()))
+        .filter(usage::Column::Period.eq(key.period.clone()))
+        .one(db_conn)
+        .await?
+        .map(|x| x.count as u64)
+        .unwrap_or(count))
+}
+
+fn day_period(now: OffsetDateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}",
+        now.year(),
+        now.month() as u8,
+        now.day()
+    )
+}
+
+fn month_period(now: OffsetDateTime) -> String {
+    format!("{:04}-{:02}", now.year(), now.month() as u8)
+}
+
+fn seconds_to_next_day(now: OffsetDateTime) -> u64 {
+    let (hour, minute, second) = now.time().as_hms();
+    86400 - (hour as u64 * 3600 + minute as u64 * 60 + second as u64)
+}
+
+fn seconds_to_next_month(now: OffsetDateTime) -> u64 {
+    let (year, month) = match now.month() {
+        Month::December => (now.year() + 1, Month::January),
+        month => (now.year(), month.next()),
+    };
+    let next_month = Date::from_calendar_date(year, month, 1)
+        .expect("first day of month is always valid")
+        .midnight()
+        .assume_utc();
+    (next_month - now).whole_seconds().max(1) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use mars_config::Quota;
+    use time::macros::datetime;
+
+    use super::{Metered, UsageTracker};
+
+    #[tokio::test]
+    async fn test_quota() {
+        let tracker = UsageTracker::default();
+        let metered = vec![
+            Metered {
+                principal: "token:1".to_string(),
+                quota: Quota {
+                    daily: Some(2),
+                    monthly: Some(3),
+                },
+            },
+            Metered {
+                principal: "user:1".to_string(),
+                quota: Quota::default(),
+            },
+        ];
+        let now = datetime!(2022-12-30 23:00 UTC);
+        assert_eq!(tracker.consume_at(&metered, now).await, None);
+        assert_eq!(tracker.consume_at(&metered, now).await, None);
+        // third request of the day is over daily quota, which resets in an hour
+        assert_eq!(tracker.consume_at(&metered, now).await, Some(3600));
+
+        let now = datetime!(2022-12-31 00:00 UTC);
+        assert_eq!(tracker.consume_at(&metered, now).await, None);
+        // monthly quota resets with the new year
+        assert_eq!(tracker.consume_at(&metered, now).await, Some(86400));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests_stay_within_quota() {
+        let tracker = std::sync::Arc::new(UsageTracker::default());
+        let metered = vec![Metered {
+            principal: "token:1".to_string(),
+            quota: Quota {
+                daily: Some(50),
+                monthly: Some(100),
+            },
+        }];
+        let requests: Vec<_> = (0..200)
+            .map(|_| {
+                let (tracker, metered) = (tracker.clone(), metered.clone());
+                tokio::spawn(async move { tracker.consume(&metered).await.is_none() })
+            })
+            .collect();
+        let mut allowed = 0;
+        for request in requests {
+            if request.await.unwrap() {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 50);
+    }
+
+    #[cfg(feature = "sql")]
+    #[tokio::test]
+    async fn test_usage_is_persisted() {
+        use sea_orm::{ConnectionTrait, Database, EntityTrait, Schema};
+
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(db.get_database_backend());
+        db.execute(
+            db.get_database_backend()
+                .build(&schema.create_table_from_entity(mars_entity::usage::Entity)),
+        )
+        .await
+        .unwrap();
+        db.execute(
+            db.get_database_backend()
+                .build(&mars_entity::usage::unique_index()),
+        )
+        .await
+        .unwrap();
+        let metered = vec![Metered {
+            principal: "token:1".to_string(),
+            quota: Quota {
+                daily: None,
+                monthly: Some(3),
+            },
+        }];
+        let tracker = UsageTracker::with_db(db.clone());
+        assert_eq!(tracker.consume(&metered).await, None);
+        assert_eq!(tracker.consume(&metered).await, None);
+        tracker.flush().await;
+        tracker.flush().await;
+
+        // another instance continues from persisted usage
+        let tracker = UsageTracker::with_db(db.clone());
+        assert_eq!(tracker.consume(&metered).await, None);
+        assert!(tracker.consume(&metered).await.is_some());
+        tracker.flush().await;
+
+        // usage of a period is kept in a single row, however many instances flushed it
+        let rows = mars_entity::usage::Entity::find().all(&db).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].count, 3);
+        let duplicate = mars_entity::usage::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            principal: sea_orm::ActiveValue::Set(rows[0].principal.clone()),
+            period: sea_orm::ActiveValue::Set(rows[0].period.clone()),
+            count: sea_orm::ActiveValue::Set(1),
+        };
+        assert!(mars_entity::usage::Entity::insert(duplicate)
+            .exec(&db)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,704 @@
+//! Projects, subprojects and token grants kept in Redis, shared by every replica.
+//!
+//! Keys are under a prefix, `mars` unless configured:
+//!
+//! - `mars:project:<project>` is a hash of project fields as they are written in config file,
+//!   `needs_auth`, `token_sources`, `ip_rules`, `signature`, `defaults` and `auth_profiles`, each
+//!   a json value.
+//! - `mars:subprojects:<project>` is a hash of json service configs of project, keyed by
+//!   subproject.
+//! - `mars:tokens` is a hash of grants keyed by token or certificate identity, as in tokens file.
+//!   A grant of a project as a whole can be its index alone, `HSET mars:tokens project:1 aviko`.
+//!
+//! ```sh
+//! HSET mars:project:aviko needs_auth true defaults '{"url": "https://httpbin.org/", "method": "ANY"}'
+//! HSET mars:subprojects:aviko json '{"url": "json"}'
+//! PUBLISH mars:invalidate aviko
+//! ```
+//!
+//! Projects are cached along with services built for them. A replica drops a cached project when
+//! its index is published on `mars:invalidate` (`*` drops every project), and on keyspace
+//! notifications of its keys when redis sends them (`notify-keyspace-events Kh`). Whole cache is
+//! dropped whenever subscription is lost, as changes may have been missed meanwhile. A project
+//! dropped while it was being loaded is not cached, so that config it was loaded with isn't kept.
+//! Tokens are looked up on every request, and are never logged. Usage against quotas is counted
+//! by each replica on its own.
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::redis::{aio::ConnectionManager, AsyncCommands, Client, ErrorKind, RedisResult};
+use dashmap::DashMap;
+use futures::StreamExt;
+use mars_config::{secrets_generation, ClientIdentity, MarsError};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::file::{FileBasedProject, TokenGrant};
+use crate::project::{AuthProjectRequestHandler, AuthToken, ConfigError, Grant, ProjectManager};
+use crate::quota::UsageTracker;
+
+/// prefix of keys when not configured
+pub const DEFAULT_PREFIX: &str = "mars";
+
+/// time between attempts to subscribe again, once subscription is lost
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+type Projects = Arc<Cache>;
+
+/// projects cached along with services built for them
+#[derive(Default)]
+struct Cache {
+    projects: DashMap<String, Arc<Box<dyn AuthProjectRequestHandler>>>,
+    /// counts projects dropped, so that projects loaded meanwhile are not cached
+    drops: AtomicU64,
+}
+
+impl Cache {
+    fn get(&self, index: &str) -> Option<Arc<Box<dyn AuthProjectRequestHandler>>> {
+        self.projects.get(index).map(|x| x.clone())
+    }
+
+    /// to be taken before loading a project, and given to [`Cache::insert`] along with it
+    fn generation(&self) -> u64 {
+        self.drops.load(Ordering::Acquire)
+    }
+
+    /// caches `project` loaded in `generation`, unless a project was dropped since
+    fn insert(
+        &self,
+        index: String,
+        project: Arc<Box<dyn AuthProjectRequestHandler>>,
+        generation: u64,
+    ) {
+        self.projects.insert(index.clone(), project.clone());
+        // a drop counted after this check removes project itself, as it comes after insert
+        if self.generation() != generation {
+            self.projects
+                .remove_if(&index, |_, cached| Arc::ptr_eq(cached, &project));
+        }
+    }
+
+    fn remove(&self, index: &str) {
+        self.drops.fetch_add(1, Ordering::AcqRel);
+        self.projects.remove(index);
+    }
+
+    fn clear(&self) {
+        self.drops.fetch_add(1, Ordering::AcqRel);
+        self.projects.clear();
+    }
+}
+
+/// token or identity `key` as it can be logged. identities are, tokens are only told apart by
+/// a hash prefix
+fn loggable(key: &str) -> String {
+    if ClientIdentity::is_identity_key(key) {
+        key.to_string()
+    } else {
+        format!("token {}", &hex::encode(Sha256::digest(key))[..12])
+    }
+}
+
+/// names of keys and channels under a prefix
+#[derive(Clone, Debug)]
+struct Keys {
+    prefix: String,
+}
+
+impl Keys {
+    fn project(&self, index: &str) -> String {
+        format!("{}:project:{index}", self.prefix)
+    }
+
+    fn subprojects(&self, index: &str) -> String {
+        format!("{}:subprojects:{index}", self.prefix)
+    }
+
+    fn tokens(&self) -> String {
+        format!("{}:tokens", self.prefix)
+    }
+
+    fn invalidate(&self) -> String {
+        format!("{}:invalidate", self.prefix)
+    }
+
+    /// project a key belongs to, `None` when it is not a project or subprojects key
+    fn project_of<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let key = key.strip_prefix(&self.prefix)?.strip_prefix(':')?;
+        key.strip_prefix("project:")
+            .or_else(|| key.strip_prefix("subprojects:"))
+    }
+
+    /// project a keyspace notification channel, `__keyspace@0__:mars:project:aviko`, is about
+    fn notified_project<'a>(&self, channel: &'a str) -> Option<&'a str> {
+        self.project_of(channel.split_once("__:")?.1)
+    }
+}
+
+/// project config, as it is in config file, of fields and subprojects hashes of a project.
+/// `None` when both are empty, project doesn't exist
+fn project_config(
+    fields: HashMap<String, String>,
+    subprojects: HashMap<String, String>,
+) -> Result<Option<Value>, MarsError> {
+    if fields.is_empty() && subprojects.is_empty() {
+        return Ok(None);
+    }
+    let parse = |kind: &str, key: &str, value: &str| {
+        serde_json::from_str::<Value>(value).map_err(|err| {
+            MarsError::ServiceConfigError(format!("{kind} `{key}` is not json: {err}"))
+        })
+    };
+    let mut config = Map::new();
+    for (key, value) in &fields {
+        config.insert(key.clone(), parse("field", key, value)?);
+    }
+    let mut services = Map::new();
+    for (key, value) in &subprojects {
+        services.insert(key.clone(), parse("subproject", key, value)?);
+    }
+    config.insert("subprojects".to_string(), Value::Object(services));
+    Ok(Some(Value::Object(config)))
+}
+
+/// grant of a tokens hash value, json or a project index as it is
+fn parse_grant(value: &str) -> Result<TokenGrant, MarsError> {
+    if value.trim_start().starts_with(['{', '"']) {
+        serde_json::from_str(value)
+            .map_err(|err| MarsError::ServiceConfigError(format!("grant is not parsable: {err}")))
+    } else {
+        Ok(TokenGrant::Project(value.to_string()))
+    }
+}
+
+async fn load_project(
+    connection: &mut ConnectionManager,
+    keys: &Keys,
+    index: &str,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    let fields: HashMap<String, String> = connection.hgetall(keys.project(index)).await?;
+    let subprojects: HashMap<String, String> = connection.hgetall(keys.subprojects(index)).await?;
+    Ok(project_config(fields, subprojects)?)
+}
+
+/// Project manager over Redis, see [module](self) for how projects are kept.
+#[derive(Clone)]
+pub(crate) struct RedisProjectManager {
+    connection: ConnectionManager,
+    keys: Keys,
+    projects: Projects,
+    usage: Arc<UsageTracker>,
+    /// secrets generation cached projects were built in
+    generation: Arc<AtomicU64>,
+}
+
+#[async_trait::async_trait]
+impl ProjectManager for RedisProjectManager {
+    async fn get_project(
+        &self,
+        project_key: String,
+    ) -> Result<Option<Arc<Box<dyn AuthProjectRequestHandler>>>, Box<dyn Error>> {
+        let generation = secrets_generation();
+        if self.generation.swap(generation, Ordering::AcqRel) != generation {
+            // services of cached projects hold secrets that were rotated since
+            self.projects.clear();
+        }
+        if let Some(project) = self.projects.get(&project_key) {
+            return Ok(Some(project));
+        }
+        let generation = self.projects.generation();
+        let mut connection = self.connection.clone();
+        match load_project(&mut connection, &self.keys, &project_key).await? {
+            Some(config) => {
+                let project: Box<dyn AuthProjectRequestHandler> =
+                    Box::new(FileBasedProject::try_from(config).map_err(|err| {
+                        MarsError::ServiceConfigError(format!(
+                            "project `{project_key}` is not valid: {err}"
+                        ))
+                    })?);
+                let project = Arc::new(project);
+                self.projects
+                    .insert(project_key, project.clone(), generation);
+                Ok(Some(project))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn exists(&self, token: &AuthToken, project_index: &str) -> bool {
+        self.grant(token, project_index).await.is_some()
+    }
+
+    async fn identity_exists(&self, identity: &ClientIdentity, project_index: &str) -> bool {
+        self.identity_grant(identity, project_index).await.is_some()
+    }
+
+    async fn grant(&self, token: &AuthToken, project_index: &str) -> Option<Grant> {
+        // identities can only be matched by a verified client certificate
+        if ClientIdentity::is_identity_key(&token.0) {
+            return None;
+        }
+        self.lookup(&token.0)
+            .await?
+            .grant_for(&format!("token:{}", token.0), project_index)
+    }
+
+    async fn identity_grant(
+        &self,
+        identity: &ClientIdentity,
+        project_index: &str,
+    ) -> Option<Grant> {
+        for candidate in identity.candidates() {
+            let grant = self
+                .lookup(&candidate)
+                .await
+                .and_then(|grant| grant.grant_for(&candidate, project_index));
+            if grant.is_some() {
+                return grant;
+            }
+        }
+        None
+    }
+
+    async fn consume_quota(&self, grant: &Grant) -> Option<u64> {
+        self.usage.consume(&grant.quotas).await
+    }
+
+    /// drops project here, and on other replicas through `invalidate` channel
+    async fn invalidate(&self, project_index: &str) {
+        self.projects.remove(project_index);
+        let mut connection = self.connection.clone();
+        let published: RedisResult<()> = connection
+            .publish(self.keys.invalidate(), project_index)
+            .await;
+        if let Err(err) = published {
+            log::error!(
+                "unable to publish invalidation of {}: {}",
+                project_index,
+                err
+            );
+        }
+    }
+}
+
+impl RedisProjectManager {
+    /// grant of token or identity `key`, `None` when there is none or it can't be read
+    async fn lookup(&self, key: &str) -> Option<TokenGrant> {
+        let mut connection = self.connection.clone();
+        let value: RedisResult<Option<String>> = connection.hget(self.keys.tokens(), key).await;
+        let value = match value {
+            Ok(value) => value?,
+            Err(err) => {
+                log::error!("unable to look up grant of {}: {}", loggable(key), err);
+                return None;
+            }
+        };
+        match parse_grant(&value) {
+            Ok(grant) => Some(grant),
+            Err(err) => {
+                log::error!(
+                    "grant of {} is not valid: {}",
+                    loggable(key),
+                    err.to_string().trim_end()
+                );
+                None
+            }
+        }
+    }
+}
+
+/// drops cached projects as they change, for as long as process runs
+fn watch(client: Client, keys: Keys, projects: Projects) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = listen(&client, &keys, &projects).await {
+                log::error!("lost redis subscription, {}", err);
+            }
+            projects.clear();
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn listen(client: &Client, keys: &Keys, projects: &Projects) -> RedisResult<()> {
+    let mut pubsub = client.get_tokio_connection().await?.into_pubsub();
+    pubsub.subscribe(keys.invalidate()).await?;
+    for pattern in [keys.project("*"), keys.subprojects("*")] {
+        pubsub
+            .psubscribe(format!("__keyspace@*__:{pattern}"))
+            .await?;
+    }
+    // changes made before subscribing were not seen
+    projects.clear();
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let project = if message.from_pattern() {
+            keys.notified_project(message.get_channel_name())
+                .map(str::to_string)
+        } else {
+            message.get_payload::<String>().ok()
+        };
+        match project.as_deref() {
+            Some("*") => projects.clear(),
+            Some(project) => {
+                projects.remove(project);
+            }
+            None => {}
+        }
+    }
+    Err((ErrorKind::IoError, "subscription closed").into())
+}
+
+/// Project manager over Redis at `url`, with keys under `prefix`.
+pub async fn get_redis_project_manager(
+    url: &str,
+    prefix: &str,
+) -> Result<Arc<Box<dyn ProjectManager>>, Box<dyn Error>> {
+    let client = Client::open(url)?;
+    let connection = client.get_connection_manager().await?;
+    let keys = Keys {
+        prefix: prefix.to_string(),
+    };
+    let projects = Projects::default();
+    watch(client, keys.clone(), projects.clone());
+    Ok(Arc::new(Box::new(RedisProjectManager {
+        connection,
+        keys,
+        projects,
+        usage: Default::default(),
+        generation: Default::default(),
+    })))
+}
+
+/// Builds service of every subproject in Redis, returning each one that doesn't build.
+pub async fn validate_redis_config(
+    url: &str,
+    prefix: &str,
+) -> Result<Vec<ConfigError>, Box<dyn Error>> {
+    let mut connection = Client::open(url)?.get_connection_manager().await?;
+    let keys = Keys {
+        prefix: prefix.to_string(),
+    };
+    let mut indexes = vec![];
+    for pattern in [keys.project("*"), keys.subprojects("*")] {
+        let mut iter = connection.scan_match::<_, String>(pattern).await?;
+        while let Some(key) = iter.next_item().await {
+            if let Some(index) = keys.project_of(&key) {
+                indexes.push(index.to_string());
+            }
+        }
+    }
+    indexes.sort();
+    indexes.dedup();
+    let mut errors = vec![];
+    for index in indexes {
+        match load_project(&mut connection, &keys, &index).await {
+            Ok(Some(config)) => errors.extend(FileBasedProject::validate(&index, config)),
+            Ok(None) => {}
+            Err(err) => match err.downcast::<MarsError>() {
+                Ok(err) => errors.push(ConfigError::new(index, &err)),
+                Err(err) => return Err(err),
+            },
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::TcpListener;
+
+    use super::{loggable, parse_grant, project_config, Cache, Keys};
+    use crate::file::{FileBasedProject, TokenGrant};
+    use crate::project::{AuthProjectRequestHandler, AuthToken};
+
+    type Writer = Arc<tokio::sync::Mutex<OwnedWriteHalf>>;
+
+    /// hashes of redis stub, and connections subscribed to each channel or pattern
+    #[derive(Default)]
+    struct Store {
+        hashes: HashMap<String, HashMap<String, String>>,
+        subscribers: Vec<(String, Writer)>,
+    }
+
+    fn bulk(value: &str) -> String {
+        format!("${}\r\n{value}\r\n", value.len())
+    }
+
+    async fn read_command(read: &mut BufReader<OwnedReadHalf>) -> Option<Vec<String>> {
+        let mut line = String::new();
+        read.read_line(&mut line)
+            .await
+            .ok()
+            .filter(|read| *read > 0)?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = vec![];
+        for _ in 0..count {
+            line.clear();
+            read.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            read.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    /// reply to `command`, along with messages it pushes to subscribers
+    fn reply(
+        store: &Mutex<Store>,
+        writer: &Writer,
+        command: &[String],
+    ) -> (String, Vec<(Writer, String)>) {
+        let name = command[0].to_ascii_uppercase();
+        let mut store = store.lock().unwrap();
+        let reply = match (name.as_str(), &command[1..]) {
+            ("HGETALL", [key]) => {
+                let hash = store.hashes.get(key).cloned().unwrap_or_default();
+                let fields: String = hash
+                    .iter()
+                    .map(|(field, value)| bulk(field) + &bulk(value))
+                    .collect();
+                format!("*{}\r\n{fields}", hash.len() * 2)
+            }
+            ("HGET", [key, field]) => match store.hashes.get(key).and_then(|x| x.get(field)) {
+                Some(value) => bulk(value),
+                None => "$-1\r\n".to_string(),
+            },
+            ("SUBSCRIBE" | "PSUBSCRIBE", [channel]) => {
+                store.subscribers.push((channel.clone(), writer.clone()));
+                format!(
+                    "*3\r\n{}{}:1\r\n",
+                    bulk(&name.to_ascii_lowercase()),
+                    bulk(channel)
+                )
+            }
+            ("PUBLISH", [channel, message]) => {
+                let pushed = format!(
+                    "*3\r\n{}{}{}",
+                    bulk("message"),
+                    bulk(channel),
+                    bulk(message)
+                );
+                let pushes: Vec<(Writer, String)> = store
+                    .subscribers
+                    .iter()
+                    .filter(|(subscribed, _)| subscribed == channel)
+                    .map(|(_, writer)| (writer.clone(), pushed.clone()))
+                    .collect();
+                return (format!(":{}\r\n", pushes.len()), pushes);
+            }
+            _ => "+OK\r\n".to_string(),
+        };
+        (reply, vec![])
+    }
+
+    /// stub of redis answering the few commands manager sends, url to connect to it
+    async fn serve(store: Arc<Mutex<Store>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let (read, write) = socket.into_split();
+                    let mut read = BufReader::new(read);
+                    let writer = Arc::new(tokio::sync::Mutex::new(write));
+                    while let Some(command) = read_command(&mut read).await {
+                        let (reply, pushes) = reply(&store, &writer, &command);
+                        for (subscriber, pushed) in pushes {
+                            let _ = subscriber.lock().await.write_all(pushed.as_bytes()).await;
+                        }
+                        if writer
+                            .lock()
+                            .await
+                            .write_all(reply.as_bytes())
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        format!("redis://{addr}/")
+    }
+
+    #[tokio::test]
+    async fn test_redis() {
+        let hash = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let store = Arc::new(Mutex::new(Store::default()));
+        store.lock().unwrap().hashes = [
+            (
+                "mars:tokens".to_string(),
+                hash(&[("project:1", "aviko"), ("broken", "{")]),
+            ),
+            (
+                "mars:project:aviko".to_string(),
+                hash(&[("needs_auth", "true")]),
+            ),
+            (
+                "mars:subprojects:aviko".to_string(),
+                hash(&[(
+                    "json",
+                    r#"{"url": "http://localhost:1/json", "method": "ANY"}"#,
+                )]),
+            ),
+        ]
+        .into();
+        let url = serve(store.clone()).await;
+        let replica = super::get_redis_project_manager(&url, "mars")
+            .await
+            .unwrap();
+        let other = super::get_redis_project_manager(&url, "mars")
+            .await
+            .unwrap();
+
+        let token = |token: &str| AuthToken(token.to_string());
+        assert!(replica.grant(&token("project:1"), "aviko").await.is_some());
+        assert!(replica.grant(&token("project:1"), "other").await.is_none());
+        assert!(replica.grant(&token("project:2"), "aviko").await.is_none());
+        assert!(replica.grant(&token("broken"), "aviko").await.is_none());
+
+        // both replicas subscribe to invalidations and to keyspace notifications of two patterns
+        for _ in 0..500 {
+            if store.lock().unwrap().subscribers.len() == 6 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // cache each replica clears once subscribed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let project = || async {
+            other
+                .get_project("aviko".to_string())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        let cached = project().await;
+        assert!(Arc::ptr_eq(&cached, &project().await));
+
+        // other replica drops project once invalidation published by this one reaches it
+        replica.invalidate("aviko").await;
+        let mut dropped = false;
+        for _ in 0..500 {
+            if !Arc::ptr_eq(&cached, &project().await) {
+                dropped = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dropped);
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = Cache::default();
+        let project = || -> Arc<Box<dyn AuthProjectRequestHandler>> {
+            Arc::new(Box::new(
+                FileBasedProject::try_from(json!({"subprojects": {}})).unwrap(),
+            ))
+        };
+        // project dropped while it was loaded is not cached
+        let generation = cache.generation();
+        cache.remove("aviko");
+        cache.insert("aviko".to_string(), project(), generation);
+        assert!(cache.get("aviko").is_none());
+        cache.insert("aviko".to_string(), project(), cache.generation());
+        assert!(cache.get("aviko").is_some());
+    }
+
+    #[test]
+    fn test_loggable() {
+        assert!(!loggable("project:1").contains("project:1"));
+        assert_eq!(loggable("project:1"), loggable("project:1"));
+        assert_ne!(loggable("project:1"), loggable("project:2"));
+        assert_eq!(loggable("san:neptune.internal"), "san:neptune.internal");
+    }
+
+    #[test]
+    fn test_keys() {
+        let keys = Keys {
+            prefix: "mars".to_string(),
+        };
+        assert_eq!(keys.project("aviko"), "mars:project:aviko");
+        assert_eq!(
+            keys.notified_project("__keyspace@0__:mars:subprojects:aviko"),
+            Some("aviko")
+        );
+        assert_eq!(keys.notified_project("__keyspace@0__:mars:tokens"), None);
+        assert_eq!(
+            keys.notified_project("__keyspace@0__:marsh:project:aviko"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_project_config() {
+        let hash = |entries: &[(&str, &str)]| -> HashMap<String, String> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(project_config(hash(&[]), hash(&[])).unwrap(), None);
+
+        let config = project_config(
+            hash(&[
+                ("needs_auth", "false"),
+                (
+                    "defaults",
+                    r#"{"url": "http://localhost:1/", "method": "ANY"}"#,
+                ),
+            ]),
+            hash(&[("json", r#"{"url": "json"}"#)]),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "needs_auth": false,
+                "defaults": {"url": "http://localhost:1/", "method": "ANY"},
+                "subprojects": {"json": {"url": "json"}}
+            })
+        );
+        assert!(FileBasedProject::try_from(config).is_ok());
+
+        assert!(project_config(hash(&[("needs_auth", "yes")]), hash(&[])).is_err());
+    }
+
+    #[test]
+    fn test_parse_grant() {
+        assert!(matches!(
+            parse_grant("aviko").unwrap(),
+            TokenGrant::Project(project) if project == "aviko"
+        ));
+        assert!(matches!(
+            parse_grant(r#""aviko""#).unwrap(),
+            TokenGrant::Project(project) if project == "aviko"
+        ));
+        let grant = parse_grant(r#"{"project": "aviko", "quota": {"daily": 1}}"#).unwrap();
+        assert_eq!(grant.grant_for("token:1", "aviko").unwrap().quotas.len(), 1);
+        assert!(grant.grant_for("token:1", "other").is_none());
+        assert!(parse_grant(r#"{"quota": {"daily": 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! Config and tokens loaded from a local file or an http(s) url.
+//!
+//! Remote sources are fetched with the headers they are configured with, `Authorization` for
+//! example. The `ETag` of a response is remembered and sent back as `If-None-Match`, so polling
+//! an unchanged source costs a `304` instead of the whole document. Format of a source is picked
+//! by its extension, unless it is given.
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use http::header::{HeaderName, ETAG, IF_NONE_MATCH};
+use http::{HeaderMap, HeaderValue, Request, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use hyper_tls::HttpsConnector;
+use mars_config::{ConfigFormat, MarsError};
+use tokio::sync::Mutex;
+
+/// how long a remote source may take to respond
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum Location {
+    File(PathBuf),
+    Remote(Uri),
+}
+
+/// `Link` is where config or tokens are read from, a path or an `http(s)://` url.
+pub struct Link {
+    location: Location,
+    format: ConfigFormat,
+    headers: HeaderMap,
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// `ETag` and body of last response
+    cached: Mutex<Option<(HeaderValue, String)>>,
+}
+
+impl Link {
+    /// `headers` are only sent to remote sources
+    pub fn new(link: &str, headers: HeaderMap) -> Result<Self, MarsError> {
+        let location = if link.starts_with("http://") || link.starts_with("https://") {
+            Location::Remote(
+                link.parse()
+                    .map_err(|err| MarsError::UrlError(format!("`{link}` is not valid, {err}")))?,
+            )
+        } else {
+            Location::File(link.into())
+        };
+        // extension of remote source is the one of its path, not of its host or query
+        let format = match &location {
+            Location::File(_) => ConfigFormat::from_path(link),
+            Location::Remote(uri) => ConfigFormat::from_path(uri.path()),
+        };
+        Ok(Link {
+            location,
+            format,
+            headers,
+            client: Client::builder().build(HttpsConnector::new()),
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// source in `format`, instead of the one its extension suggests
+    pub fn with_format(mut self, format: ConfigFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        self.format
+    }
+
+    /// contents of source, the cached body when remote source responds with `304 Not Modified`
+    pub async fn fetch(&self) -> Result<String, MarsError> {
+        self.fetch_if_exists().await?.ok_or_else(|| {
+            MarsError::ServiceConfigError(format!("unable to read {self}, it doesn't exist"))
+        })
+    }
+
+    /// same as [`Link::fetch`], but `None` when file is not found or remote source responds with
+    /// `404 Not Found`
+    pub async fn fetch_if_exists(&self) -> Result<Option<String>, MarsError> {
+        match &self.location {
+            Location::File(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => Ok(Some(contents)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(MarsError::ServiceConfigError(format!(
+                    "unable to read {}, {err}",
+                    path.display()
+                ))),
+            },
+            Location::Remote(uri) => self.fetch_remote(uri).await,
+        }
+    }
+
+    async fn fetch_remote(&self, uri: &Uri) -> Result<Option<String>, MarsError> {
+        let error = |error: &dyn Display| {
+            MarsError::ServiceConfigError(format!("unable to download {uri}, {error}"))
+        };
+        let mut cached = self.cached.lock().await;
+        let mut request = Request::builder()
+            .uri(uri.clone())
+            .body(Body::empty())
+            .unwrap();
+        *request.headers_mut() = self.headers.clone();
+        if let Some((etag, _)) = cached.as_ref() {
+            request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        }
+        let response = tokio::time::timeout(FETCH_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| error(&"timed out"))?
+            .map_err(|err| error(&err))?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some((_, body)) = cached.as_ref() {
+                return Ok(Some(body.clone()));
+            }
+        }
+        if status == StatusCode::NOT_FOUND {
+            *cached = None;
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(error(&format!("status {status}")));
+        }
+        let etag = response.headers().get(ETAG).cloned();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| error(&err))?;
+        let body = String::from_utf8(body.to_vec()).map_err(|err| error(&err))?;
+        *cached = etag.map(|etag| (etag, body.clone()));
+        Ok(Some(body))
+    }
+}
+
+impl Display for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Location::File(path) => write!(f, "{}", path.display()),
+            Location::Remote(uri) => write!(f, "{uri}"),
+        }
+    }
+}
+
+/// parses a `Name: value` header, as given on command line
+pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), MarsError> {
+    let invalid = || MarsError::ServiceConfigError(format!("header `{header}` is not valid"));
+    let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+    Ok((
+        name.trim().parse().map_err(|_| invalid())?,
+        value.trim().parse().map_err(|_| invalid())?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use http::{HeaderMap, Request, Response};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Server};
+
+    use mars_config::ConfigFormat;
+
+    use super::{parse_header, Link};
+
+    /// serves `document` with its version as `ETag`, to requests carrying `authorization: secret`.
+    /// every request is logged with its `If-None-Match`. `/missing` is not found
+    fn serve(document: Arc<Mutex<(u32, String)>>, log: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+        let make_service = make_service_fn(move |_| {
+            let (document, log) = (document.clone(), log.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (document, log) = (document.clone(), log.clone());
+                    async move {
+                        let header = |name| {
+                            request
+                                .headers()
+                                .get(name)
+                                .and_then(|x| x.to_str().ok())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        log.lock().unwrap().push(header("if-none-match"));
+                        let (version, body) = document.lock().unwrap().clone();
+                        let etag = format!("\"{version}\"");
+                        let response = if header("authorization") != "secret" {
+                            Response::builder().status(401).body(Body::empty())
+                        } else if request.uri().path() == "/missing" {
+                            Response::builder().status(404).body(Body::empty())
+                        } else if header("if-none-match") == etag {
+                            Response::builder().status(304).body(Body::empty())
+                        } else {
+                            Response::builder()
+                                .header("etag", etag)
+                                .body(Body::from(body))
+                        };
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_remote() {
+        let document = Arc::new(Mutex::new((1, "{aviko: {}}".to_string())));
+        let log = Arc::new(Mutex::new(vec![]));
+        let addr = serve(document.clone(), log.clone());
+        let url = format!("http://{addr}/config.json5");
+
+        let mut headers = HeaderMap::new();
+        let (name, value) = parse_header("Authorization: secret").unwrap();
+        headers.insert(name, value);
+        let link = Link::new(&url, headers).unwrap();
+        assert_eq!(link.fetch().await.unwrap(), "{aviko: {}}");
+        assert_eq!(link.fetch().await.unwrap(), "{aviko: {}}");
+        *document.lock().unwrap() = (2, "{other: {}}".to_string());
+        assert_eq!(link.fetch().await.unwrap(), "{other: {}}");
+        assert_eq!(*log.lock().unwrap(), vec!["", "\"1\"", "\"1\""]);
+
+        let error = Link::new(&url, HeaderMap::new())
+            .unwrap()
+            .fetch()
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("401"));
+        assert!(parse_header("Authorization").is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "secret".parse().unwrap());
+        let missing = Link::new(&format!("http://{addr}/missing"), headers).unwrap();
+        assert_eq!(missing.fetch_if_exists().await.unwrap(), None);
+        assert!(missing.fetch().await.is_err());
+        let missing = Link::new("/nonexistent/mars-rover/config.json5", HeaderMap::new()).unwrap();
+        assert_eq!(missing.fetch_if_exists().await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let format = |link| Link::new(link, HeaderMap::new()).unwrap().format();
+        assert_eq!(format("http://localhost/config.yaml"), ConfigFormat::Yaml);
+        assert_eq!(
+            format("https://localhost/config.toml?v=1.json#x.yaml"),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            format("https://localhost/config?v=1.yaml"),
+            ConfigFormat::Json5
+        );
+        assert_eq!(format("https://configs.toml"), ConfigFormat::Json5);
+        assert_eq!(format("https://configs.toml/"), ConfigFormat::Json5);
+        assert_eq!(format("config.yml"), ConfigFormat::Yaml);
+    }
+}
@@ -0,0 +1,414 @@
+//! Verification of HMAC signed inbound requests, for projects that are called by webhooks.
+//!
+//! The body is buffered to compute the signature and is then handed to the upstream unchanged.
+//! Signatures of requests the upstream accepted are remembered per instance for the tolerance of
+//! a scheme, so a captured request can't be replayed. A request the upstream failed with a 5xx,
+//! or couldn't be sent to, is not remembered, so that webhook retries get through. GitHub signs
+//! the body alone, with no timestamp to expire, so its signatures are remembered for
+//! [`UNTIMESTAMPED_RETENTION`] instead. A delivery captured earlier than that, or replayed to
+//! another instance or after a restart, is still accepted. At most [`MAX_REMEMBERED_SIGNATURES`]
+//! are remembered, the oldest are forgotten first.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, Request};
+use hyper::{body::HttpBody, Body};
+use mars_config::SignatureScheme;
+use sha2::Sha256;
+
+/// largest body that is buffered for verification
+pub const MAX_SIGNED_BODY: usize = 5 * 1024 * 1024;
+
+/// how long signatures of schemes without a timestamp are remembered
+pub const UNTIMESTAMPED_RETENTION: u64 = 7 * 24 * 60 * 60;
+
+/// most signatures a verifier remembers
+pub const MAX_REMEMBERED_SIGNATURES: usize = 100_000;
+
+const GITHUB_SIGNATURE: &str = "x-hub-signature-256";
+const STRIPE_SIGNATURE: &str = "stripe-signature";
+const SLACK_SIGNATURE: &str = "x-slack-signature";
+const SLACK_TIMESTAMP: &str = "x-slack-request-timestamp";
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Expired,
+    Invalid,
+    Replayed,
+    TooLarge,
+    Body(hyper::Error),
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "request signature not provided"),
+            SignatureError::Expired => write!(f, "request signature expired"),
+            SignatureError::Invalid => write!(f, "request signature not valid"),
+            SignatureError::Replayed => write!(f, "request signature already used"),
+            SignatureError::TooLarge => {
+                write!(f, "signed body is larger than {MAX_SIGNED_BODY} bytes")
+            }
+            SignatureError::Body(error) => write!(f, "unable to read body: {error}"),
+        }
+    }
+}
+
+impl SignatureError {
+    /// http status request is rejected with
+    pub fn status(&self) -> u16 {
+        match self {
+            SignatureError::TooLarge => 413,
+            SignatureError::Body(_) => 400,
+            _ => 401,
+        }
+    }
+}
+
+/// `Verified` is a request whose signature is valid, to be remembered by
+/// [`SignatureVerifier::remember`] once the upstream accepted it.
+#[derive(Debug)]
+pub struct Verified {
+    signature: Vec<u8>,
+    expires_at: u64,
+}
+
+/// signatures remembered, along with when they expire in order they were remembered
+#[derive(Default)]
+struct Seen {
+    signatures: HashMap<Vec<u8>, u64>,
+    expiry: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl Seen {
+    fn forget_oldest(&mut self) {
+        if let Some((expires_at, signature)) = self.expiry.pop_front() {
+            // signature may have been remembered again since
+            if self.signatures.get(&signature) == Some(&expires_at) {
+                self.signatures.remove(&signature);
+            }
+        }
+    }
+}
+
+/// `SignatureVerifier` verifies requests signed with a [`SignatureScheme`].
+pub struct SignatureVerifier {
+    scheme: SignatureScheme,
+    seen: Mutex<Seen>,
+    capacity: usize,
+}
+
+impl SignatureVerifier {
+    pub fn new(scheme: SignatureScheme) -> Self {
+        SignatureVerifier {
+            scheme,
+            seen: Default::default(),
+            capacity: MAX_REMEMBERED_SIGNATURES,
+        }
+    }
+
+    /// verifies signature of `request`, its body is buffered and put back
+    pub async fn verify(&self, request: &mut Request<Body>) -> Result<Verified, SignatureError> {
+        let body = read_body(request.body_mut()).await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        let result = self.verify_at(request.headers(), &body, now);
+        *request.body_mut() = Body::from(body);
+        result
+    }
+
+    fn verify_at(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: u64,
+    ) -> Result<Verified, SignatureError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .ok_or(SignatureError::Missing)
+        };
+        let (timestamp, prefix, signatures) = match &self.scheme {
+            SignatureScheme::Github { .. } => {
+                let signature = header(GITHUB_SIGNATURE)?;
+                (None, vec![], vec![strip(signature, "sha256=")?])
+            }
+            SignatureScheme::Stripe { .. } => {
+                let mut timestamp = None;
+                let mut signatures = vec![];
+                for pair in header(STRIPE_SIGNATURE)?.split(',') {
+                    match pair.trim().split_once('=') {
+                        Some(("t", value)) => timestamp = Some(value),
+                        Some(("v1", value)) => signatures.push(value),
+                        _ => {}
+                    }
+                }
+                let timestamp = timestamp.ok_or(SignatureError::Missing)?;
+                (Some(timestamp), format!("{timestamp}.").into_bytes(), signatures)
+            }
+            SignatureScheme::Slack { .. } => {
+                let timestamp = header(SLACK_TIMESTAMP)?;
+                let signature = strip(header(SLACK_SIGNATURE)?, "v0=")?;
+                (
+                    Some(timestamp),
+                    format!("v0:{timestamp}:").into_bytes(),
+                    vec![signature],
+                )
+            }
+            SignatureScheme::Hmac {
+                header: signature_header,
+                prefix,
+                timestamp_header,
+                ..
+            } => {
+                let timestamp = header(timestamp_header)?;
+                let signature = strip(header(signature_header)?, prefix)?;
+                (
+                    Some(timestamp),
+                    format!("{timestamp}.").into_bytes(),
+                    vec![signature],
+                )
+            }
+        };
+        let tolerance = self.scheme.tolerance();
+        if let Some(timestamp) = timestamp {
+            let timestamp: u64 = timestamp.parse().map_err(|_| SignatureError::Invalid)?;
+            if now.abs_diff(timestamp) > tolerance {
+                return Err(SignatureError::Expired);
+            }
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.scheme.secret().as_bytes())
+            .expect("hmac accepts keys of any size");
+        mac.update(&prefix);
+        mac.update(body);
+        let signature = signatures
+            .into_iter()
+            .filter_map(|signature| hex::decode(signature).ok())
+            // verify_slice compares in constant time
+            .find(|signature| mac.clone().verify_slice(signature).is_ok())
+            .ok_or(SignatureError::Invalid)?;
+
+        let retention = match timestamp {
+            Some(_) => tolerance,
+            // nothing expires the signature, it is remembered for as long as is affordable
+            None => tolerance.max(UNTIMESTAMPED_RETENTION),
+        };
+        let seen = self
+            .seen
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if matches!(seen.signatures.get(&signature), Some(expires_at) if *expires_at >= now) {
+            return Err(SignatureError::Replayed);
+        }
+        Ok(Verified {
+            signature,
+            expires_at: now + retention,
+        })
+    }
+
+    /// remembers signature of a request upstream accepted, so that it can't be replayed
+    pub fn remember(&self, verified: Verified) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        self.remember_at(verified, now);
+    }
+
+    fn remember_at(&self, verified: Verified, now: u64) {
+        let mut seen = self
+            .seen
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // expiry is in order, pruning stops at the first signature still remembered
+        while matches!(seen.expiry.front(), Some((expires_at, _)) if *expires_at < now)
+            || (!seen.expiry.is_empty() && seen.expiry.len() >= self.capacity)
+        {
+            seen.forget_oldest();
+        }
+        seen.signatures
+            .insert(verified.signature.clone(), verified.expires_at);
+        seen.expiry
+            .push_back((verified.expires_at, verified.signature));
+    }
+}
+
+fn strip<'a>(value: &'a str, prefix: &str) -> Result<&'a str, SignatureError> {
+    value.strip_prefix(prefix).ok_or(SignatureError::Invalid)
+}
+
+async fn read_body(body: &mut Body) -> Result<Vec<u8>, SignatureError> {
+    let mut buffer = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(SignatureError::Body)?;
+        if buffer.len() + chunk.len() > MAX_SIGNED_BODY {
+            return Err(SignatureError::TooLarge);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use hmac::{Hmac, Mac};
+    use http::HeaderMap;
+    use mars_config::SignatureScheme;
+    use sha2::Sha256;
+
+    use super::{SignatureError, SignatureVerifier, UNTIMESTAMPED_RETENTION};
+
+    const NOW: u64 = 1_700_000_000;
+    const BODY: &[u8] = br#"{"event": "push"}"#;
+
+    fn sign(payload: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(payload);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_github() {
+        let verifier = SignatureVerifier::new(SignatureScheme::Github {
+            secret: "secret".to_string(),
+            tolerance: 300,
+        });
+        let signed = headers(&[("x-hub-signature-256", format!("sha256={}", sign(BODY)))]);
+        // upstream failed, the retry is accepted
+        assert!(verifier.verify_at(&signed, BODY, NOW).is_ok());
+        let verified = verifier.verify_at(&signed, BODY, NOW).unwrap();
+        verifier.remember_at(verified, NOW);
+        assert!(matches!(
+            verifier.verify_at(&signed, BODY, NOW + 1),
+            Err(SignatureError::Replayed)
+        ));
+        // signature has no timestamp, a replay past tolerance is caught as well
+        assert!(matches!(
+            verifier.verify_at(&signed, BODY, NOW + 301),
+            Err(SignatureError::Replayed)
+        ));
+        assert!(matches!(
+            verifier.verify_at(&signed, BODY, NOW + UNTIMESTAMPED_RETENTION),
+            Err(SignatureError::Replayed)
+        ));
+        assert!(matches!(
+            verifier.verify_at(&signed, b"{}", NOW),
+            Err(SignatureError::Invalid)
+        ));
+        assert!(matches!(
+            verifier.verify_at(&HeaderMap::new(), BODY, NOW),
+            Err(SignatureError::Missing)
+        ));
+    }
+
+    #[test]
+    fn test_stripe() {
+        let verifier = SignatureVerifier::new(SignatureScheme::Stripe {
+            secret: "secret".to_string(),
+            tolerance: 300,
+        });
+        let payload = [format!("{NOW}.").as_bytes(), BODY].concat();
+        let signed = headers(&[(
+            "stripe-signature",
+            format!("t={NOW},v1={},v1={}", sign(b"rotated"), sign(&payload)),
+        )]);
+        assert!(verifier.verify_at(&signed, BODY, NOW + 10).is_ok());
+        assert!(matches!(
+            verifier.verify_at(&signed, BODY, NOW + 301),
+            Err(SignatureError::Expired)
+        ));
+    }
+
+    #[test]
+    fn test_slack() {
+        let verifier = SignatureVerifier::new(SignatureScheme::Slack {
+            secret: "secret".to_string(),
+            tolerance: 300,
+        });
+        let payload = [format!("v0:{NOW}:").as_bytes(), BODY].concat();
+        let signed = headers(&[
+            ("x-slack-request-timestamp", NOW.to_string()),
+            ("x-slack-signature", format!("v0={}", sign(&payload))),
+        ]);
+        assert!(verifier.verify_at(&signed, BODY, NOW).is_ok());
+    }
+
+    #[test]
+    fn test_hmac() {
+        let scheme: SignatureScheme =
+            serde_json::from_str(r#"{"type": "hmac", "secret": "secret"}"#).unwrap();
+        let verifier = SignatureVerifier::new(scheme);
+        let payload = [format!("{NOW}.").as_bytes(), BODY].concat();
+        let signed = headers(&[
+            ("x-signature-timestamp", NOW.to_string()),
+            ("x-signature", format!("sha256={}", sign(&payload))),
+        ]);
+        // timestamp is part of signature, it can't be refreshed by replaying party
+        let mut tampered = signed.clone();
+        tampered.insert(
+            "x-signature-timestamp",
+            (NOW + 1).to_string().parse().unwrap(),
+        );
+        assert!(matches!(
+            verifier.verify_at(&tampered, BODY, NOW),
+            Err(SignatureError::Invalid)
+        ));
+        assert!(verifier.verify_at(&signed, BODY, NOW).is_ok());
+    }
+
+    #[test]
+    fn test_remembered() {
+        let mut verifier = SignatureVerifier::new(SignatureScheme::Github {
+            secret: "secret".to_string(),
+            tolerance: 300,
+        });
+        verifier.capacity = 2;
+        let delivery = |body: &[u8]| {
+            (
+                headers(&[("x-hub-signature-256", format!("sha256={}", sign(body)))]),
+                body.to_vec(),
+            )
+        };
+        let deliveries = [delivery(b"1"), delivery(b"2"), delivery(b"3")];
+        for (signed, body) in &deliveries {
+            let verified = verifier.verify_at(signed, body, NOW).unwrap();
+            verifier.remember_at(verified, NOW);
+        }
+        // oldest is forgotten past capacity
+        let (signed, body) = &deliveries[0];
+        assert!(verifier.verify_at(signed, body, NOW).is_ok());
+        let (signed, body) = &deliveries[2];
+        assert!(matches!(
+            verifier.verify_at(signed, body, NOW),
+            Err(SignatureError::Replayed)
+        ));
+        // and expired ones as soon as another is remembered
+        let later = NOW + UNTIMESTAMPED_RETENTION + 1;
+        assert!(verifier.verify_at(signed, body, later).is_ok());
+        let (signed, body) = delivery(b"4");
+        let verified = verifier.verify_at(&signed, &body, later).unwrap();
+        verifier.remember_at(verified, later);
+        let seen = verifier.seen.lock().unwrap();
+        assert_eq!(seen.signatures.len(), 1);
+        assert_eq!(seen.expiry.len(), 1);
+    }
+}
@@ -0,0 +1,193 @@
+//! TLS termination for mars-rover.
+//!
+//! When a client CA is configured, every client has to present a certificate signed by it. The
+//! certificate is turned into a [`ClientIdentity`] which is matched against grants instead of an
+//! avalanche token.
+use std::{fs::File, io::BufReader, net::IpAddr, path::PathBuf, sync::Arc};
+
+use mars_config::{ClientIdentity, MarsError};
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// Certificates used to terminate TLS.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// pem encoded certificate chain of the server
+    pub cert: PathBuf,
+    /// pem encoded private key of the server
+    pub key: PathBuf,
+    /// pem encoded CA bundle, when set client certificates are required
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn server_config(&self) -> Result<ServerConfig, MarsError> {
+        let certs = read_certificates(&self.cert)?;
+        let key = read_private_key(&self.key)?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let config = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificates(client_ca)? {
+                    roots.add(&certificate).map_err(|err| {
+                        MarsError::ServiceConfigError(format!("invalid client ca: {err}"))
+                    })?;
+                }
+                builder
+                    .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+                    .with_single_cert(certs, key)
+            }
+            None => builder
+                .with_no_client_auth()
+                .with_single_cert(certs, key),
+        }
+        .map_err(|err| MarsError::ServiceConfigError(format!("invalid tls config: {err}")))?;
+        Ok(config)
+    }
+
+    pub fn acceptor(&self) -> Result<tokio_rustls::TlsAcceptor, MarsError> {
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(
+            self.server_config()?,
+        )))
+    }
+}
+
+/// Derives identity of the client from its DER encoded certificate.
+pub fn client_identity(certificate: &[u8]) -> Result<ClientIdentity, MarsError> {
+    let (_, parsed) = X509Certificate::from_der(certificate).map_err(|err| {
+        MarsError::ServiceConfigError(format!("unable to parse client certificate: {err}"))
+    })?;
+    let mut sans = vec![];
+    if let Ok(Some(san)) = parsed.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+                    sans.push(name.to_string())
+                }
+                GeneralName::IPAddress(ip) => {
+                    if let Ok(ip) = <[u8; 4]>::try_from(*ip) {
+                        sans.push(IpAddr::from(ip).to_string());
+                    } else if let Ok(ip) = <[u8; 16]>::try_from(*ip) {
+                        sans.push(IpAddr::from(ip).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(ClientIdentity {
+        fingerprint: hex::encode(Sha256::digest(certificate)),
+        subject: parsed.subject().to_string(),
+        sans,
+    })
+}
+
+fn read_certificates(path: &PathBuf) -> Result<Vec<Certificate>, MarsError> {
+    let file = File::open(path).map_err(|err| {
+        MarsError::ServiceConfigError(format!("unable to open {}: {err}", path.display()))
+    })?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|err| {
+        MarsError::ServiceConfigError(format!("unable to read {}: {err}", path.display()))
+    })?;
+    if certs.is_empty() {
+        return Err(MarsError::ServiceConfigError(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_private_key(path: &PathBuf) -> Result<PrivateKey, MarsError> {
+    let file = File::open(path).map_err(|err| {
+        MarsError::ServiceConfigError(format!("unable to open {}: {err}", path.display()))
+    })?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|err| {
+        MarsError::ServiceConfigError(format!("unable to read {}: {err}", path.display()))
+    })?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            MarsError::ServiceConfigError(format!("no private key found in {}", path.display()))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, IsCa, SanType};
+
+    use super::{client_identity, TlsConfig};
+
+    fn certificate(common_name: &str, sans: Vec<SanType>, is_ca: bool) -> Certificate {
+        let mut params = CertificateParams::default();
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, common_name);
+        params.distinguished_name = name;
+        params.subject_alt_names = sans;
+        if is_ca {
+            params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        }
+        Certificate::from_params(params).unwrap()
+    }
+
+    #[test]
+    fn test_client_identity() {
+        let client = certificate(
+            "billing",
+            vec![
+                SanType::DnsName("billing.internal".to_string()),
+                SanType::IpAddress("10.0.0.7".parse().unwrap()),
+            ],
+            false,
+        );
+        let der = client.serialize_der().unwrap();
+        let identity = client_identity(&der).unwrap();
+        assert_eq!(identity.subject, "CN=billing");
+        assert_eq!(identity.sans, vec!["billing.internal", "10.0.0.7"]);
+        assert_eq!(identity.fingerprint.len(), 64);
+        assert_eq!(
+            identity.candidates()[1..],
+            [
+                "subject:CN=billing".to_string(),
+                "san:billing.internal".to_string(),
+                "san:10.0.0.7".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_config_with_client_ca() {
+        let dir = std::env::temp_dir().join(format!("mars-rover-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca = certificate("avalanche ca", vec![], true);
+        let server = certificate("localhost", vec![SanType::DnsName("localhost".into())], false);
+        std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        std::fs::write(
+            dir.join("server.pem"),
+            server.serialize_pem_with_signer(&ca).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("server.key"), server.serialize_private_key_pem()).unwrap();
+        let tls_config = TlsConfig {
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            client_ca: Some(dir.join("ca.pem")),
+        };
+        assert!(tls_config.server_config().is_ok());
+        let missing_key = TlsConfig {
+            key: dir.join("missing.key"),
+            ..tls_config
+        };
+        assert!(missing_key.server_config().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,591 @@
+//! Secrets read from HashiCorp Vault, referred to as `${vault:<path>#<key>}`.
+//!
+//! Any path can be read: KV v2 (`kv/data/app#password`) as well as dynamic secrets engines, such
+//! as database (`database/creds/readonly#password`) or aws (`aws/creds/deploy#access_key`). Every
+//! key of a path comes from a single read, so username and password of dynamic credentials match.
+//!
+//! Reads are cached. Only the first reference to a path waits for vault, later ones are served
+//! from the cache. Leases of dynamic secrets are renewed in background and read again once vault
+//! won't renew them further, KV secrets are read again every `kv_refresh`. A value that changed
+//! is reported through [`secrets_rotated`], so services are built again with it.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::{Method, Request};
+use hyper::{Body, Client};
+use hyper_tls::HttpsConnector;
+use mars_config::{secrets_rotated, MarsError, SecretProvider};
+use serde_json::{json, Value};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// how often tokens and leases are checked for renewal
+const REFRESH_TICK: Duration = Duration::from_secs(10);
+
+/// how long vault may take to respond
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `VaultAuth` is how the provider logs in to vault.
+#[derive(Clone)]
+pub enum VaultAuth {
+    Token(String),
+    AppRole {
+        /// mount path of approle auth method, `approle` by default
+        mount: String,
+        role_id: String,
+        secret_id: String,
+    },
+}
+
+#[derive(Clone)]
+pub struct VaultConfig {
+    /// `https://vault:8200`
+    pub addr: String,
+    pub auth: VaultAuth,
+    pub namespace: Option<String>,
+    /// how often secrets without a lease, like KV, are read again
+    pub kv_refresh: Duration,
+}
+
+impl VaultConfig {
+    /// config from `VAULT_ADDR`, along with either `VAULT_TOKEN`, or `VAULT_ROLE_ID` and
+    /// `VAULT_SECRET_ID` (mounted at `VAULT_APPROLE_MOUNT`). `VAULT_NAMESPACE` is optional.
+    /// `None` when `VAULT_ADDR` isn't set
+    pub fn from_env() -> Option<Result<Self, MarsError>> {
+        let addr = std::env::var("VAULT_ADDR").ok()?;
+        let var = |name| std::env::var(name).ok();
+        let auth = match (var("VAULT_TOKEN"), var("VAULT_ROLE_ID"), var("VAULT_SECRET_ID")) {
+            (_, Some(role_id), Some(secret_id)) => VaultAuth::AppRole {
+                mount: var("VAULT_APPROLE_MOUNT").unwrap_or_else(|| "approle".to_string()),
+                role_id,
+                secret_id,
+            },
+            (Some(token), _, _) => VaultAuth::Token(token),
+            _ => {
+                return Some(Err(MarsError::ServiceConfigError(
+                    "VAULT_ADDR is set, but neither VAULT_TOKEN nor VAULT_ROLE_ID and VAULT_SECRET_ID are"
+                        .to_string(),
+                )))
+            }
+        };
+        Some(Ok(VaultConfig {
+            addr,
+            auth,
+            namespace: var("VAULT_NAMESPACE"),
+            kv_refresh: Duration::from_secs(300),
+        }))
+    }
+}
+
+struct Token {
+    value: String,
+    renewable: bool,
+    /// `None` for tokens that don't expire
+    renew_at: Option<Instant>,
+}
+
+struct Lease {
+    id: String,
+    renewable: bool,
+}
+
+struct Secret {
+    data: Value,
+    /// `None` for secrets without a lease, like KV
+    lease: Option<Lease>,
+    /// when lease is renewed or secret is read again
+    refresh_at: Instant,
+}
+
+/// renewal is due after two thirds of a lease
+fn renew_at(duration: Duration) -> Instant {
+    Instant::now() + duration * 2 / 3
+}
+
+struct Vault {
+    config: VaultConfig,
+    token: Mutex<Option<Token>>,
+    secrets: Mutex<HashMap<String, Secret>>,
+}
+
+impl Vault {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<Value, MarsError> {
+        let error =
+            |error: String| MarsError::ServiceConfigError(format!("vault `{path}`: {error}"));
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!(
+                "{}/v1/{path}",
+                self.config.addr.trim_end_matches('/')
+            ))
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("x-vault-token", token);
+        }
+        if let Some(namespace) = &self.config.namespace {
+            request = request.header("x-vault-namespace", namespace);
+        }
+        let request = request
+            .body(body.map(|x| Body::from(x.to_string())).unwrap_or_default())
+            .map_err(|err| error(err.to_string()))?;
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, client.request(request))
+            .await
+            .map_err(|_| error("timed out".to_string()))?
+            .map_err(|err| error(err.to_string()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| error(err.to_string()))?;
+        let body: Value = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&body).map_err(|err| error(err.to_string()))?
+        };
+        if !status.is_success() {
+            // vault lists errors without secrets in them
+            return Err(error(format!("status {status}, {}", body["errors"])));
+        }
+        Ok(body)
+    }
+
+    /// token of current login, logging in when there is none
+    async fn token(&self) -> Result<String, MarsError> {
+        if let Some(token) = self.token.lock().unwrap().as_ref() {
+            return Ok(token.value.clone());
+        }
+        let token = self.login().await?;
+        let value = token.value.clone();
+        *self.token.lock().unwrap() = Some(token);
+        Ok(value)
+    }
+
+    async fn login(&self) -> Result<Token, MarsError> {
+        let (value, response) = match &self.config.auth {
+            VaultAuth::Token(token) => {
+                let response = self
+                    .request(Method::GET, "auth/token/lookup-self", Some(token), None)
+                    .await?;
+                (token.clone(), response["data"].clone())
+            }
+            VaultAuth::AppRole {
+                mount,
+                role_id,
+                secret_id,
+            } => {
+                let response = self
+                    .request(
+                        Method::POST,
+                        &format!("auth/{mount}/login"),
+                        None,
+                        Some(json!({"role_id": role_id, "secret_id": secret_id})),
+                    )
+                    .await?;
+                let auth = &response["auth"];
+                let value = auth["client_token"]
+                    .as_str()
+                    .ok_or_else(|| {
+                        MarsError::ServiceConfigError("vault login returned no token".to_string())
+                    })?
+                    .to_string();
+                (
+                    value,
+                    json!({"ttl": auth["lease_duration"], "renewable": auth["renewable"]}),
+                )
+            }
+        };
+        let ttl = response["ttl"].as_u64().unwrap_or_default();
+        Ok(Token {
+            value,
+            renewable: response["renewable"].as_bool().unwrap_or_default(),
+            renew_at: (ttl > 0).then(|| renew_at(Duration::from_secs(ttl))),
+        })
+    }
+
+    async fn read(&self, path: &str) -> Result<Secret, MarsError> {
+        let token = self.token().await?;
+        let response = match self.request(Method::GET, path, Some(&token), None).await {
+            Err(_) if matches!(self.config.auth, VaultAuth::AppRole { .. }) => {
+                // token may have been revoked or outlived its max ttl, log in again once
+                self.token.lock().unwrap().take();
+                let token = self.token().await?;
+                self.request(Method::GET, path, Some(&token), None).await?
+            }
+            response => response?,
+        };
+        let data = &response["data"];
+        // kv v2 nests secret within `data.data`, next to its `metadata`
+        let data = if data["metadata"].is_object() && data["data"].is_object() {
+            data["data"].clone()
+        } else {
+            data.clone()
+        };
+        let lease_id = response["lease_id"].as_str().unwrap_or_default();
+        let duration = response["lease_duration"].as_u64().unwrap_or_default();
+        Ok(if lease_id.is_empty() || duration == 0 {
+            Secret {
+                data,
+                lease: None,
+                refresh_at: Instant::now() + self.config.kv_refresh,
+            }
+        } else {
+            Secret {
+                data,
+                lease: Some(Lease {
+                    id: lease_id.to_string(),
+                    renewable: response["renewable"].as_bool().unwrap_or_default(),
+                }),
+                refresh_at: renew_at(Duration::from_secs(duration)),
+            }
+        })
+    }
+
+    /// reads `path` again, reporting rotation when its value changed
+    async fn reread(&self, path: &str) -> Result<(), MarsError> {
+        let secret = self.read(path).await?;
+        let changed = {
+            let mut secrets = self.secrets.lock().unwrap();
+            let changed =
+                matches!(secrets.get(path), Some(previous) if previous.data != secret.data);
+            secrets.insert(path.to_string(), secret);
+            changed
+        };
+        if changed {
+            log::info!("secret `{path}` of vault changed, services using it are built again");
+            secrets_rotated();
+        }
+        Ok(())
+    }
+
+    /// lease duration given by vault, zero once lease reached its max ttl
+    async fn renew_lease(&self, lease_id: &str) -> Result<u64, MarsError> {
+        let token = self.token().await?;
+        let response = self
+            .request(
+                Method::PUT,
+                "sys/leases/renew",
+                Some(&token),
+                Some(json!({ "lease_id": lease_id })),
+            )
+            .await?;
+        Ok(response["lease_duration"].as_u64().unwrap_or_default())
+    }
+
+    /// renews token and leases that are due, and reads again secrets that can't be renewed
+    async fn refresh(&self) {
+        let now = Instant::now();
+        let token_due = match self.token.lock().unwrap().as_ref() {
+            Some(token) => token
+                .renew_at
+                .map(|at| at <= now)
+                .map(|due| (due, token.renewable)),
+            None => None,
+        };
+        if let Some((true, renewable)) = token_due {
+            if let Err(err) = self.renew_token(renewable).await {
+                log::error!("unable to renew vault token, {}", err);
+            }
+        }
+
+        let due: Vec<(String, Option<String>)> = self
+            .secrets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, secret)| secret.refresh_at <= now)
+            .map(|(path, secret)| {
+                let lease = secret
+                    .lease
+                    .as_ref()
+                    .filter(|lease| lease.renewable)
+                    .map(|lease| lease.id.clone());
+                (path.clone(), lease)
+            })
+            .collect();
+        for (path, lease_id) in due {
+            if let Some(lease_id) = lease_id {
+                match self.renew_lease(&lease_id).await {
+                    Ok(duration) if duration > REFRESH_TICK.as_secs() => {
+                        if let Some(secret) = self.secrets.lock().unwrap().get_mut(&path) {
+                            secret.refresh_at = renew_at(Duration::from_secs(duration));
+                        }
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(err) => log::error!("unable to renew lease of `{}`, {}", path, err),
+                }
+            }
+            if let Err(err) = self.reread(&path).await {
+                log::error!("unable to read `{}` from vault, {}", path, err);
+            }
+        }
+    }
+
+    async fn renew_token(&self, renewable: bool) -> Result<(), MarsError> {
+        let token = if renewable {
+            let value = self.token().await?;
+            let response = self
+                .request(Method::POST, "auth/token/renew-self", Some(&value), None)
+                .await;
+            match response {
+                Ok(response) => {
+                    let ttl = response["auth"]["lease_duration"]
+                        .as_u64()
+                        .unwrap_or_default();
+                    Token {
+                        value,
+                        renewable: response["auth"]["renewable"].as_bool().unwrap_or_default(),
+                        renew_at: (ttl > 0).then(|| renew_at(Duration::from_secs(ttl))),
+                    }
+                }
+                Err(_) if matches!(self.config.auth, VaultAuth::AppRole { .. }) => {
+                    self.login().await?
+                }
+                Err(err) => return Err(err),
+            }
+        } else {
+            self.login().await?
+        };
+        *self.token.lock().unwrap() = Some(token);
+        Ok(())
+    }
+}
+
+/// `VaultProvider` resolves `${vault:<path>#<key>}` references.
+#[derive(Clone)]
+pub struct VaultProvider(Arc<Vault>);
+
+impl VaultProvider {
+    pub fn new(config: VaultConfig) -> Self {
+        VaultProvider(Arc::new(Vault {
+            config,
+            token: Mutex::new(None),
+            secrets: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// keeps token and leases alive in background, for as long as process runs
+    pub fn spawn_refresh(&self) {
+        let vault = self.0.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(REFRESH_TICK);
+            loop {
+                ticks.tick().await;
+                vault.refresh().await;
+            }
+        });
+    }
+}
+
+impl SecretProvider for VaultProvider {
+    fn resolve(&self, reference: &str) -> Result<String, MarsError> {
+        let (path, key) = reference
+            .split_once('#')
+            .ok_or_else(|| MarsError::ServiceConfigError("expected `<path>#<key>`".to_string()))?;
+        // secrets due for refresh are still served, they are renewed or read again in background
+        let cached = |secrets: &HashMap<String, Secret>| {
+            secrets.get(path).map(|secret| secret.data[key].clone())
+        };
+        let value = cached(&self.0.secrets.lock().unwrap());
+        let value = match value {
+            Some(value) => value,
+            None => {
+                // services are built synchronously, path is read once while they wait
+                let vault = self.0.clone();
+                block_on(async move { vault.reread(path).await })??;
+                cached(&self.0.secrets.lock().unwrap()).unwrap_or_default()
+            }
+        };
+        match value {
+            Value::String(value) => Ok(value),
+            Value::Null => Err(MarsError::ServiceConfigError(format!(
+                "key `{key}` is not in `{path}`"
+            ))),
+            value => Ok(value.to_string()),
+        }
+    }
+}
+
+/// runs `future` to completion from synchronous code. on a multi thread runtime, worker of
+/// caller is handed over to other tasks meanwhile, elsewhere future runs on a thread and runtime
+/// of its own
+fn block_on<F>(future: F) -> Result<F::Output, MarsError>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    if let Ok(handle) = Handle::try_current() {
+        if handle.runtime_flavor() == RuntimeFlavor::MultiThread {
+            return Ok(tokio::task::block_in_place(|| handle.block_on(future)));
+        }
+    }
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map(|runtime| runtime.block_on(future))
+            })
+            .join()
+            .expect("vault reader panicked")
+    })
+    .map_err(|err| MarsError::Error(Box::new(err)))
+}
+
+/// registers vault as provider of `${vault:..}` references and keeps its leases alive
+pub fn register(config: VaultConfig) {
+    let provider = VaultProvider::new(config);
+    provider.spawn_refresh();
+    mars_config::register_secret_provider("vault", Arc::new(provider));
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use http::{Request, Response};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Server};
+    use mars_config::{secrets_generation, SecretProvider};
+    use serde_json::{json, Value};
+
+    use super::{VaultAuth, VaultConfig, VaultProvider};
+
+    /// stub of vault with approle login, a kv v2 secret and aws credentials that change on every
+    /// read. leases are renewed once
+    fn serve(reads: Arc<AtomicUsize>) -> SocketAddr {
+        let renewals = Arc::new(AtomicUsize::new(0));
+        let make_service = make_service_fn(move |_| {
+            let (reads, renewals) = (reads.clone(), renewals.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (reads, renewals) = (reads.clone(), renewals.clone());
+                    async move {
+                        let token = request
+                            .headers()
+                            .get("x-vault-token")
+                            .map(|x| x.to_str().unwrap().to_string());
+                        let path = request.uri().path().to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+                        let response = match (path.as_str(), token.as_deref()) {
+                            ("/v1/auth/approle/login", None)
+                                if body == json!({"role_id": "role", "secret_id": "secret"}) =>
+                            {
+                                json!({"auth": {"client_token": "s.token", "lease_duration": 3600, "renewable": true}})
+                            }
+                            ("/v1/kv/data/app", Some("s.token")) => json!({
+                                "lease_id": "", "lease_duration": 0,
+                                "data": {"data": {"password": "hunter2", "port": 5432}, "metadata": {"version": 1}},
+                            }),
+                            ("/v1/aws/creds/deploy", Some("s.token")) => {
+                                let read = reads.fetch_add(1, Ordering::SeqCst) + 1;
+                                json!({
+                                    "lease_id": format!("aws/creds/deploy/{read}"), "lease_duration": 60, "renewable": true,
+                                    "data": {"access_key": format!("AK{read}"), "secret_key": format!("SK{read}")},
+                                })
+                            }
+                            ("/v1/sys/leases/renew", Some("s.token")) => {
+                                // lease is renewed once, then it has reached its max ttl
+                                let renewal = renewals.fetch_add(1, Ordering::SeqCst);
+                                let duration = if renewal == 0 { 60 } else { 0 };
+                                json!({"lease_id": body["lease_id"], "lease_duration": duration})
+                            }
+                            _ => {
+                                return Ok::<_, Infallible>(
+                                    Response::builder()
+                                        .status(403)
+                                        .body(Body::from(r#"{"errors": ["permission denied"]}"#))
+                                        .unwrap(),
+                                )
+                            }
+                        };
+                        Ok(Response::new(Body::from(response.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_vault() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let addr = serve(reads.clone());
+        let provider = VaultProvider::new(VaultConfig {
+            addr: format!("http://{addr}"),
+            auth: VaultAuth::AppRole {
+                mount: "approle".to_string(),
+                role_id: "role".to_string(),
+                secret_id: "secret".to_string(),
+            },
+            namespace: None,
+            kv_refresh: Duration::from_secs(300),
+        });
+        assert_eq!(provider.resolve("kv/data/app#password").unwrap(), "hunter2");
+        assert_eq!(provider.resolve("kv/data/app#port").unwrap(), "5432");
+        assert!(provider.resolve("kv/data/app#missing").is_err());
+        assert!(provider.resolve("kv/data/other#password").is_err());
+
+        // both keys come from one read
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#access_key").unwrap(),
+            "AK1"
+        );
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#secret_key").unwrap(),
+            "SK1"
+        );
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        let due = |provider: &VaultProvider| {
+            for secret in provider.0.secrets.lock().unwrap().values_mut() {
+                secret.refresh_at = Instant::now();
+            }
+        };
+        // secrets due are served from cache, only refresh in background reads vault
+        let generation = secrets_generation();
+        due(&provider);
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#access_key").unwrap(),
+            "AK1"
+        );
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        // lease is renewed, credentials stay
+        provider.0.refresh().await;
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#access_key").unwrap(),
+            "AK1"
+        );
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        // lease can't be renewed anymore, credentials are read again and services rebuilt
+        due(&provider);
+        provider.0.refresh().await;
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#access_key").unwrap(),
+            "AK2"
+        );
+        assert_eq!(
+            provider.resolve("aws/creds/deploy#secret_key").unwrap(),
+            "SK2"
+        );
+        assert!(secrets_generation() > generation);
+    }
+}